@@ -0,0 +1,669 @@
+// Native tool for poking at WoW assets in a sheepfile dump without going
+// through the browser. Usage:
+//
+//   wow_inspect <sheepfile dir> <command> [--out <dir>] [--mip <level>] [--doodad-set <index>] [--tile <x>,<y>] <file id or name>...
+//
+// Commands:
+//   chunks   list each chunk's magic, offset and size
+//   info     print the parsed header fields
+//   refs     print the file IDs the asset references (textures, models, skins, etc)
+//   png      decode a BLP mip level to <out>/<file id>.png
+//   gltf     export an M2 (with its first skin, textures and stand animation) or a WMO
+//            (with its groups and doodad set) to <out>/<file id>.glb, or a WDT's tile
+//            (with its baked terrain, doodads and WMOs) to <out>/<file id>_<x>_<y>.glb
+//
+// Every file is handled independently, so one broken asset (including ones
+// that make the parsers panic) doesn't stop the rest of a batch.
+
+use noclip_support::wow::{
+    adt::{Adt, Doodad, WmoDefinition},
+    blp::Blp,
+    common::parse_array,
+    db::Wdc4Db2File,
+    gltf,
+    m2::M2,
+    sheep::SheepfileDirectory,
+    skel::Skeleton,
+    skin::Skin,
+    wdt::Wdt,
+    wmo::{Wmo, WmoGroup},
+};
+use deku::DekuContainerRead;
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+};
+
+const USAGE: &str = "usage: wow_inspect <sheepfile dir> <chunks|info|refs|png|gltf> [--out <dir>] [--mip <level>] [--doodad-set <index>] [--tile <x>,<y>] <file id or name>...";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Chunks,
+    Info,
+    Refs,
+    Png,
+    Gltf,
+}
+
+struct Options {
+    sheepfile_path: PathBuf,
+    command: Command,
+    out_dir: PathBuf,
+    mip_level: usize,
+    doodad_set_id: usize,
+    tile: Option<(usize, usize)>,
+    files: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let sheepfile_path = PathBuf::from(args.next().ok_or("missing sheepfile directory")?);
+    let command = match args.next().ok_or("missing command")?.as_str() {
+        "chunks" => Command::Chunks,
+        "info" => Command::Info,
+        "refs" => Command::Refs,
+        "png" => Command::Png,
+        "gltf" => Command::Gltf,
+        other => return Err(format!("unknown command {}", other)),
+    };
+    let mut out_dir = PathBuf::from(".");
+    let mut mip_level = 0;
+    let mut doodad_set_id = 0;
+    let mut tile = None;
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_dir = PathBuf::from(args.next().ok_or("--out needs a directory")?),
+            "--mip" => {
+                let level = args.next().ok_or("--mip needs a level")?;
+                mip_level = level.parse().map_err(|_| format!("invalid mip level {}", level))?;
+            },
+            "--doodad-set" => {
+                let index = args.next().ok_or("--doodad-set needs an index")?;
+                doodad_set_id = index.parse().map_err(|_| format!("invalid doodad set {}", index))?;
+            },
+            "--tile" => {
+                let coords = args.next().ok_or("--tile needs coordinates")?;
+                tile = Some(parse_tile(&coords).ok_or(format!("invalid tile {}, expected <x>,<y>", coords))?);
+            },
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        return Err("no file IDs or names given".to_string());
+    }
+    Ok(Options { sheepfile_path, command, out_dir, mip_level, doodad_set_id, tile, files })
+}
+
+fn parse_tile(coords: &str) -> Option<(usize, usize)> {
+    let (x, y) = coords.split_once(',')?;
+    let (x, y) = (x.trim().parse().ok()?, y.trim().parse().ok()?);
+    if x < 64 && y < 64 {
+        Some((x, y))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AssetKind {
+    M2,
+    Skin,
+    Blp,
+    Db2,
+    Wmo,
+    WmoGroup,
+    Wdt,
+    Adt,
+    AdtTex,
+    AdtObj,
+    AdtLod,
+    Unknown,
+}
+
+impl AssetKind {
+    // WMOs, ADTs and WDTs store their chunk magics byte-swapped
+    fn has_reversed_magics(&self) -> bool {
+        matches!(self, AssetKind::Wmo | AssetKind::WmoGroup | AssetKind::Wdt |
+            AssetKind::Adt | AssetKind::AdtTex | AssetKind::AdtObj | AssetKind::AdtLod)
+    }
+
+    fn is_chunked(&self) -> bool {
+        *self == AssetKind::M2 || self.has_reversed_magics()
+    }
+}
+
+struct RawChunk<'a> {
+    magic: [u8; 4],
+    offset: usize,
+    data: &'a [u8],
+}
+
+// unlike ChunkedData, this stops at the first truncated chunk rather than
+// panicking, and reports where things went wrong
+fn read_chunks(data: &[u8]) -> (Vec<RawChunk<'_>>, Option<String>) {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        if offset + 8 > data.len() {
+            return (chunks, Some(format!("truncated chunk header at 0x{:x}", offset)));
+        }
+        let mut magic = [0; 4];
+        magic.copy_from_slice(&data[offset..offset + 4]);
+        let size = u32::from_le_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]) as usize;
+        let start = offset + 8;
+        if start + size > data.len() {
+            let error = format!("chunk {} at 0x{:x} claims 0x{:x} bytes, but only 0x{:x} remain",
+                magic_name(&magic, true), offset, size, data.len() - start);
+            chunks.push(RawChunk { magic, offset, data: &data[start..] });
+            return (chunks, Some(error));
+        }
+        chunks.push(RawChunk { magic, offset, data: &data[start..start + size] });
+        offset = start + size;
+    }
+    (chunks, None)
+}
+
+fn magic_name(magic: &[u8; 4], reversed: bool) -> String {
+    let mut magic = *magic;
+    if reversed {
+        magic.reverse();
+    }
+    String::from_utf8_lossy(&magic).into_owned()
+}
+
+fn detect_kind(data: &[u8]) -> AssetKind {
+    match data.get(0..4) {
+        Some(b"MD21") => return AssetKind::M2,
+        Some(b"SKIN") => return AssetKind::Skin,
+        Some(b"BLP1") | Some(b"BLP2") => return AssetKind::Blp,
+        Some(b"WDC3") | Some(b"WDC4") | Some(b"WDC5") => return AssetKind::Db2,
+        Some(b"REVM") => {},
+        _ => return AssetKind::Unknown,
+    }
+    let (chunks, _) = read_chunks(data);
+    match chunks.get(1).map(|chunk| &chunk.magic) {
+        Some(b"DHOM") => return AssetKind::Wmo,
+        Some(b"PGOM") => return AssetKind::WmoGroup,
+        Some(b"DHPM") => return AssetKind::Wdt,
+        Some(b"RDHM") => return AssetKind::Adt,
+        _ => {},
+    }
+    let has_chunk = |magics: &[&[u8; 4]]| chunks.iter().any(|chunk| magics.contains(&&chunk.magic));
+    if has_chunk(&[b"DFLM"]) {
+        AssetKind::AdtLod
+    } else if has_chunk(&[b"DIDM", b"DIHM", b"XETM", b"PXTM"]) {
+        AssetKind::AdtTex
+    } else if has_chunk(&[b"FDDM", b"FDOM", b"XDMM", b"OMWM"]) {
+        AssetKind::AdtObj
+    } else {
+        AssetKind::Unknown
+    }
+}
+
+fn print_chunks(data: &[u8], kind: AssetKind) -> Result<(), String> {
+    if !kind.is_chunked() {
+        println!("{:?} files aren't chunked ({} bytes)", kind, data.len());
+        return Ok(());
+    }
+    let (chunks, error) = read_chunks(data);
+    for chunk in &chunks {
+        println!("{} offset=0x{:x} size=0x{:x}", magic_name(&chunk.magic, kind.has_reversed_magics()), chunk.offset, chunk.data.len());
+    }
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+fn print_info(data: &[u8], kind: AssetKind) -> Result<(), String> {
+    match kind {
+        AssetKind::M2 => {
+            let m2 = M2::new(data)?;
+            println!("name: {}", m2.name);
+            println!("flags: 0x{:x}", m2.flags);
+            println!("textures: {}", m2.texture_ids.len());
+            println!("skins: {}", m2.skin_ids.len());
+            println!("materials: {}", m2.materials.len());
+            println!("anim files: {}", m2.anim_file_ids.len());
+            println!("skeleton: {:?}", m2.skeleton_file_id);
+            println!("attachments: {}", m2.attachments.len());
+            println!("bounding box: {:?}", m2.get_bounding_box());
+            println!("bounding radius: {}", m2.get_bounding_radius());
+        },
+        AssetKind::Skin => {
+            let mut skin = Skin::new(data)?;
+            println!("submeshes: {}", skin.submeshes.len());
+            println!("batches: {}", skin.batches.len());
+            println!("indices: {}", skin.take_indices().len());
+        },
+        AssetKind::Blp => {
+            let blp = Blp::new(data)?;
+            println!("{:#?}", blp.header);
+            for level in 0..blp.get_num_mips() {
+                println!("mip {}: {}x{}", level, blp.get_mip_width(level), blp.get_mip_height(level));
+            }
+        },
+        AssetKind::Db2 => {
+            let (_, db2) = Wdc4Db2File::from_bytes((data, 0))
+                .map_err(|e| format!("{:?}", e))?;
+            println!("{:#?}", db2.header);
+            for (i, section) in db2.section_headers.iter().enumerate() {
+                println!("section {}: {:?}", i, section);
+            }
+            db2.print_table_debug_info();
+        },
+        AssetKind::Wmo => {
+            let wmo = Wmo::new(data)?;
+            println!("{:#?}", wmo.header);
+            println!("groups: {}", wmo.group_file_ids.len());
+            println!("doodad defs: {}", wmo.doodad_defs.len());
+            println!("fogs: {}", wmo.fogs.len());
+            println!("skybox: {:?} {:?}", wmo.skybox_file_id, wmo.skybox_name);
+        },
+        AssetKind::WmoGroup => {
+            let group = WmoGroup::new(data)?;
+            println!("{:#?}", group.header);
+            println!("vertices: {}", group.num_vertices);
+            println!("uv buffers: {}", group.num_uv_bufs);
+            println!("color buffers: {}", group.num_color_bufs);
+            println!("batches: {}", group.batches.len());
+        },
+        AssetKind::Wdt => {
+            let wdt = Wdt::new(data)?;
+            println!("{:#?}", wdt.header);
+            println!("global map obj: {:?}", wdt.global_wmo);
+            println!("loaded tiles: {}", wdt.get_loaded_map_data().len());
+        },
+        AssetKind::Adt => {
+            let adt = Adt::new(data)?;
+            let chunks = adt.get_map_chunks();
+            println!("map chunks: {}", chunks.len());
+            if let Some(chunk) = chunks.first() {
+                println!("first chunk: {:#?}", chunk.header);
+            }
+        },
+        AssetKind::AdtTex | AssetKind::AdtObj | AssetKind::AdtLod => {
+            println!("{:?} files have no header, try chunks or refs", kind);
+        },
+        AssetKind::Unknown => return Err("unrecognized file format".to_string()),
+    }
+    Ok(())
+}
+
+fn print_refs(data: &[u8], kind: AssetKind) -> Result<(), String> {
+    let mut refs: Vec<(&str, u32)> = Vec::new();
+    match kind {
+        AssetKind::M2 => {
+            let m2 = M2::new(data)?;
+            refs.extend(m2.texture_ids.iter().map(|&id| ("texture", id)));
+            refs.extend(m2.skin_ids.iter().map(|&id| ("skin", id)));
+            refs.extend(m2.anim_file_ids.iter().map(|anim| ("anim", anim.file_id)));
+            refs.extend(m2.skeleton_file_id.map(|id| ("skeleton", id)));
+        },
+        AssetKind::Wmo => {
+            let wmo = Wmo::new(data)?;
+            refs.extend(wmo.group_file_ids.iter().map(|&id| ("group", id)));
+            for material in &wmo.textures {
+                refs.extend([material.texture_1, material.texture_2, material.texture_3].iter().map(|&id| ("texture", id)));
+            }
+            refs.extend(wmo.doodad_file_ids.iter().map(|&id| ("model", id)));
+            refs.extend(wmo.skybox_file_id.map(|id| ("skybox", id)));
+        },
+        AssetKind::Wdt => {
+            let wdt = Wdt::new(data)?;
+            let header = &wdt.header;
+            refs.push(("lgt", header.lgt_file_data_id));
+            refs.push(("occ", header.occ_file_data_id));
+            refs.push(("fogs", header.fogs_file_data_id));
+            refs.push(("mpv", header.mpv_file_data_id));
+            refs.push(("tex", header.tex_file_data_id));
+            refs.push(("wdl", header.wdl_file_data_id));
+            refs.extend(wdt.global_wmo.as_ref().map(|wmo| ("wmo", wmo.name_id)));
+            // tiles are stored row by row, so print them with their coordinates
+            for (i, tile) in wdt.get_all_map_data().iter().enumerate() {
+                if tile.root_adt == 0 {
+                    continue;
+                }
+                println!("tile {},{}: root_adt={} obj0_adt={} obj1_adt={} tex0_adt={} lod_adt={} map_texture={} map_texture_n={} minimap_texture={}",
+                    i % 64, i / 64, tile.root_adt, tile.obj0_adt, tile.obj1_adt, tile.tex0_adt, tile.lod_adt,
+                    tile.map_texture, tile.map_texture_n, tile.minimap_texture);
+            }
+        },
+        AssetKind::AdtTex | AssetKind::AdtObj | AssetKind::AdtLod => {
+            // the Adt parser needs the root file before it'll take any of the
+            // split files, so pull the references straight out of the chunks
+            let (chunks, error) = read_chunks(data);
+            for chunk in &chunks {
+                match &chunk.magic {
+                    b"DIDM" => refs.extend(parse_array::<u32>(chunk.data, 4)?.into_iter().map(|id| ("texture", id))),
+                    b"DIHM" => refs.extend(parse_array::<u32>(chunk.data, 4)?.into_iter().map(|id| ("height_texture", id))),
+                    b"FDDM" | b"DDLM" => {
+                        let doodads: Vec<Doodad> = parse_array(chunk.data, 0x24)?;
+                        refs.extend(doodads.iter().map(|doodad| ("model", doodad.name_id)));
+                    },
+                    b"FDOM" | b"DMLM" => {
+                        let wmos: Vec<WmoDefinition> = parse_array(chunk.data, 0x40)?;
+                        refs.extend(wmos.iter().map(|wmo| ("wmo", wmo.name_id)));
+                    },
+                    _ => {},
+                }
+            }
+            if let Some(error) = error {
+                return Err(error);
+            }
+        },
+        AssetKind::Skin | AssetKind::Blp | AssetKind::Db2 | AssetKind::WmoGroup | AssetKind::Adt => {},
+        AssetKind::Unknown => return Err("unrecognized file format".to_string()),
+    }
+
+    // 0 means "no file" everywhere, and most assets reference the same
+    // textures/models over and over
+    let mut seen = std::collections::HashSet::new();
+    for (label, id) in refs {
+        if id != 0 && seen.insert((label, id)) {
+            println!("{} {}", label, id);
+        }
+    }
+    Ok(())
+}
+
+fn write_png(data: &[u8], kind: AssetKind, file_id: u32, mip_level: usize, out_dir: &Path) -> Result<(), String> {
+    if kind != AssetKind::Blp {
+        return Err(format!("can't convert {:?} files to PNG", kind));
+    }
+    let blp = Blp::new(data)?;
+    if mip_level >= blp.get_num_mips() {
+        return Err(format!("mip {} requested, but the BLP only has {}", mip_level, blp.get_num_mips()));
+    }
+    let png = blp.get_mip_png(mip_level)?;
+    let file_name = if mip_level == 0 {
+        format!("{}.png", file_id)
+    } else {
+        format!("{}_mip{}.png", file_id, mip_level)
+    };
+    let path = out_dir.join(file_name);
+    std::fs::write(&path, png)
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    println!("wrote {} ({}x{})", path.display(), blp.get_mip_width(mip_level), blp.get_mip_height(mip_level));
+    Ok(())
+}
+
+// reads the textures an M2 references, leaving out replaceable textures
+// (which have no file ID) and any that fail to load
+fn read_m2_textures(sheepfile: &mut SheepfileDirectory, m2: &M2) -> Vec<Option<Blp>> {
+    m2.texture_ids.iter().map(|&file_id| {
+        if file_id == 0 {
+            return None;
+        }
+        match sheepfile.read_file_id(file_id).and_then(|data| Blp::new(&data)) {
+            Ok(blp) => Some(blp),
+            Err(e) => {
+                eprintln!("skipping texture {}: {}", file_id, e);
+                None
+            },
+        }
+    }).collect()
+}
+
+// an M2 with its first (most detailed) skin and its textures
+fn read_m2_model(sheepfile: &mut SheepfileDirectory, data: &[u8]) -> Result<gltf::M2Model, String> {
+    let m2 = M2::new(data)?;
+    let skin_id = *m2.skin_ids.first().ok_or("M2 has no skins")?;
+    let skin = Skin::new(&sheepfile.read_file_id(skin_id)?)?;
+    let textures = read_m2_textures(sheepfile, &m2);
+    Ok(gltf::M2Model { m2, skin, textures })
+}
+
+fn export_m2(sheepfile: &mut SheepfileDirectory, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut model = read_m2_model(sheepfile, data)?;
+    let m2 = &mut model.m2;
+    let mut animation_manager = m2.take_animation_manager();
+    let mut anim_file_ids = m2.anim_file_ids.clone();
+    if let Some(skeleton_id) = m2.skeleton_file_id {
+        let skeleton = Skeleton::new(&sheepfile.read_file_id(skeleton_id)?)?;
+        animation_manager.set_skeleton(&skeleton);
+        anim_file_ids = skeleton.anim_file_ids.clone();
+    }
+    // only bother with the stand animation's .anim files, since character
+    // models can have hundreds of them
+    for anim in anim_file_ids.iter().filter(|anim| anim.anim_id == 0) {
+        let result = sheepfile.read_file_id(anim.file_id)
+            .and_then(|anim_data| animation_manager.add_anim_file(anim.anim_id, anim.sub_anim_id, &anim_data));
+        if let Err(e) = result {
+            eprintln!("skipping .anim file {}: {}", anim.file_id, e);
+        }
+    }
+
+    gltf::export_m2(model, &animation_manager)
+}
+
+// adds a texture to `textures` unless it's already there, skipping (and
+// reporting) any that fail to load
+fn read_texture(sheepfile: &mut SheepfileDirectory, file_id: u32, textures: &mut HashMap<u32, Blp>) {
+    if file_id == 0 || textures.contains_key(&file_id) {
+        return;
+    }
+    match sheepfile.read_file_id(file_id).and_then(|texture_data| Blp::new(&texture_data)) {
+        Ok(blp) => { textures.insert(file_id, blp); },
+        Err(e) => eprintln!("skipping texture {}: {}", file_id, e),
+    }
+}
+
+// like read_texture, for doodad models
+fn read_model(sheepfile: &mut SheepfileDirectory, file_id: u32, models: &mut HashMap<u32, gltf::M2Model>) {
+    if file_id == 0 || models.contains_key(&file_id) {
+        return;
+    }
+    match sheepfile.read_file_id(file_id).and_then(|model_data| read_m2_model(sheepfile, &model_data)) {
+        Ok(model) => { models.insert(file_id, model); },
+        Err(e) => eprintln!("skipping doodad model {}: {}", file_id, e),
+    }
+}
+
+// a WMO with its groups, plus the textures and doodad models it needs for
+// the given doodad set
+fn read_wmo(sheepfile: &mut SheepfileDirectory, data: &[u8], doodad_set_id: usize, textures: &mut HashMap<u32, Blp>, models: &mut HashMap<u32, gltf::M2Model>) -> Result<Wmo, String> {
+    let mut wmo = Wmo::new(data)?;
+    for file_id in wmo.group_file_ids.clone() {
+        let result = sheepfile.read_file_id(file_id)
+            .and_then(|group_data| wmo.append_group(file_id, &group_data));
+        if let Err(e) = result {
+            eprintln!("skipping group {}: {}", file_id, e);
+        }
+    }
+
+    for material in &wmo.textures {
+        read_texture(sheepfile, material.texture_1, textures);
+    }
+
+    if wmo.get_num_doodad_sets() > 0 {
+        for doodad_index in wmo.get_doodad_set_refs(doodad_set_id) {
+            let file_id = wmo.doodad_defs.get(doodad_index as usize)
+                .filter(|doodad| doodad.name_index >= 0)
+                .and_then(|doodad| wmo.doodad_file_ids.get(doodad.name_index as usize))
+                .copied();
+            if let Some(file_id) = file_id {
+                read_model(sheepfile, file_id, models);
+            }
+        }
+    }
+    Ok(wmo)
+}
+
+fn export_wmo(sheepfile: &mut SheepfileDirectory, data: &[u8], doodad_set_id: usize) -> Result<Vec<u8>, String> {
+    let mut textures = HashMap::new();
+    let mut models = HashMap::new();
+    let wmo = read_wmo(sheepfile, data, doodad_set_id, &mut textures, &mut models)?;
+    gltf::export_wmo(&wmo, doodad_set_id, &textures, models)
+}
+
+fn export_adt(sheepfile: &mut SheepfileDirectory, data: &[u8], (x, y): (usize, usize)) -> Result<Vec<u8>, String> {
+    let wdt = Wdt::new(data)?;
+    let tile = wdt.get_all_map_data().get(y * 64 + x).cloned()
+        .filter(|tile| tile.root_adt != 0)
+        .ok_or(format!("the WDT has no tile {},{}", x, y))?;
+    let mut adt = Adt::new(&sheepfile.read_file_id(tile.root_adt)?)?;
+    if tile.obj0_adt != 0 {
+        adt.append_obj_adt(&sheepfile.read_file_id(tile.obj0_adt)?)?;
+    }
+    if tile.tex0_adt != 0 {
+        adt.append_tex_adt(&sheepfile.read_file_id(tile.tex0_adt)?)?;
+    }
+
+    let mut textures = HashMap::new();
+    let layers: Vec<usize> = adt.get_map_chunks().iter()
+        .flat_map(|chunk| chunk.texture_layers.iter().map(|layer| layer.texture_index as usize))
+        .collect();
+    for texture_index in layers {
+        if let Some(file_id) = adt.get_diffuse_texture_id(texture_index) {
+            read_texture(sheepfile, file_id, &mut textures);
+        }
+        if let Some(file_id) = adt.get_height_texture_id(texture_index).filter(|_| wdt.adt_has_height_texturing()) {
+            read_texture(sheepfile, file_id, &mut textures);
+        }
+    }
+
+    let mut models = HashMap::new();
+    for doodad in adt.get_doodads(0) {
+        read_model(sheepfile, doodad.name_id, &mut models);
+    }
+
+    // a WMO placed more than once is only read once, so only the first
+    // placement's doodad set gets its models loaded
+    let mut wmos = HashMap::new();
+    for def in adt.get_wmo_defs(0) {
+        if wmos.contains_key(&def.name_id) {
+            continue;
+        }
+        let result = sheepfile.read_file_id(def.name_id)
+            .and_then(|wmo_data| read_wmo(sheepfile, &wmo_data, def.doodad_set as usize, &mut textures, &mut models));
+        match result {
+            Ok(wmo) => { wmos.insert(def.name_id, wmo); },
+            Err(e) => eprintln!("skipping WMO {}: {}", def.name_id, e),
+        }
+    }
+
+    gltf::export_adt(&adt, wdt.adt_has_big_alpha(), wdt.adt_has_height_texturing(), &textures, &wmos, models)
+}
+
+fn write_gltf(sheepfile: &mut SheepfileDirectory, options: &Options, data: &[u8], kind: AssetKind, file_id: u32) -> Result<(), String> {
+    let (glb, file_name) = match (kind, options.tile) {
+        (AssetKind::M2, _) => (export_m2(sheepfile, data)?, format!("{}.glb", file_id)),
+        (AssetKind::Wmo, _) => (export_wmo(sheepfile, data, options.doodad_set_id)?, format!("{}.glb", file_id)),
+        (AssetKind::Wdt, Some(tile)) => (export_adt(sheepfile, data, tile)?, format!("{}_{}_{}.glb", file_id, tile.0, tile.1)),
+        (AssetKind::Wdt, None) => return Err("exporting a WDT needs a --tile".to_string()),
+        _ => return Err(format!("can't export {:?} files to glTF", kind)),
+    };
+    let path = options.out_dir.join(file_name);
+    std::fs::write(&path, &glb)
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    println!("wrote {} ({} bytes)", path.display(), glb.len());
+    Ok(())
+}
+
+fn inspect(sheepfile: &mut SheepfileDirectory, options: &Options, name_or_id: &str) -> Result<(), String> {
+    let entry = match name_or_id.parse::<u32>() {
+        Ok(file_id) => sheepfile.get_entry_for_file_id(file_id),
+        Err(_) => sheepfile.get_entry_for_name(name_or_id),
+    }.ok_or(format!("{} isn't in the sheepfile", name_or_id))?.clone();
+    let data = sheepfile.read_entry(&entry)?;
+    let kind = detect_kind(&data);
+
+    // the parsers assume well-formed data and will happily panic on a
+    // broken asset, which is exactly what we're here to look at
+    let result = panic::catch_unwind(AssertUnwindSafe(|| match options.command {
+        Command::Chunks => print_chunks(&data, kind),
+        Command::Info => print_info(&data, kind),
+        Command::Refs => print_refs(&data, kind),
+        Command::Png => write_png(&data, kind, entry.file_id, options.mip_level, &options.out_dir),
+        Command::Gltf => write_gltf(sheepfile, options, &data, kind, entry.file_id),
+    }));
+    match result {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload.downcast_ref::<String>().cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(format!("parser panicked: {}", message))
+        },
+    }
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        },
+    };
+    let mut sheepfile = match SheepfileDirectory::open(&options.sheepfile_path) {
+        Ok(sheepfile) => sheepfile,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    };
+
+    let mut failures = 0;
+    for name_or_id in &options.files {
+        if options.files.len() > 1 {
+            println!("== {}", name_or_id);
+        }
+        if let Err(e) = inspect(&mut sheepfile, &options, name_or_id) {
+            eprintln!("{}: {}", name_or_id, e);
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(magic: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut result = magic.to_vec();
+        result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        result.extend_from_slice(data);
+        result
+    }
+
+    #[test]
+    fn test_detect_kind() {
+        let mut wdt = chunk(b"REVM", &18u32.to_le_bytes());
+        wdt.extend(chunk(b"DHPM", &[0; 32]));
+        assert_eq!(detect_kind(&wdt), AssetKind::Wdt);
+
+        let mut tex = chunk(b"REVM", &18u32.to_le_bytes());
+        tex.extend(chunk(b"PMAM", &[0; 4]));
+        tex.extend(chunk(b"DIDM", &[0; 8]));
+        assert_eq!(detect_kind(&tex), AssetKind::AdtTex);
+
+        assert_eq!(detect_kind(b"BLP2"), AssetKind::Blp);
+        assert_eq!(detect_kind(b"nope"), AssetKind::Unknown);
+    }
+
+    #[test]
+    fn test_parse_tile() {
+        assert_eq!(parse_tile("31,42"), Some((31, 42)));
+        assert_eq!(parse_tile("31, 42"), Some((31, 42)));
+        assert_eq!(parse_tile("64,0"), None);
+        assert_eq!(parse_tile("31"), None);
+    }
+
+    #[test]
+    fn test_truncated_chunks() {
+        let mut data = chunk(b"REVM", &18u32.to_le_bytes());
+        data.extend(chunk(b"DIDM", &[1, 0, 0, 0, 2, 0, 0, 0]));
+        data.truncate(data.len() - 2);
+        let (chunks, error) = read_chunks(&data);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].data.len(), 6);
+        assert!(error.unwrap().contains("MDID"));
+    }
+}
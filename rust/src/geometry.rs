@@ -233,6 +233,23 @@ impl ConvexHull {
             plane.transform(&inv_transpose_mat);
         }
     }
+
+    // conservative, like intersect_aabb: triangles lying outside the hull
+    // near one of its corners may still be reported as intersecting
+    pub fn intersect_triangle(&self, p0: &Vec3, p1: &Vec3, p2: &Vec3) -> IntersectionState {
+        let mut result = IntersectionState::Inside;
+        for plane in &self.planes {
+            let d0 = plane.distance(p0);
+            let d1 = plane.distance(p1);
+            let d2 = plane.distance(p2);
+            if d0 < 0.0 && d1 < 0.0 && d2 < 0.0 {
+                return IntersectionState::Outside;
+            } else if d0 < 0.0 || d1 < 0.0 || d2 < 0.0 {
+                result = IntersectionState::Intersection;
+            }
+        }
+        result
+    }
 }
 
 #[wasm_bindgen(js_class = "ConvexHull")]
@@ -293,6 +310,329 @@ impl ConvexHull {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    // Moller-Trumbore. returns t such that the hit is at origin + t * direction
+    pub fn intersect_triangle(&self, p0: &Vec3, p1: &Vec3, p2: &Vec3) -> Option<f32> {
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let h = self.direction.cross(&edge2);
+        let det = edge1.dot(&h);
+        if det.abs() < f32::EPSILON {
+            return None; // parallel to the triangle
+        }
+        let inv_det = 1.0 / det;
+        let s = self.origin - p0;
+        let u = inv_det * s.dot(&h);
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = inv_det * self.direction.dot(&q);
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = inv_det * edge2.dot(&q);
+        if t >= 0.0 {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    // slab test. returns the t at which the ray enters the box, or 0 if it
+    // starts inside of it
+    pub fn intersect_aabb(&self, aabb: &AABB) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+        for i in 0..3 {
+            if self.direction[i].abs() < f32::EPSILON {
+                if self.origin[i] < aabb.min[i] || self.origin[i] > aabb.max[i] {
+                    return None;
+                }
+            } else {
+                let inv_dir = 1.0 / self.direction[i];
+                let t0 = (aabb.min[i] - self.origin[i]) * inv_dir;
+                let t1 = (aabb.max[i] - self.origin[i]) * inv_dir;
+                t_min = t_min.max(t0.min(t1));
+                t_max = t_max.min(t0.max(t1));
+                if t_min > t_max {
+                    return None;
+                }
+            }
+        }
+        Some(t_min)
+    }
+}
+
+#[wasm_bindgen(js_name = "RayHit")]
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub t: f32,
+    pub triangle_index: usize,
+}
+
+// an indexed triangle soup, e.g. for collision
+#[wasm_bindgen(js_name = "TriangleMesh")]
+#[derive(Debug, Clone)]
+pub struct TriangleMesh {
+    pub(crate) vertices: Vec<Vec3>,
+    pub(crate) indices: Vec<u32>,
+    pub(crate) normals: Vec<Vec3>, // one per triangle
+    pub(crate) aabb: AABB,
+}
+
+impl TriangleMesh {
+    // if normals aren't provided (or don't match the triangle count), they're
+    // calculated from the triangles' winding
+    pub fn new(vertices: Vec<Vec3>, indices: Vec<u32>, normals: Option<Vec<Vec3>>) -> Self {
+        let mut aabb = AABB::default();
+        aabb.set_from_points(&vertices);
+        let mut mesh = TriangleMesh {
+            vertices,
+            indices,
+            normals: Vec::new(),
+            aabb,
+        };
+        match normals {
+            Some(normals) if normals.len() == mesh.num_triangles() => mesh.normals = normals,
+            _ => mesh.calculate_normals(),
+        }
+        mesh
+    }
+
+    // checks that untrusted indices (e.g. read from a file) form whole
+    // triangles of existing vertices, since triangle() doesn't
+    pub fn check_indices(indices: &[u32], num_vertices: usize) -> Result<(), String> {
+        if !indices.len().is_multiple_of(3) {
+            return Err(format!("{} indices don't make whole triangles", indices.len()));
+        }
+        match indices.iter().find(|&&index| index as usize >= num_vertices) {
+            Some(index) => Err(format!("index {} is out of bounds for {} vertices", index, num_vertices)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangle(&self, index: usize) -> (&Vec3, &Vec3, &Vec3) {
+        (
+            &self.vertices[self.indices[index * 3] as usize],
+            &self.vertices[self.indices[index * 3 + 1] as usize],
+            &self.vertices[self.indices[index * 3 + 2] as usize],
+        )
+    }
+
+    pub fn triangle_normal(&self, index: usize) -> Vec3 {
+        self.normals[index]
+    }
+
+    fn calculate_normals(&mut self) {
+        self.normals = (0..self.num_triangles())
+            .map(|i| {
+                let (p0, p1, p2) = self.triangle(i);
+                triangle_normal(p0, p1, p2)
+            })
+            .collect();
+    }
+
+    // returns the closest hit along the ray
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<RayHit> {
+        ray.intersect_aabb(&self.aabb)?;
+        let mut closest: Option<RayHit> = None;
+        for i in 0..self.num_triangles() {
+            let (p0, p1, p2) = self.triangle(i);
+            if let Some(t) = ray.intersect_triangle(p0, p1, p2) {
+                match closest {
+                    Some(hit) if hit.t <= t => {},
+                    _ => closest = Some(RayHit { t, triangle_index: i }),
+                }
+            }
+        }
+        closest
+    }
+
+    pub fn intersect_convex_hull(&self, hull: &ConvexHull) -> IntersectionState {
+        match hull.intersect_aabb(&self.aabb) {
+            IntersectionState::Intersection => {},
+            state => return state,
+        }
+
+        let mut any_inside = false;
+        let mut any_outside = false;
+        for i in 0..self.num_triangles() {
+            let (p0, p1, p2) = self.triangle(i);
+            match hull.intersect_triangle(p0, p1, p2) {
+                IntersectionState::Inside => any_inside = true,
+                IntersectionState::Outside => any_outside = true,
+                IntersectionState::Intersection => return IntersectionState::Intersection,
+            }
+        }
+        match (any_inside, any_outside) {
+            (true, false) => IntersectionState::Inside,
+            (false, _) => IntersectionState::Outside,
+            (true, true) => IntersectionState::Intersection,
+        }
+    }
+
+    pub fn transform(&mut self, mat: &Mat4) {
+        for v in self.vertices.iter_mut() {
+            *v = (mat * vec4(v.x, v.y, v.z, 1.0)).xyz();
+        }
+        self.aabb.set_from_points(&self.vertices);
+        self.calculate_normals();
+    }
+}
+
+#[wasm_bindgen(js_class = "TriangleMesh")]
+impl TriangleMesh {
+    pub fn js_intersect_ray(&self, origin_slice: &[f32], direction_slice: &[f32]) -> Option<RayHit> {
+        assert_eq!(origin_slice.len(), 3);
+        assert_eq!(direction_slice.len(), 3);
+        let ray = Ray::new(make_vec3(origin_slice), make_vec3(direction_slice));
+        self.intersect_ray(&ray)
+    }
+
+    pub fn js_intersect_convex_hull(&self, hull: &ConvexHull) -> IntersectionState {
+        self.intersect_convex_hull(hull)
+    }
+
+    pub fn js_transform(&mut self, mat_slice: &[f32]) {
+        assert_eq!(mat_slice.len(), 16);
+        let mat = make_mat4(mat_slice);
+        self.transform(&mat);
+    }
+
+    pub fn js_triangle_normal(&self, index: usize) -> Vec<f32> {
+        let normal = self.triangle_normal(index);
+        vec![normal.x, normal.y, normal.z]
+    }
+
+    pub fn get_num_triangles(&self) -> usize {
+        self.num_triangles()
+    }
+
+    pub fn get_vertices(&self) -> Vec<f32> {
+        self.vertices.iter().flat_map(|v| [v.x, v.y, v.z]).collect()
+    }
+
+    pub fn get_indices(&self) -> Vec<u32> {
+        self.indices.clone()
+    }
+
+    pub fn get_aabb(&self) -> Vec<f32> {
+        vec![self.aabb.min.x, self.aabb.min.y, self.aabb.min.z, self.aabb.max.x, self.aabb.max.y, self.aabb.max.z]
+    }
+}
+
+// where a point moving from `start` to `end` first touches a surface
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentHit {
+    pub t: f32, // fraction of the way from start to end
+    pub normal: Vec3, // facing the side the point came from
+}
+
+// a surface with a single height per (x, y), e.g. terrain
+pub trait Heightfield: std::fmt::Debug {
+    // the height and normal at (x, y), or None where there's no surface
+    fn query(&self, x: f32, y: f32) -> Option<(f32, Vec3)>;
+}
+
+// the world that simple simulations (e.g. particles) collide with: planes
+// that are solid on their negative side, triangle meshes, and heightfields
+#[wasm_bindgen(js_name = "CollisionGeometry")]
+#[derive(Debug, Default)]
+pub struct CollisionGeometry {
+    planes: Vec<Plane>,
+    meshes: Vec<TriangleMesh>,
+    heightfields: Vec<Box<dyn Heightfield>>,
+}
+
+#[wasm_bindgen(js_class = "CollisionGeometry")]
+impl CollisionGeometry {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        CollisionGeometry::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.planes.clear();
+        self.meshes.clear();
+        self.heightfields.clear();
+    }
+
+    pub fn push_plane(&mut self, x: f32, y: f32, z: f32, d: f32) {
+        self.planes.push(Plane::new(Vec3::new(x, y, z), d).normalized());
+    }
+
+    pub fn add_mesh(&mut self, mesh: &TriangleMesh) {
+        self.meshes.push(mesh.clone());
+    }
+}
+
+impl CollisionGeometry {
+    pub fn add_heightfield(&mut self, heightfield: Box<dyn Heightfield>) {
+        self.heightfields.push(heightfield);
+    }
+
+    // returns the first surface the segment crosses. Meshes are hit from
+    // either side, planes and heightfields only from above
+    pub fn intersect_segment(&self, start: &Vec3, end: &Vec3) -> Option<SegmentHit> {
+        let mut closest: Option<SegmentHit> = None;
+        let mut consider = |t: f32, normal: Vec3| {
+            match closest {
+                Some(hit) if hit.t <= t => {},
+                _ => closest = Some(SegmentHit { t, normal }),
+            }
+        };
+
+        for plane in &self.planes {
+            let (d0, d1) = (plane.distance(start), plane.distance(end));
+            if d0 >= 0.0 && d1 < 0.0 {
+                consider(d0 / (d0 - d1), plane.normal);
+            }
+        }
+
+        let direction = end - start;
+        let ray = Ray::new(*start, direction);
+        for mesh in &self.meshes {
+            if let Some(hit) = mesh.intersect_ray(&ray) {
+                if hit.t <= 1.0 {
+                    let normal = mesh.triangle_normal(hit.triangle_index).normalize();
+                    consider(hit.t, if normal.dot(&direction) > 0.0 { -normal } else { normal });
+                }
+            }
+        }
+
+        // heightfields are sampled at the segment's ends, so this assumes
+        // the segment is short relative to the surface's features
+        for heightfield in &self.heightfields {
+            if let (Some((h0, _)), Some((h1, normal))) = (heightfield.query(start.x, start.y), heightfield.query(end.x, end.y)) {
+                let (d0, d1) = (start.z - h0, end.z - h1);
+                if d0 >= 0.0 && d1 < 0.0 {
+                    consider(d0 / (d0 - d1), normal);
+                }
+            }
+        }
+        closest
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Axis {
     X,
@@ -377,3 +717,102 @@ pub fn point_inside_convex_polygon(point: &Vec2, polygon_vertices: &[Vec2]) -> b
     }
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_quad(z: f32) -> TriangleMesh {
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, z),
+            Vec3::new(1.0, 0.0, z),
+            Vec3::new(1.0, 1.0, z),
+            Vec3::new(0.0, 1.0, z),
+        ];
+        TriangleMesh::new(vertices, vec![0, 1, 2, 0, 2, 3], None)
+    }
+
+    #[test]
+    fn test_ray_triangle_mesh() {
+        let mesh = unit_quad(2.0);
+        let down = Ray::new(Vec3::new(0.25, 0.75, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = mesh.intersect_ray(&down).unwrap();
+        assert_eq!(hit.triangle_index, 1);
+        assert!((hit.t - 8.0).abs() < 1e-5);
+        assert!((mesh.triangle_normal(hit.triangle_index) - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+
+        let miss = Ray::new(Vec3::new(2.0, 0.5, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(mesh.intersect_ray(&miss).is_none());
+        let away = Ray::new(Vec3::new(0.5, 0.5, 10.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(mesh.intersect_ray(&away).is_none());
+
+        let mut moved = unit_quad(2.0);
+        moved.transform(&nalgebra_glm::translation(&Vec3::new(0.0, 0.0, 3.0)));
+        assert!((moved.intersect_ray(&down).unwrap().t - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_check_indices() {
+        assert!(TriangleMesh::check_indices(&[0, 1, 2, 0, 2, 3], 4).is_ok());
+        assert!(TriangleMesh::check_indices(&[0, 1, 2, 0, 2, 4], 4).is_err());
+        assert!(TriangleMesh::check_indices(&[0, 1, 2, 0], 4).is_err());
+    }
+
+    #[derive(Debug)]
+    struct Slope;
+
+    impl Heightfield for Slope {
+        fn query(&self, x: f32, _y: f32) -> Option<(f32, Vec3)> {
+            if x < 0.0 {
+                return None;
+            }
+            Some((x, Vec3::new(-1.0, 0.0, 1.0).normalize()))
+        }
+    }
+
+    #[test]
+    fn test_collision_geometry() {
+        let mut geometry = CollisionGeometry::new();
+        let start = Vec3::new(0.5, 0.5, 10.0);
+        let end = Vec3::new(0.5, 0.5, -10.0);
+        assert!(geometry.intersect_segment(&start, &end).is_none());
+
+        geometry.push_plane(0.0, 0.0, 2.0, 10.0); // z = -5
+        let hit = geometry.intersect_segment(&start, &end).unwrap();
+        assert!((hit.t - 0.75).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+        assert!(geometry.intersect_segment(&end, &start).is_none());
+
+        // meshes are hit from below too, with the normal facing the segment
+        geometry.add_mesh(&unit_quad(2.0));
+        let hit = geometry.intersect_segment(&start, &end).unwrap();
+        assert!((hit.t - 0.4).abs() < 1e-5);
+        let hit = geometry.intersect_segment(&Vec3::new(0.5, 0.5, 0.0), &Vec3::new(0.5, 0.5, 4.0)).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+
+        geometry.add_heightfield(Box::new(Slope));
+        let hit = geometry.intersect_segment(&Vec3::new(4.0, 0.0, 5.0), &Vec3::new(4.0, 0.0, 3.0)).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-5);
+        assert!(hit.normal.x < 0.0);
+        assert!(geometry.intersect_segment(&Vec3::new(-4.0, 0.0, 5.0), &Vec3::new(-4.0, 0.0, 3.0)).is_none());
+    }
+
+    #[test]
+    fn test_triangle_mesh_convex_hull() {
+        let mut hull = ConvexHull::new();
+        // the box [0, 1] on every axis
+        hull.push_plane(1.0, 0.0, 0.0, 0.0);
+        hull.push_plane(-1.0, 0.0, 0.0, 1.0);
+        hull.push_plane(0.0, 1.0, 0.0, 0.0);
+        hull.push_plane(0.0, -1.0, 0.0, 1.0);
+        hull.push_plane(0.0, 0.0, 1.0, 0.0);
+        hull.push_plane(0.0, 0.0, -1.0, 1.0);
+
+        assert!(matches!(unit_quad(0.5).intersect_convex_hull(&hull), IntersectionState::Inside));
+        assert!(matches!(unit_quad(2.0).intersect_convex_hull(&hull), IntersectionState::Outside));
+        let mut straddling = unit_quad(0.5);
+        straddling.transform(&nalgebra_glm::translation(&Vec3::new(0.5, 0.0, 0.0)));
+        assert!(matches!(straddling.intersect_convex_hull(&hull), IntersectionState::Intersection));
+    }
+}
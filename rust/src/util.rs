@@ -44,8 +44,7 @@ pub fn linear_to_srgb(value: f32) -> u8 {
         } else {
             value.powf(1.0/2.4) * 1.055 - 0.055
         };
-        let value = value / 255.0;
-        (value + 0.5) as u8
+        (value * 255.0 + 0.5) as u8
     }
 }
 
@@ -4,6 +4,7 @@ use deku::ctx::ByteSize;
 use wasm_bindgen::prelude::*;
 
 use super::common::{Chunk, parse, parse_array, parse_with_byte_size, ChunkedData, Vec3, AABBox};
+use super::terrain::AdtLodTerrain;
 
 pub const TILE_SIZE: f32 = 1600.0 / 3.0;
 pub const CHUNK_SIZE: f32 = TILE_SIZE / 16.0;
@@ -22,6 +23,7 @@ pub struct Adt {
     lod_map_object_defs: Vec<WmoDefinition>,
     lod_levels: Option<LodLevels>,
     liquids: Vec<Option<LiquidData>>,
+    texture_params: Vec<TextureParams>,
 }
 
 #[wasm_bindgen(js_class = "WowAdt")]
@@ -110,6 +112,7 @@ impl Adt {
             diffuse_tex_ids: None,
             lod_levels: None,
             liquids,
+            texture_params: vec![],
         })
     }
 
@@ -240,6 +243,7 @@ impl Adt {
                 }
                 b"DIHM" => self.height_tex_ids = Some(parse_with_byte_size(chunk_data)?),
                 b"DIDM" => self.diffuse_tex_ids = Some(parse_with_byte_size(chunk_data)?),
+                b"PXTM" => self.texture_params = parse_array(chunk_data, 16)?,
                 _ => println!("skipping {}", std::str::from_utf8(&chunk.magic).unwrap()),
             }
         }
@@ -355,6 +359,32 @@ impl Adt {
     pub fn get_vbo_info() -> AdtVBOInfo {
         ADT_VBO_INFO.clone()
     }
+
+    // a simplified mesh of the whole ADT for distant terrain, see
+    // `AdtLodTerrain::new()`
+    pub fn build_lod_terrain(&self, lod_level: u32, skirt_depth: f32) -> Result<AdtLodTerrain, String> {
+        AdtLodTerrain::new(self, lod_level, skirt_depth)
+    }
+}
+
+// rust-only interface
+impl Adt {
+    pub fn get_map_chunks(&self) -> &[MapChunk] {
+        &self.map_chunks
+    }
+
+    pub fn get_diffuse_texture_id(&self, texture_index: usize) -> Option<u32> {
+        self.diffuse_tex_ids.as_ref()?.file_data_ids.get(texture_index).copied()
+    }
+
+    pub fn get_height_texture_id(&self, texture_index: usize) -> Option<u32> {
+        self.height_tex_ids.as_ref()?.file_data_ids.get(texture_index).copied()
+            .filter(|&id| id != 0)
+    }
+
+    pub fn get_texture_params(&self, texture_index: usize) -> Option<&TextureParams> {
+        self.texture_params.get(texture_index)
+    }
 }
 
 #[derive(Debug, DekuRead, Clone)]
@@ -660,6 +690,15 @@ impl From<u32> for MapChunkTextureLayerSettings {
     }
 }
 
+// MTXP, one per MDID texture
+#[derive(Debug, Clone, DekuRead)]
+pub struct TextureParams {
+    pub _flags: u32,
+    pub height_scale: f32,
+    pub height_offset: f32,
+    pub _padding: u32,
+}
+
 #[derive(Debug, Clone, DekuRead)]
 pub struct VertexColors {
     pub vertex_colors: [u8; 4 * (9*9 + 8*8)],
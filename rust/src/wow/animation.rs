@@ -1,12 +1,16 @@
+use std::marker::PhantomData;
+
 use deku::prelude::*;
 use js_sys::Uint8Array;
 use wasm_bindgen::prelude::*;
 use js_sys::Float32Array;
+use nalgebra_glm::{self as glm, Mat4};
 use crate::wow::m2::*;
 use crate::wow::common::*;
+use crate::wow::skel::Skeleton;
 
 #[derive(Debug, Clone)]
-struct LcgRng {
+pub(crate) struct LcgRng {
     state: u32,
 }
 
@@ -40,6 +44,58 @@ pub struct M2CompBone {
     pub pivot: Vec3,
 }
 
+impl M2CompBone {
+    pub fn allocate(&mut self, data: &[u8], sequences: &[M2Sequence]) -> Result<(), String> {
+        self.translation.allocate_inline(data, sequences)?;
+        self.rotation_quat16.allocate_inline(data, sequences)?;
+        self.scaling.allocate_inline(data, sequences)?;
+        self.update_rotation();
+        Ok(())
+    }
+
+    pub fn allocate_external(&mut self, sequence_index: usize, anim_data: &[u8]) -> Result<(), String> {
+        self.translation.allocate_external(sequence_index, anim_data)?;
+        self.rotation_quat16.allocate_external(sequence_index, anim_data)?;
+        self.scaling.allocate_external(sequence_index, anim_data)?;
+        self.update_rotation();
+        Ok(())
+    }
+
+    fn update_rotation(&mut self) {
+        // convert the quat16s into quats so we don't have to do the
+        // math countless times per frame
+        let mut quat_values = Vec::new();
+        for quats in self.rotation_quat16.values() {
+            let mut values = Vec::new();
+            for quat16 in quats {
+                values.push(Quat::from(*quat16));
+            }
+            quat_values.push(values);
+        }
+
+        self.rotation = Some(M2Track {
+            interpolation_type: self.rotation_quat16.interpolation_type,
+            global_sequence: self.rotation_quat16.global_sequence,
+            timestamps: Some(self.rotation_quat16.timestamps().clone()),
+            values: Some(quat_values),
+
+            // hack: put in some fake pointers
+            timestamps_unallocated: WowArray { count: 0, offset: 0, element_type: PhantomData },
+            values_unallocated: WowArray { count: 0, offset: 0, element_type: PhantomData },
+            sequence_timestamps_unallocated: None,
+            sequence_values_unallocated: None,
+        });
+    }
+}
+
+#[wasm_bindgen(js_name = "WowM2AnimFileId")]
+#[derive(DekuRead, Debug, Clone, Copy)]
+pub struct AnimFileId {
+    pub anim_id: u16,
+    pub sub_anim_id: u16,
+    pub file_id: u32,
+}
+
 #[derive(DekuRead, Debug, Clone)]
 pub struct M2Sequence {
     pub id: u16, // lookup table id?
@@ -59,6 +115,12 @@ pub struct M2Sequence {
 }
 
 impl M2Sequence {
+    // sequences without the "embedded data" flag keep their keyframes in an
+    // external .anim file. aliases don't have any keyframes at all
+    pub fn is_external(&self) -> bool {
+        (self.flags & 0x20) == 0 && (self.flags & 0x40) == 0
+    }
+
     fn calculate_animation_repeats(&self, rng: &mut LcgRng) -> i32 {
         let times = (self.replay_max - self.replay_min) as f32;
         self.replay_min as i32 + (times * rng.next_f32()) as i32
@@ -98,22 +160,59 @@ pub struct M2Track<T> {
     #[deku(skip)] pub timestamps: Option<Vec<Vec<u32>>>,
     pub values_unallocated: WowArray<WowArray<T>>,
     #[deku(skip)] pub values: Option<Vec<Vec<T>>>,
+    #[deku(skip)] sequence_timestamps_unallocated: Option<Vec<WowArray<u32>>>,
+    #[deku(skip)] sequence_values_unallocated: Option<Vec<WowArray<T>>>,
 }
 
 impl<T> M2Track<T> {
     pub fn allocate(&mut self, data: &[u8]) -> Result<(), String> where for<'a> T: DekuReader<'a> {
-        let mut timestamps = Vec::new();
-        for arr in self.timestamps_unallocated.to_vec(data)? {
-            timestamps.push(arr.to_vec(data)?);
+        self.allocate_inline(data, &[])
+    }
+
+    // Like `allocate()`, but leaves the keyframes of any sequence stored in
+    // an external .anim file empty until `allocate_external()` is called.
+    pub fn allocate_inline(&mut self, data: &[u8], sequences: &[M2Sequence]) -> Result<(), String> where for<'a> T: DekuReader<'a> {
+        // global sequence tracks always live in the M2 itself
+        let is_external = |i: usize| self.global_sequence < 0 && matches!(sequences.get(i), Some(seq) if seq.is_external());
+
+        let timestamp_arrays = self.timestamps_unallocated.to_vec(data)?;
+        let mut timestamps = Vec::with_capacity(timestamp_arrays.len());
+        for (i, arr) in timestamp_arrays.iter().enumerate() {
+            if is_external(i) {
+                timestamps.push(Vec::new());
+            } else {
+                timestamps.push(arr.to_vec(data)?);
+            }
         }
-        self.timestamps = Some(timestamps);
 
-        let mut values = Vec::new();
-        for arr in self.values_unallocated.to_vec(data)? {
-            values.push(arr.to_vec(data)?);
+        let value_arrays = self.values_unallocated.to_vec(data)?;
+        let mut values = Vec::with_capacity(value_arrays.len());
+        for (i, arr) in value_arrays.iter().enumerate() {
+            if is_external(i) {
+                values.push(Vec::new());
+            } else {
+                values.push(arr.to_vec(data)?);
+            }
         }
+
+        self.timestamps = Some(timestamps);
         self.values = Some(values);
+        self.sequence_timestamps_unallocated = Some(timestamp_arrays);
+        self.sequence_values_unallocated = Some(value_arrays);
+        Ok(())
+    }
 
+    // Reads the keyframes for a single sequence out of its .anim file data.
+    pub fn allocate_external(&mut self, sequence_index: usize, anim_data: &[u8]) -> Result<(), String> where for<'a> T: DekuReader<'a> {
+        let error = "must call M2Track::allocate_inline() before allocate_external()";
+        let timestamp_arrays = self.sequence_timestamps_unallocated.as_ref().ok_or(error)?;
+        let value_arrays = self.sequence_values_unallocated.as_ref().ok_or(error)?;
+        let timestamps = self.timestamps.as_mut().ok_or(error)?;
+        let values = self.values.as_mut().ok_or(error)?;
+        if let (Some(timestamp_arr), Some(value_arr)) = (timestamp_arrays.get(sequence_index), value_arrays.get(sequence_index)) {
+            timestamps[sequence_index] = timestamp_arr.to_vec(anim_data)?;
+            values[sequence_index] = value_arr.to_vec(anim_data)?;
+        }
         Ok(())
     }
 
@@ -195,6 +294,8 @@ pub struct AnimationManager {
     blend_factor: f32,
     colors: Vec<M2Color>,
     bones: Vec<M2CompBone>,
+    bones_from_skeleton: bool,
+    loaded_sequences: Vec<bool>,
     lights: Vec<M2Light>,
 }
 
@@ -210,6 +311,12 @@ impl AnimationManager {
             }
         }
 
+        // models whose sequences live in a .skel file have nothing to play
+        // until it's been attached
+        if self.current_animation.animation_index.is_none() {
+            return;
+        }
+
         let main_variation_record = &self.sequences[self.current_animation.main_variation_index];
 
         // If we don't have a next animation yet, and this animation isn't set
@@ -365,6 +472,36 @@ impl AnimationManager {
         }
     }
     
+    // returns a column-major 4x4 matrix per bone, including parent transforms
+    // and billboarding. camera_position_slice must be in the same space that
+    // model_matrix_slice transforms into
+    pub fn get_bone_matrices(&self, model_matrix_slice: &[f32], camera_position_slice: &[f32]) -> Result<Vec<f32>, String> {
+        check_model_matrix_and_camera(model_matrix_slice, camera_position_slice)?;
+        let model_matrix = Mat4::from_column_slice(model_matrix_slice);
+        let camera_position = glm::make_vec3(camera_position_slice);
+        let mut result = Vec::with_capacity(self.bones.len() * 16);
+        for mat in self.calculate_bone_matrices(&model_matrix, &camera_position) {
+            result.extend_from_slice(mat.as_slice());
+        }
+        Ok(result)
+    }
+
+    // poses the M2's vertex data (see WowM2::take_vertex_data) with the
+    // current animation, returning a position and normal per vertex
+    pub fn get_skinned_vertices(&self, vertex_data: &[u8], model_matrix_slice: &[f32], camera_position_slice: &[f32]) -> Result<Vec<f32>, String> {
+        check_model_matrix_and_camera(model_matrix_slice, camera_position_slice)?;
+        let model_matrix = Mat4::from_column_slice(model_matrix_slice);
+        let camera_position = glm::make_vec3(camera_position_slice);
+        let vertices = M2::parse_vertex_data(vertex_data)?;
+        let bone_matrices = self.calculate_bone_matrices(&model_matrix, &camera_position);
+        let mut result = Vec::with_capacity(vertices.len() * 6);
+        for vertex in skin_vertices(&vertices, &bone_matrices) {
+            result.extend_from_slice(vertex.position.as_slice());
+            result.extend_from_slice(vertex.normal.as_slice());
+        }
+        Ok(result)
+    }
+
     pub fn update_textures(&self, transparencies: &Float32Array, texture_translations: &Float32Array, texture_rotations: &Float32Array, texture_scalings: &Float32Array) {
         let default_alpha = Fixedi16::from(1.0);
         for (i, weight) in self.texture_weights.iter().enumerate() {
@@ -401,6 +538,54 @@ impl AnimationManager {
         self.sequences.iter().map(|seq| seq.id).collect()
     }
 
+    // Loads the bone keyframes for the sequence matching the given IDs (as
+    // listed in the M2's or skeleton's AFID chunk) from its .anim file.
+    pub fn add_anim_file(&mut self, anim_id: u16, sub_anim_id: u16, data: &[u8]) -> Result<(), String> {
+        let index = self.sequences.iter()
+            .position(|seq| seq.id == anim_id && seq.sub_id == sub_anim_id)
+            .ok_or(format!("no sequence matching .anim file {}-{}", anim_id, sub_anim_id))?;
+        let bone_data = get_anim_bone_data(data, self.bones_from_skeleton);
+        for bone in self.bones.iter_mut() {
+            bone.allocate_external(index, bone_data)?;
+        }
+        self.loaded_sequences[index] = true;
+        Ok(())
+    }
+
+    // Whether the given sequence's keyframes are available, i.e. it's either
+    // embedded in the M2 or its .anim file has been added.
+    pub fn is_sequence_loaded(&self, anim_id: u16, sub_anim_id: u16) -> bool {
+        self.sequences.iter()
+            .position(|seq| seq.id == anim_id && seq.sub_id == sub_anim_id)
+            .map(|index| self.loaded_sequences[index])
+            .unwrap_or(false)
+    }
+
+    // Replaces the M2's bones and sequences with those of its .skel file, if
+    // present. Any .anim files must be added after this.
+    pub fn set_skeleton(&mut self, skeleton: &Skeleton) {
+        if let Some(bones) = skeleton.bones.as_ref() {
+            self.bones = bones.clone();
+            self.bones_from_skeleton = true;
+        }
+
+        if let Some(sequences) = skeleton.sequences.as_ref() {
+            self.sequences = sequences.clone();
+            self.loaded_sequences = self.sequences.iter().map(|seq| !seq.is_external()).collect();
+            self.current_animation = AnimationState::new(find_stand_index(&self.sequences));
+            if let Some(index) = self.current_animation.animation_index {
+                self.current_animation.repeat_times = self.sequences[index].calculate_animation_repeats(&mut self.rng);
+            }
+            self.next_animation = AnimationState::new(None);
+            self.blend_factor = 0.0;
+        }
+
+        if let Some(durations) = skeleton.global_sequence_durations.as_ref() {
+            self.global_sequence_durations = durations.clone();
+            self.global_sequence_times = vec![0.0; durations.len()];
+        }
+    }
+
     pub fn set_sequence_id(&mut self, id: u16) {
         let index = self.sequences.iter()
             .position(|seq| seq.id == id)
@@ -469,14 +654,13 @@ impl AnimationManager {
         lights: Vec<M2Light>,
     ) -> Self {
         let global_sequence_times = vec![0.0; global_sequence_durations.len()];
-        // pull out the "Stand" animation, which is the resting animation for all models
-        let index = sequences.iter()
-            .position(|seq| seq.id == 0)
-            .unwrap();
-        let mut current_animation = AnimationState::new(Some(index));
+        let mut current_animation = AnimationState::new(find_stand_index(&sequences));
         let mut rng = LcgRng::new(1312);
-        current_animation.repeat_times = sequences[index].calculate_animation_repeats(&mut rng);
+        if let Some(index) = current_animation.animation_index {
+            current_animation.repeat_times = sequences[index].calculate_animation_repeats(&mut rng);
+        }
         let next_animation = AnimationState::new(None);
+        let loaded_sequences = sequences.iter().map(|seq| !seq.is_external()).collect();
 
         AnimationManager {
             global_sequence_durations,
@@ -488,17 +672,91 @@ impl AnimationManager {
             texture_weights,
             colors,
             bones,
+            bones_from_skeleton: false,
+            loaded_sequences,
             lights,
             global_sequence_times,
             rng,
         }
     }
 
+    pub fn get_bones(&self) -> &[M2CompBone] {
+        &self.bones
+    }
+
+    pub fn get_sequences(&self) -> &[M2Sequence] {
+        &self.sequences
+    }
+
+    // like `is_sequence_loaded()`, but by index into `get_sequences()`
+    pub fn is_sequence_index_loaded(&self, index: usize) -> bool {
+        self.loaded_sequences.get(index).copied().unwrap_or(false)
+    }
+
+    // mirrors the bone matrix setup in the model shader: each bone's transform
+    // is the parent chain up to the bone (optionally billboarded towards the
+    // camera), followed by whatever part of the chain comes after billboarding
+    pub fn calculate_bone_matrices(&self, model_matrix: &Mat4, camera_position: &glm::Vec3) -> Vec<Mat4> {
+        let default_translation = Vec3::new(0.0);
+        let default_rotation = Quat { x: 0.0, y: 0.0, z: 0.0, w: 1.0 };
+        let default_scaling = Vec3::new(1.0);
+        let mut billboards: Vec<Option<Billboard>> = Vec::with_capacity(self.bones.len());
+        let mut transforms: Vec<Mat4> = Vec::with_capacity(self.bones.len());
+        let mut post_billboard_transforms: Vec<Mat4> = Vec::with_capacity(self.bones.len());
+        let mut result = Vec::with_capacity(self.bones.len());
+        for (i, bone) in self.bones.iter().enumerate() {
+            let translation: glm::Vec3 = self.get_current_value_with_blend(&bone.translation, default_translation).into();
+            let rotation = self.get_current_value_with_blend(bone.rotation.as_ref().unwrap(), default_rotation);
+            let scaling: glm::Vec3 = self.get_current_value_with_blend(&bone.scaling, default_scaling).into();
+            let pivot: glm::Vec3 = bone.pivot.into();
+
+            let local_transform = glm::translation(&pivot)
+                * glm::translation(&translation)
+                * glm::quat_to_mat4(&glm::quat(rotation.x, rotation.y, rotation.z, rotation.w))
+                * glm::scaling(&scaling);
+            let anti_pivot = glm::translation(&-pivot);
+
+            // bones are sorted such that parents always come first
+            let parent = if bone.parent_bone >= 0 && (bone.parent_bone as usize) < i {
+                Some(bone.parent_bone as usize)
+            } else {
+                None
+            };
+
+            // children of billboarded bones are billboarded too
+            let mut billboard = Billboard::from_flags(&M2BoneFlags::new(bone.flags));
+            if billboard.is_none() {
+                billboard = parent.and_then(|parent| billboards[parent]);
+            }
+
+            let parent_transform = parent.map_or_else(Mat4::identity, |parent| transforms[parent]);
+            let parent_post_billboard_transform = parent.map_or(*model_matrix, |parent| post_billboard_transforms[parent]);
+            let (transform, post_billboard_transform) = if billboard.is_some() {
+                (parent_transform * anti_pivot, parent_post_billboard_transform * local_transform)
+            } else {
+                (Mat4::identity(), parent_post_billboard_transform * local_transform * anti_pivot)
+            };
+
+            let mut final_transform = post_billboard_transform;
+            if let Some(billboard) = billboard {
+                billboard.apply(&mut final_transform, camera_position);
+            }
+            result.push(final_transform * transform);
+
+            billboards.push(billboard);
+            transforms.push(transform);
+            post_billboard_transforms.push(post_billboard_transform);
+        }
+        result
+    }
+
     fn get_current_value<U, V>(&self, mut curr_time: f64, mut animation_index: usize, animation: &M2Track<U>, default: V) -> V
         where V: Clone + Lerp, U: Into<V> + Clone
         {
         if animation.global_sequence >= 0 {
-            curr_time = self.global_sequence_times[animation.global_sequence as usize];
+            curr_time = self.global_sequence_times.get(animation.global_sequence as usize)
+                .copied()
+                .unwrap_or(0.0);
         }
 
         if animation.timestamps().len() <= animation_index {
@@ -541,9 +799,10 @@ impl AnimationManager {
 
     pub fn get_current_value_with_blend<U, V>(&self, animation: &M2Track<U>, default: V) -> V
         where V: Clone + Lerp, U: Into<V> + Clone {
+        // with no sequences to play, only global sequence tracks will animate
         let result = self.get_current_value(
             self.current_animation.animation_time,
-            self.current_animation.animation_index.unwrap(),
+            self.current_animation.animation_index.unwrap_or(0),
             animation,
             default.clone()
         );
@@ -564,7 +823,8 @@ impl AnimationManager {
         result
     }
 
-    pub fn get_particle_value<T>(&self, age: f64, animation: &M2TrackPartial<T>, default: T) -> T
+    // particle tracks are keyed by the particle's age, not the animation time
+    pub fn get_particle_value<T>(age: f64, animation: &M2TrackPartial<T>, default: T) -> T
         where T: Clone + Lerp {
             let num_timestamps = animation.timestamps().len();
             if num_timestamps == 0 {
@@ -588,6 +848,152 @@ impl AnimationManager {
         }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Billboard {
+    Spherical,
+    CylindricalLockX,
+    CylindricalLockY,
+    CylindricalLockZ,
+}
+
+impl Billboard {
+    fn from_flags(flags: &M2BoneFlags) -> Option<Self> {
+        if flags.spherical_billboard {
+            Some(Billboard::Spherical)
+        } else if flags.cylindrical_billboard_lock_x {
+            Some(Billboard::CylindricalLockX)
+        } else if flags.cylindrical_billboard_lock_y {
+            Some(Billboard::CylindricalLockY)
+        } else if flags.cylindrical_billboard_lock_z {
+            Some(Billboard::CylindricalLockZ)
+        } else {
+            None
+        }
+    }
+
+    // replaces the matrix's rotation (and scale) so that its X axis points at
+    // the camera. cylindrical billboards may only rotate around their locked
+    // axis
+    fn apply(&self, m: &mut Mat4, camera_position: &glm::Vec3) {
+        let position = glm::vec3(m[12], m[13], m[14]);
+        let to_camera = camera_position - position;
+        let locked_axis = |column: usize| glm::vec3(m[column * 4], m[column * 4 + 1], m[column * 4 + 2]).normalize();
+        let perpendicular = |v: &glm::Vec3, axis: &glm::Vec3| v - axis * v.dot(axis);
+
+        let (x, y, z) = match self {
+            Billboard::Spherical => {
+                let forward = to_camera.normalize();
+                let left = glm::vec3(0.0, 0.0, 1.0).cross(&forward).normalize();
+                let up = forward.cross(&left).normalize();
+                (forward, left, up)
+            },
+            Billboard::CylindricalLockX => {
+                let axis = locked_axis(0);
+                let up = perpendicular(&to_camera, &axis).normalize();
+                (axis, up.cross(&axis), up)
+            },
+            Billboard::CylindricalLockY => {
+                let axis = locked_axis(1);
+                let forward = perpendicular(&to_camera, &axis).normalize();
+                (forward, axis, forward.cross(&axis))
+            },
+            Billboard::CylindricalLockZ => {
+                let axis = locked_axis(2);
+                let forward = perpendicular(&to_camera, &axis).normalize();
+                (forward, axis.cross(&forward), axis)
+            },
+        };
+
+        // the camera is sitting right on top of the bone, so leave it be
+        if x.iter().chain(y.iter()).chain(z.iter()).any(|v| !v.is_finite()) {
+            return;
+        }
+
+        for (column, axis) in [x, y, z].iter().enumerate() {
+            m[column * 4] = axis.x;
+            m[column * 4 + 1] = axis.y;
+            m[column * 4 + 2] = axis.z;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkinnedVertex {
+    pub position: glm::Vec3,
+    pub normal: glm::Vec3,
+}
+
+// CPU equivalent of the model shader's vertex skinning
+pub fn skin_vertices(vertices: &[M2Vertex], bone_matrices: &[Mat4]) -> Vec<SkinnedVertex> {
+    let mut result = Vec::with_capacity(vertices.len());
+    for vertex in vertices {
+        let mut combined = Mat4::zeros();
+        for j in 0..4 {
+            let weight = vertex.bone_weights[j] as f32 / 255.0;
+            if weight == 0.0 {
+                continue;
+            }
+            if let Some(bone_matrix) = bone_matrices.get(vertex.bone_indices[j] as usize) {
+                combined += bone_matrix * weight;
+            }
+        }
+
+        let position: glm::Vec3 = vertex.position.into();
+        let normal: glm::Vec3 = vertex.normal.into();
+        let mut skinned_normal = glm::mat4_to_mat3(&combined) * normal;
+        if skinned_normal.magnitude() > 0.0 {
+            skinned_normal.normalize_mut();
+        }
+        result.push(SkinnedVertex {
+            position: (combined * glm::vec4(position.x, position.y, position.z, 1.0)).xyz(),
+            normal: skinned_normal,
+        });
+    }
+    result
+}
+
+fn check_model_matrix_and_camera(model_matrix_slice: &[f32], camera_position_slice: &[f32]) -> Result<(), String> {
+    if model_matrix_slice.len() != 16 {
+        return Err(format!("expected a 16 float matrix, got {}", model_matrix_slice.len()));
+    }
+    if camera_position_slice.len() != 3 {
+        return Err(format!("expected a 3 float camera position, got {}", camera_position_slice.len()));
+    }
+    Ok(())
+}
+
+// pull out the "Stand" animation, which is the resting animation for all models
+fn find_stand_index(sequences: &[M2Sequence]) -> Option<usize> {
+    sequences.iter().position(|seq| seq.id == 0)
+}
+
+// Legion+ .anim files are chunked, with AFM2 holding keyframes for the M2's
+// own bones and AFSB holding those for .skel bones. Older ones are unchunked,
+// and offsets are relative to the start of the file.
+fn get_anim_bone_data(data: &[u8], from_skeleton: bool) -> &[u8] {
+    let is_chunked = data.len() >= 8 && matches!(&data[0..4], b"AFM2" | b"AFSA" | b"AFSB");
+    if !is_chunked {
+        return data;
+    }
+
+    let mut m2_bone_data = None;
+    let mut skeleton_bone_data = None;
+    for (chunk, chunk_data) in ChunkedData::new(data) {
+        match &chunk.magic {
+            b"AFM2" => m2_bone_data = Some(chunk_data),
+            b"AFSB" => skeleton_bone_data = Some(chunk_data),
+            _ => {},
+        }
+    }
+
+    let bone_data = if from_skeleton {
+        skeleton_bone_data.or(m2_bone_data)
+    } else {
+        m2_bone_data.or(skeleton_bone_data)
+    };
+    bone_data.unwrap_or(&[])
+}
+
 trait AsTimestamp {
     fn as_timestamp(&self) -> f64;
 }
@@ -623,3 +1029,96 @@ fn find_timestamp_index<T: AsTimestamp>(timestamps: &Vec<T>, curr_time: f64) ->
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(bone_weights: [u8; 4], bone_indices: [u8; 4]) -> M2Vertex {
+        M2Vertex {
+            position: Vec3 { x: 1.0, y: 2.0, z: 3.0 },
+            bone_weights,
+            bone_indices,
+            normal: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+            tex_coords: [Vec2 { x: 0.0, y: 0.0 }; 2],
+        }
+    }
+
+    #[test]
+    fn test_skin_vertices() {
+        let bone_matrices = [
+            glm::translation(&glm::vec3(2.0, 0.0, 0.0)),
+            glm::translation(&glm::vec3(-2.0, 0.0, 0.0)),
+            glm::rotation(std::f32::consts::FRAC_PI_2, &glm::vec3(1.0, 0.0, 0.0)),
+        ];
+
+        let single = skin_vertices(&[vertex([255, 0, 0, 0], [0, 0, 0, 0])], &bone_matrices);
+        assert!((single[0].position - glm::vec3(3.0, 2.0, 3.0)).magnitude() < 1e-5);
+
+        let blended = skin_vertices(&[vertex([128, 127, 0, 0], [0, 1, 0, 0])], &bone_matrices);
+        assert!((blended[0].position - glm::vec3(1.0 + 2.0 / 255.0, 2.0, 3.0)).magnitude() < 1e-5);
+        assert!((blended[0].normal - glm::vec3(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+
+        let rotated = skin_vertices(&[vertex([255, 0, 0, 0], [2, 0, 0, 0])], &bone_matrices);
+        assert!((rotated[0].normal - glm::vec3(0.0, -1.0, 0.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn test_anim_bone_data() {
+        let legacy = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(get_anim_bone_data(&legacy, false), &legacy);
+        assert_eq!(get_anim_bone_data(&legacy, true), &legacy);
+
+        let mut chunked = chunk(b"AFM2", &[1, 1, 1, 1]);
+        chunked.extend(chunk(b"AFSA", &[2, 2, 2, 2]));
+        chunked.extend(chunk(b"AFSB", &[3, 3, 3, 3]));
+        assert_eq!(get_anim_bone_data(&chunked, false), &[1, 1, 1, 1]);
+        assert_eq!(get_anim_bone_data(&chunked, true), &[3, 3, 3, 3]);
+
+        // either kind of bone falls back to the other chunk if its own is missing
+        let m2_only = chunk(b"AFM2", &[1, 1, 1, 1]);
+        assert_eq!(get_anim_bone_data(&m2_only, true), &[1, 1, 1, 1]);
+        let skeleton_only = chunk(b"AFSB", &[3, 3, 3, 3]);
+        assert_eq!(get_anim_bone_data(&skeleton_only, false), &[3, 3, 3, 3]);
+        assert!(get_anim_bone_data(&chunk(b"AFSA", &[2, 2, 2, 2]), false).is_empty());
+    }
+
+    #[test]
+    fn test_allocate_external_before_inline() {
+        let mut track = M2Track::<u32> {
+            interpolation_type: 0,
+            global_sequence: -1,
+            timestamps_unallocated: WowArray { count: 0, offset: 0, element_type: PhantomData },
+            timestamps: None,
+            values_unallocated: WowArray { count: 0, offset: 0, element_type: PhantomData },
+            values: None,
+            sequence_timestamps_unallocated: None,
+            sequence_values_unallocated: None,
+        };
+        assert!(track.allocate_external(0, &[]).is_err());
+        track.allocate_inline(&[], &[]).unwrap();
+        assert!(track.allocate_external(0, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_billboards() {
+        let camera_position = glm::vec3(5.0, 5.0, 10.0);
+
+        let mut spherical = Mat4::identity();
+        Billboard::Spherical.apply(&mut spherical, &camera_position);
+        let forward = glm::vec3(spherical[0], spherical[1], spherical[2]);
+        assert!((forward - camera_position.normalize()).magnitude() < 1e-5);
+
+        let mut cylindrical = Mat4::identity();
+        Billboard::CylindricalLockZ.apply(&mut cylindrical, &camera_position);
+        let forward = glm::vec3(cylindrical[0], cylindrical[1], cylindrical[2]);
+        let up = glm::vec3(cylindrical[8], cylindrical[9], cylindrical[10]);
+        assert!((forward - glm::vec3(1.0, 1.0, 0.0).normalize()).magnitude() < 1e-5);
+        assert!((up - glm::vec3(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+
+        // looking straight down the locked axis leaves the matrix untouched
+        let mut degenerate = Mat4::identity();
+        Billboard::CylindricalLockZ.apply(&mut degenerate, &glm::vec3(0.0, 0.0, 10.0));
+        assert_eq!(degenerate, Mat4::identity());
+    }
+}
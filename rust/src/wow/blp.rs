@@ -1,10 +1,16 @@
+use std::collections::HashMap;
+
 use deku::prelude::*;
 use wasm_bindgen::prelude::*;
 
+use crate::util;
+
 #[wasm_bindgen(js_name = "WowColorEncoding")]
-#[derive(Debug, DekuRead, Copy, Clone)]
+#[derive(Debug, DekuRead, Copy, Clone, PartialEq)]
 #[deku(id_type = "u8")]
 pub enum ColorEncoding {
+    #[deku(id = "0")]
+    Jpeg, // only used by BLP1
     #[deku(id = "1")]
     Uncompressed,
     #[deku(id = "2")]
@@ -14,7 +20,7 @@ pub enum ColorEncoding {
 }
 
 #[wasm_bindgen(js_name = "WowPixelFormat")]
-#[derive(Debug, DekuRead, Copy, Clone)]
+#[derive(Debug, DekuRead, Copy, Clone, PartialEq)]
 #[deku(id_type = "u8")]
 pub enum PixelFormat {
     #[deku(id = "0")]
@@ -57,10 +63,32 @@ pub struct BlpHeader {
     palette: [u32; 256], // BGRA values
 }
 
+// the classic-era format, which is either palettized or a set of JPEGs
+// sharing a common header
+#[derive(Debug, DekuRead)]
+#[deku(magic = b"BLP1")]
+struct Blp1Header {
+    compression: u32, // 0 for JPEG, 1 for palettized
+    alpha_bits: u32,
+    width: u32,
+    height: u32,
+    _extra: u32,
+    has_mips: u32,
+    mip_offsets: [u32; 16],
+    mip_sizes: [u32; 16],
+    #[deku(cond = "*compression == 0")]
+    _jpeg_header_size: Option<u32>,
+    #[deku(count = "_jpeg_header_size.unwrap_or(0)")]
+    jpeg_header: Vec<u8>,
+    #[deku(cond = "*compression == 1")]
+    palette: Option<[u32; 256]>,
+}
+
 #[wasm_bindgen(js_name = "WowBlp")]
 #[derive(Debug)]
 pub struct Blp {
-    texture_data: Vec<u8>,
+    data: Vec<u8>,
+    jpeg_header: Vec<u8>,
     pub header: BlpHeader,
 }
 
@@ -70,63 +98,943 @@ pub struct BlpMipMetadata {
     pub size: u32,
 }
 
+// how the encoder builds the mip chain
+#[wasm_bindgen(js_name = "WowBlpMipFilter")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MipFilter {
+    None, // only write the top level
+    Box, // average each 2x2 block
+    Kaiser, // Kaiser window over the nearest 4x4 texels, softer than box
+}
+
 #[wasm_bindgen(js_class = "WowBlp")]
 impl Blp {
     pub fn new(data: &[u8]) -> Result<Blp, String> {
+        if data.starts_with(b"BLP1") {
+            return Blp::new_blp1(data);
+        }
         let (_, header) = BlpHeader::from_bytes((data, 0))
             .map_err(|e| format!("{:?}", e))?;
 
         Ok(Blp {
-            texture_data: data[1172..].to_vec(),
+            data: data.to_vec(),
+            jpeg_header: Vec::new(),
             header,
         })
     }
 
-    pub fn get_texture_data(&self, start: usize, size: usize) -> Result<Vec<u8>, String> {
-        let data = &self.texture_data[start..start+size];
+    // DXT compressed mips are returned as-is, everything else as RGBA
+    pub fn get_mip_data(&self, mip_level: usize) -> Result<Vec<u8>, String> {
+        self.decode_mip(mip_level, false)
+    }
+
+    // always decodes to RGBA, including DXT compressed mips
+    pub fn get_mip_rgba(&self, mip_level: usize) -> Result<Vec<u8>, String> {
+        self.decode_mip(mip_level, true)
+    }
+
+    pub fn get_mip_width(&self, mip_level: usize) -> u32 {
+        (self.header.width >> mip_level).max(1)
+    }
+
+    pub fn get_mip_height(&self, mip_level: usize) -> u32 {
+        (self.header.height >> mip_level).max(1)
+    }
+
+    // encodes RGBA pixels as a BLP2 file. Uncompressed textures are
+    // palettized, with an `alpha_bit_depth` of 0, 1, 4 or 8 picking the alpha
+    // plane. DXT textures pick DXT1/3/5 via `format`, where DXT1 uses
+    // punch-through alpha if `alpha_bit_depth` is nonzero
+    pub fn encode(pixels: &[u8], width: u32, height: u32, encoding: ColorEncoding, format: PixelFormat, alpha_bit_depth: u8, mip_filter: MipFilter) -> Result<Vec<u8>, String> {
+        if width == 0 || height == 0 || pixels.len() != width as usize * height as usize * 4 {
+            return Err(format!("expected {}x{} RGBA pixels, got {} bytes", width, height, pixels.len()));
+        }
+        match (encoding, format) {
+            (ColorEncoding::Uncompressed, _) if [0, 1, 4, 8].contains(&alpha_bit_depth) => {},
+            (ColorEncoding::Dxtc, PixelFormat::Dxt1) | (ColorEncoding::Dxtc, PixelFormat::Dxt3) | (ColorEncoding::Dxtc, PixelFormat::Dxt5) => {},
+            (ColorEncoding::A8R8G8B8, PixelFormat::Argb8888) => {},
+            _ => return Err(format!("can't encode {:?} {:?} with {} bit alpha", encoding, format, alpha_bit_depth)),
+        }
+
+        let mut mips = vec![pixels.to_vec()];
+        let (mut w, mut h) = (width as usize, height as usize);
+        while mip_filter != MipFilter::None && (w > 1 || h > 1) && mips.len() < 16 {
+            let mip = downsample(mips.last().unwrap(), w, h, mip_filter);
+            mips.push(mip);
+            w = (w / 2).max(1);
+            h = (h / 2).max(1);
+        }
+
+        let palette = match encoding {
+            ColorEncoding::Uncompressed => build_palette(pixels),
+            _ => Vec::new(),
+        };
+        let mut palette_lookup = HashMap::new();
+        let mut payloads = Vec::with_capacity(mips.len());
+        for (level, mip) in mips.iter().enumerate() {
+            let w = (width as usize >> level).max(1);
+            let h = (height as usize >> level).max(1);
+            payloads.push(match encoding {
+                ColorEncoding::Uncompressed => encode_palettized(mip, &palette, &mut palette_lookup, alpha_bit_depth),
+                ColorEncoding::Dxtc => encode_dxt_blocks(mip, w, h, format, alpha_bit_depth > 0),
+                _ => mip.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]).collect(),
+            });
+        }
+
+        let encoding_id = match encoding {
+            ColorEncoding::Uncompressed => 1,
+            ColorEncoding::Dxtc => 2,
+            _ => 3,
+        };
+        let mut data = b"BLP2".to_vec();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&[encoding_id, alpha_bit_depth, pixel_format_id(format), (mips.len() > 1) as u8]);
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        let mut offsets = [0u32; 16];
+        let mut sizes = [0u32; 16];
+        let mut offset = 1172;
+        for (i, payload) in payloads.iter().enumerate() {
+            offsets[i] = offset as u32;
+            sizes[i] = payload.len() as u32;
+            offset += payload.len();
+        }
+        offsets.iter().chain(sizes.iter()).for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        for i in 0..256 {
+            let [r, g, b] = palette.get(i).copied().unwrap_or([0; 3]);
+            data.extend_from_slice(&[b, g, r, 0]);
+        }
+        payloads.iter().for_each(|payload| data.extend_from_slice(payload));
+        Ok(data)
+    }
+
+    pub fn get_num_mips(&self) -> usize {
+        for i in 0..16 {
+            if self.header.mip_offsets[i] == 0 || self.header.mip_sizes[i] == 0 {
+                return i;
+            }
+        }
+        16
+    }
+}
+
+// rust-only interface
+impl Blp {
+    // decodes a mip level to a standalone RGBA PNG, for native tools and
+    // exporters
+    pub fn get_mip_png(&self, mip_level: usize) -> Result<Vec<u8>, String> {
+        let rgba = self.get_mip_rgba(mip_level)?;
+        let mut result = Vec::new();
+        let mut encoder = png::Encoder::new(&mut result, self.get_mip_width(mip_level), self.get_mip_height(mip_level));
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&rgba))
+            .map_err(|e| format!("failed to encode PNG: {}", e))?;
+        Ok(result)
+    }
+}
+
+impl Blp {
+    fn new_blp1(data: &[u8]) -> Result<Blp, String> {
+        let (_, blp1) = Blp1Header::from_bytes((data, 0))
+            .map_err(|e| format!("{:?}", e))?;
+        let color_encoding = match blp1.compression {
+            0 => ColorEncoding::Jpeg,
+            1 => ColorEncoding::Uncompressed,
+            compression => return Err(format!("unknown BLP1 compression {}", compression)),
+        };
+        Ok(Blp {
+            data: data.to_vec(),
+            jpeg_header: blp1.jpeg_header,
+            header: BlpHeader {
+                _version: 1,
+                color_encoding,
+                alpha_bit_depth: blp1.alpha_bits as u8,
+                preferred_format: PixelFormat::Unspecified,
+                has_mips: blp1.has_mips as u8,
+                width: blp1.width,
+                height: blp1.height,
+                mip_offsets: blp1.mip_offsets,
+                mip_sizes: blp1.mip_sizes,
+                palette: blp1.palette.unwrap_or([0; 256]),
+            },
+        })
+    }
+
+    fn decode_mip(&self, mip_level: usize, decode_dxt: bool) -> Result<Vec<u8>, String> {
+        if mip_level >= self.header.mip_offsets.len() {
+            return Err("invalid mip level".to_string());
+        }
+        let w = self.get_mip_width(mip_level) as usize;
+        let h = self.get_mip_height(mip_level) as usize;
+        let offset = self.header.mip_offsets[mip_level] as usize;
+        let size = match (self.header.color_encoding, self.header.preferred_format) {
+            (ColorEncoding::Dxtc, PixelFormat::Dxt5) | (ColorEncoding::Dxtc, PixelFormat::Dxt3) => {
+                w.div_ceil(4) * h.div_ceil(4) * 16
+            },
+            (ColorEncoding::Dxtc, PixelFormat::Dxt1) => {
+                w.div_ceil(4) * h.div_ceil(4) * 8
+            },
+            _ => self.header.mip_sizes[mip_level] as usize,
+        };
+        let data = self.data.get(offset..offset + size)
+            .ok_or(format!("mip {} is out of bounds", mip_level))?;
         match (self.header.color_encoding, self.header.preferred_format) {
-            (ColorEncoding::Uncompressed, _) => {
-                let mut result = Vec::with_capacity(self.texture_data.len() * 4);
-                for &idx in data.iter() {
-                    let pixel: u32 = self.header.palette[idx as usize];
-                    let [b, g, r, a] = pixel.to_le_bytes();
-                    result.push(r);
-                    result.push(g);
-                    result.push(b);
-                    result.push(a);
+            (ColorEncoding::Uncompressed, _) => self.decode_palettized(data, w, h),
+            (ColorEncoding::Dxtc, _) if !decode_dxt => Ok(data.to_vec()),
+            (ColorEncoding::Dxtc, format) => decode_dxt_blocks(data, w, h, format, self.header.alpha_bit_depth > 0),
+            (ColorEncoding::A8R8G8B8, format) => decode_raw(data, w, h, format),
+            (ColorEncoding::Jpeg, _) => self.decode_jpeg(data, w, h),
+        }
+    }
+
+    // palette indices are followed by a separate plane of 1, 4 or 8 bit
+    // alpha values
+    fn decode_palettized(&self, data: &[u8], w: usize, h: usize) -> Result<Vec<u8>, String> {
+        let pixel_count = w * h;
+        let alpha_bits = self.header.alpha_bit_depth as usize;
+        if ![0, 1, 4, 8].contains(&alpha_bits) {
+            return Err(format!("unsupported alpha bit depth {}", alpha_bits));
+        }
+        let alpha_size = (pixel_count * alpha_bits).div_ceil(8);
+        if data.len() < pixel_count + alpha_size {
+            return Err(format!("expected {} bytes of palettized data, got {}", pixel_count + alpha_size, data.len()));
+        }
+        let (indices, alpha) = data.split_at(pixel_count);
+        let mut result = Vec::with_capacity(pixel_count * 4);
+        for (i, &idx) in indices.iter().enumerate() {
+            let pixel: u32 = self.header.palette[idx as usize];
+            let [b, g, r, _] = pixel.to_le_bytes();
+            let a = match alpha_bits {
+                1 => ((alpha[i / 8] >> (i % 8)) & 1) * 255,
+                4 => ((alpha[i / 2] >> ((i % 2) * 4)) & 0xf) * 17,
+                8 => alpha[i],
+                _ => 255,
+            };
+            result.extend_from_slice(&[r, g, b, a]);
+        }
+        Ok(result)
+    }
+
+    fn decode_jpeg(&self, data: &[u8], w: usize, h: usize) -> Result<Vec<u8>, String> {
+        let mut jpeg = Vec::with_capacity(self.jpeg_header.len() + data.len());
+        jpeg.extend_from_slice(&self.jpeg_header);
+        jpeg.extend_from_slice(data);
+        let mut decoder = jpeg_decoder::Decoder::new(jpeg.as_slice());
+        // BLP1 JPEGs store BGRA directly in their four components
+        decoder.set_color_transform(jpeg_decoder::ColorTransform::None);
+        let pixels = decoder.decode().map_err(|e| format!("{:?}", e))?;
+        if pixels.len() != w * h * 4 {
+            return Err(format!("expected a {}x{} 4 component JPEG, got {} bytes", w, h, pixels.len()));
+        }
+        // without a color transform, jpeg-decoder writes each row's
+        // components one after another rather than interleaved
+        let has_alpha = self.header.alpha_bit_depth > 0;
+        let mut result = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks_exact(w * 4) {
+            let (b, g, r, a) = (&row[..w], &row[w..w * 2], &row[w * 2..w * 3], &row[w * 3..]);
+            for x in 0..w {
+                result.extend_from_slice(&[r[x], g[x], b[x], if has_alpha { a[x] } else { 255 }]);
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn expand_bits(v: u16, bits: u32) -> u8 {
+    let max = (1u32 << bits) - 1;
+    ((v as u32 * 255 + max / 2) / max) as u8
+}
+
+fn rgb565_to_rgba(v: u16) -> [u8; 4] {
+    [expand_bits(v >> 11, 5), expand_bits((v >> 5) & 0x3f, 6), expand_bits(v & 0x1f, 5), 255]
+}
+
+fn decode_raw(data: &[u8], w: usize, h: usize, format: PixelFormat) -> Result<Vec<u8>, String> {
+    let pixel_count = w * h;
+    let bytes_per_pixel = match format {
+        PixelFormat::Argb8888 | PixelFormat::Unspecified => 4,
+        PixelFormat::Argb1555 | PixelFormat::Argb4444 | PixelFormat::Rgb565 => 2,
+        PixelFormat::A8 => 1,
+        format => return Err(format!("unsupported uncompressed pixel format {:?}", format)),
+    };
+    if data.len() < pixel_count * bytes_per_pixel {
+        return Err(format!("expected {} bytes of {:?} data, got {}", pixel_count * bytes_per_pixel, format, data.len()));
+    }
+    let mut result = Vec::with_capacity(pixel_count * 4);
+    for pixel in data.chunks_exact(bytes_per_pixel).take(pixel_count) {
+        let rgba = match format {
+            PixelFormat::A8 => [255, 255, 255, pixel[0]],
+            PixelFormat::Argb8888 | PixelFormat::Unspecified => [pixel[2], pixel[1], pixel[0], pixel[3]],
+            _ => {
+                let v = u16::from_le_bytes([pixel[0], pixel[1]]);
+                match format {
+                    PixelFormat::Argb1555 => [
+                        expand_bits((v >> 10) & 0x1f, 5),
+                        expand_bits((v >> 5) & 0x1f, 5),
+                        expand_bits(v & 0x1f, 5),
+                        if v & 0x8000 != 0 { 255 } else { 0 },
+                    ],
+                    PixelFormat::Argb4444 => [
+                        expand_bits((v >> 8) & 0xf, 4),
+                        expand_bits((v >> 4) & 0xf, 4),
+                        expand_bits(v & 0xf, 4),
+                        expand_bits(v >> 12, 4),
+                    ],
+                    _ => rgb565_to_rgba(v),
                 }
-                Ok(result)
             },
-            (ColorEncoding::Dxtc, _) => Ok(data.to_vec()),
-            x => Err(format!("unsupported texture format combination: {:?}", x)),
+        };
+        result.extend_from_slice(&rgba);
+    }
+    Ok(result)
+}
+
+// the 4 colors a DXT color block's endpoints expand to. DXT3/5 color blocks
+// always use 4 color mode
+fn color_block_palette(c0: u16, c1: u16, four_color_only: bool, punch_through_alpha: bool) -> [[u8; 4]; 4] {
+    let (rgb0, rgb1) = (rgb565_to_rgba(c0), rgb565_to_rgba(c1));
+    let mix = |a: u32, b: u32, wa: u32, wb: u32, total: u32| ((a * wa + b * wb) / total) as u8;
+    let mut colors = [rgb0, rgb1, [0; 4], [0; 4]];
+    if c0 > c1 || four_color_only {
+        for c in 0..3 {
+            colors[2][c] = mix(rgb0[c] as u32, rgb1[c] as u32, 2, 1, 3);
+            colors[3][c] = mix(rgb0[c] as u32, rgb1[c] as u32, 1, 2, 3);
+        }
+        colors[2][3] = 255;
+        colors[3][3] = 255;
+    } else {
+        for c in 0..3 {
+            colors[2][c] = mix(rgb0[c] as u32, rgb1[c] as u32, 1, 1, 2);
         }
+        colors[2][3] = 255;
+        colors[3][3] = if punch_through_alpha { 0 } else { 255 };
     }
+    colors
+}
 
-    pub fn get_mip_data(&self, mip_level: usize) -> Result<Vec<u8>, String> {
-        if mip_level > self.header.mip_offsets.len() {
-            return Err("invalid mip level".to_string());
+// decodes a DXT color block's 4x4 texels into `out`
+fn decode_color_block(block: &[u8], out: &mut [[u8; 4]; 16], four_color_only: bool, punch_through_alpha: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let colors = color_block_palette(c0, c1, four_color_only, punch_through_alpha);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, texel) in out.iter_mut().enumerate() {
+        *texel = colors[((indices >> (i * 2)) & 3) as usize];
+    }
+}
+
+// the 8 alpha values a DXT5 alpha block's endpoints expand to
+fn alpha_block_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a0, a1) = (a0 as u32, a1 as u32);
+    let mut alphas = [a0, a1, 0, 0, 0, 0, 0, 0];
+    if a0 > a1 {
+        for i in 1..7 {
+            alphas[i + 1] = (a0 * (7 - i as u32) + a1 * i as u32) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            alphas[i + 1] = (a0 * (5 - i as u32) + a1 * i as u32) / 5;
         }
-        let w = (self.header.width >> mip_level).max(1);
-        let h = (self.header.height >> mip_level).max(1);
-        let offset = self.header.mip_offsets[mip_level] as usize - 1172;
-        let size = match self.header.preferred_format {
-            PixelFormat::Dxt5 | PixelFormat::Dxt3 => {
-                ((w + 3) / 4) * ((h + 3) / 4) * 16
+        alphas[7] = 255;
+    }
+    alphas.map(|a| a as u8)
+}
+
+fn decode_dxt_blocks(data: &[u8], w: usize, h: usize, format: PixelFormat, has_alpha: bool) -> Result<Vec<u8>, String> {
+    let block_size = match format {
+        PixelFormat::Dxt1 => 8,
+        PixelFormat::Dxt3 | PixelFormat::Dxt5 => 16,
+        format => return Err(format!("unsupported DXT pixel format {:?}", format)),
+    };
+    let blocks_x = w.div_ceil(4);
+    let blocks_y = h.div_ceil(4);
+    if data.len() < blocks_x * blocks_y * block_size {
+        return Err(format!("expected {} bytes of {:?} data, got {}", blocks_x * blocks_y * block_size, format, data.len()));
+    }
+    let mut result = vec![0; w * h * 4];
+    let mut texels = [[0u8; 4]; 16];
+    for (block_index, block) in data.chunks_exact(block_size).take(blocks_x * blocks_y).enumerate() {
+        match format {
+            PixelFormat::Dxt1 => decode_color_block(block, &mut texels, false, has_alpha),
+            PixelFormat::Dxt3 => {
+                decode_color_block(&block[8..], &mut texels, true, false);
+                for (i, texel) in texels.iter_mut().enumerate() {
+                    texel[3] = ((block[i / 2] >> ((i % 2) * 4)) & 0xf) * 17;
+                }
             },
-            PixelFormat::Dxt1 => {
-                ((w + 3) / 4) * ((h + 3) / 4) * 8
+            _ => {
+                decode_color_block(&block[8..], &mut texels, true, false);
+                let alphas = alpha_block_palette(block[0], block[1]);
+                let mut index_bits = [0u8; 8];
+                index_bits[..6].copy_from_slice(&block[2..8]);
+                let indices = u64::from_le_bytes(index_bits);
+                for (i, texel) in texels.iter_mut().enumerate() {
+                    texel[3] = alphas[((indices >> (i * 3)) & 7) as usize];
+                }
             },
-            _ => self.header.mip_sizes[mip_level],
+        }
+        let (bx, by) = (block_index % blocks_x * 4, block_index / blocks_x * 4);
+        for (i, texel) in texels.iter().enumerate() {
+            let (x, y) = (bx + i % 4, by + i / 4);
+            if x < w && y < h {
+                result[(y * w + x) * 4..(y * w + x) * 4 + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn pixel_format_id(format: PixelFormat) -> u8 {
+    match format {
+        PixelFormat::Dxt1 => 0,
+        PixelFormat::Dxt3 => 1,
+        PixelFormat::Argb8888 => 2,
+        PixelFormat::Argb1555 => 3,
+        PixelFormat::Argb4444 => 4,
+        PixelFormat::Rgb565 => 5,
+        PixelFormat::A8 => 6,
+        PixelFormat::Dxt5 => 7,
+        PixelFormat::Unspecified => 8,
+        PixelFormat::Argb2565 => 9,
+        PixelFormat::Pc511 => 11,
+    }
+}
+
+// modified Bessel function of the first kind, for the Kaiser window
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..20 {
+        term *= (x / (2.0 * k as f32)).powi(2);
+        sum += term;
+    }
+    sum
+}
+
+// integer weights of the inner and outer taps of a 4 tap Kaiser window,
+// which sit 0.5 and 1.5 texels from the center of each destination texel
+fn kaiser_weights() -> (u32, u32) {
+    let beta = 4.0;
+    let window = |x: f32| bessel_i0(beta * (1.0 - (x / 2.0).powi(2)).sqrt()) / bessel_i0(beta);
+    ((window(0.5) * 1000.0) as u32, (window(1.5) * 1000.0) as u32)
+}
+
+// halves the texture along one axis. Colors are blended in linear space,
+// alpha as-is
+fn downsample_axis(src: &[u8], w: usize, h: usize, horizontal: bool, filter: MipFilter) -> Vec<u8> {
+    let (dst_w, dst_h) = if horizontal { (w / 2, h) } else { (w, h / 2) };
+    let (inner_weight, outer_weight) = kaiser_weights();
+    let texel = |x: usize, y: usize, c: usize| src[(y * w + x) * 4 + c];
+    let mut result = Vec::with_capacity(dst_w * dst_h * 4);
+    for y in 0..dst_h {
+        for x in 0..dst_w {
+            let i = if horizontal { x } else { y };
+            let n = if horizontal { w } else { h };
+            let taps = [i * 2, i * 2 + 1, (i * 2).saturating_sub(1), (i * 2 + 2).min(n - 1)];
+            let taps = taps.map(|t| if horizontal { (t, y) } else { (x, t) });
+            for c in 0..4 {
+                let blend = if c == 3 { util::blend_linear_u8 } else { util::blend_srgb_u8 };
+                let [p0, p1, outer0, outer1] = taps.map(|(tx, ty)| texel(tx, ty, c));
+                let inner = blend(p0, p1, 1, 1);
+                result.push(match filter {
+                    MipFilter::Kaiser => blend(inner, blend(outer0, outer1, 1, 1), inner_weight, outer_weight),
+                    _ => inner,
+                });
+            }
+        }
+    }
+    result
+}
+
+fn downsample(src: &[u8], w: usize, h: usize, filter: MipFilter) -> Vec<u8> {
+    let mut result = src.to_vec();
+    if w > 1 {
+        result = downsample_axis(&result, w, h, true, filter);
+    }
+    if h > 1 {
+        result = downsample_axis(&result, (w / 2).max(1), h, false, filter);
+    }
+    result
+}
+
+// median cut quantization down to at most 256 colors, weighted by how often
+// each color is used
+fn build_palette(pixels: &[u8]) -> Vec<[u8; 3]> {
+    let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+    for p in pixels.chunks_exact(4) {
+        *counts.entry([p[0], p[1], p[2]]).or_insert(0) += 1;
+    }
+    let mut colors: Vec<([u8; 3], u32)> = counts.into_iter().collect();
+    colors.sort();
+    if colors.len() <= 256 {
+        return colors.into_iter().map(|(color, _)| color).collect();
+    }
+
+    let channel_range = |colors: &[([u8; 3], u32)], c: usize| {
+        let min = colors.iter().map(|(color, _)| color[c]).min().unwrap();
+        let max = colors.iter().map(|(color, _)| color[c]).max().unwrap();
+        max - min
+    };
+    let mut boxes = vec![colors];
+    while boxes.len() < 256 {
+        let widest = boxes.iter().enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| (i, (0..3).map(|c| channel_range(colors, c)).max().unwrap()))
+            .max_by_key(|&(_, range)| range);
+        let i = match widest {
+            Some((i, _)) => i,
+            None => break,
         };
-        self.get_texture_data(offset, size as usize)
+        let mut colors = boxes.swap_remove(i);
+        let c = (0..3).max_by_key(|&c| channel_range(&colors, c)).unwrap();
+        colors.sort_by_key(|(color, _)| color[c]);
+        let total: u32 = colors.iter().map(|(_, count)| count).sum();
+        let mut seen = 0;
+        let mut split = 1;
+        for (j, (_, count)) in colors.iter().enumerate().take(colors.len() - 1) {
+            seen += count;
+            split = j + 1;
+            if seen * 2 >= total {
+                break;
+            }
+        }
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
     }
+    boxes.iter().map(|colors| {
+        let total: u64 = colors.iter().map(|&(_, count)| count as u64).sum();
+        let mut average = [0; 3];
+        for (c, value) in average.iter_mut().enumerate() {
+            let sum: u64 = colors.iter().map(|&(color, count)| color[c] as u64 * count as u64).sum();
+            *value = ((sum + total / 2) / total) as u8;
+        }
+        average
+    }).collect()
+}
 
-    pub fn get_num_mips(&self) -> usize {
-        for i in 0..16 {
-            if self.header.mip_offsets[i] == 0 || self.header.mip_sizes[i] == 0 {
-                return i;
+fn color_distance(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b.iter()).take(3).map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32).sum()
+}
+
+// palette indices followed by the alpha plane, the inverse of `decode_palettized`
+fn encode_palettized(pixels: &[u8], palette: &[[u8; 3]], lookup: &mut HashMap<[u8; 3], u8>, alpha_bits: u8) -> Vec<u8> {
+    let pixel_count = pixels.len() / 4;
+    let mut result = Vec::with_capacity(pixel_count * 2);
+    for p in pixels.chunks_exact(4) {
+        let color = [p[0], p[1], p[2]];
+        let index = *lookup.entry(color).or_insert_with(|| {
+            (0..palette.len()).min_by_key(|&i| color_distance(&color, &palette[i])).unwrap() as u8
+        });
+        result.push(index);
+    }
+    let alpha_start = result.len();
+    result.resize(alpha_start + (pixel_count * alpha_bits as usize).div_ceil(8), 0);
+    let alpha_plane = &mut result[alpha_start..];
+    for (i, p) in pixels.chunks_exact(4).enumerate() {
+        let a = p[3];
+        match alpha_bits {
+            1 => alpha_plane[i / 8] |= ((a >= 128) as u8) << (i % 8),
+            4 => alpha_plane[i / 2] |= (((a as u32 * 15 + 127) / 255) as u8) << ((i % 2) * 4),
+            8 => alpha_plane[i] = a,
+            _ => {},
+        }
+    }
+    result
+}
+
+fn rgb_to_565(rgb: &[u8]) -> u16 {
+    let quantize = |v: u8, bits: u32| ((v as u32 * ((1 << bits) - 1) + 127) / 255) as u16;
+    (quantize(rgb[0], 5) << 11) | (quantize(rgb[1], 6) << 5) | quantize(rgb[2], 5)
+}
+
+// picks endpoints from the texels at either end of the block's principal
+// axis, then the closest palette color for each texel
+fn encode_color_block(texels: &[[u8; 4]; 16], four_color_only: bool, punch_through_alpha: bool) -> [u8; 8] {
+    let is_transparent = |texel: &[u8; 4]| punch_through_alpha && texel[3] < 128;
+    let opaque: Vec<[f32; 3]> = texels.iter()
+        .filter(|texel| !is_transparent(texel))
+        .map(|texel| [texel[0] as f32, texel[1] as f32, texel[2] as f32])
+        .collect();
+    let mut block = [0; 8];
+    if opaque.is_empty() {
+        // 3 color mode with every texel transparent
+        block[4..].copy_from_slice(&[0xff; 4]);
+        return block;
+    }
+
+    let mut mean = [0.0; 3];
+    for texel in &opaque {
+        for c in 0..3 {
+            mean[c] += texel[c] / opaque.len() as f32;
+        }
+    }
+    let mut covariance = [[0.0f32; 3]; 3];
+    for texel in &opaque {
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value += (texel[i] - mean[i]) * (texel[j] - mean[j]);
             }
         }
-        16
+    }
+    let mut axis = [1.0f32; 3];
+    for _ in 0..8 {
+        let next: Vec<f32> = covariance.iter()
+            .map(|row| row.iter().zip(axis.iter()).map(|(a, b)| a * b).sum())
+            .collect();
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        for c in 0..3 {
+            axis[c] = next[c] / length;
+        }
+    }
+    let project = |texel: &[f32; 3]| (0..3).map(|c| (texel[c] - mean[c]) * axis[c]).sum::<f32>();
+    let min = opaque.iter().min_by(|a, b| project(a).partial_cmp(&project(b)).unwrap()).unwrap();
+    let max = opaque.iter().max_by(|a, b| project(a).partial_cmp(&project(b)).unwrap()).unwrap();
+    let to_565 = |texel: &[f32; 3]| rgb_to_565(&[texel[0] as u8, texel[1] as u8, texel[2] as u8]);
+    let (mut c0, mut c1) = (to_565(max), to_565(min));
+
+    // c0 > c1 selects 4 color mode, otherwise index 3 is transparent
+    let three_color = punch_through_alpha && opaque.len() < 16;
+    if (c0 < c1) != three_color && c0 != c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    let colors = color_block_palette(c0, c1, four_color_only, punch_through_alpha);
+    let candidates = if c0 > c1 || four_color_only { 4 } else { 3 };
+    let mut indices = 0u32;
+    for (i, texel) in texels.iter().enumerate() {
+        let index = if is_transparent(texel) {
+            3
+        } else {
+            (0..candidates).min_by_key(|&j| color_distance(texel, &colors[j])).unwrap()
+        };
+        indices |= (index as u32) << (i * 2);
+    }
+    block[0..2].copy_from_slice(&c0.to_le_bytes());
+    block[2..4].copy_from_slice(&c1.to_le_bytes());
+    block[4..8].copy_from_slice(&indices.to_le_bytes());
+    block
+}
+
+fn encode_alpha_block(texels: &[[u8; 4]; 16]) -> [u8; 8] {
+    let a0 = texels.iter().map(|texel| texel[3]).max().unwrap();
+    let a1 = texels.iter().map(|texel| texel[3]).min().unwrap();
+    let alphas = alpha_block_palette(a0, a1);
+    let mut indices = 0u64;
+    for (i, texel) in texels.iter().enumerate() {
+        let index = (0..8).min_by_key(|&j| (alphas[j] as i32 - texel[3] as i32).abs()).unwrap();
+        indices |= (index as u64) << (i * 3);
+    }
+    let mut block = [0; 8];
+    block[0] = a0;
+    block[1] = a1;
+    block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    block
+}
+
+fn encode_dxt_blocks(pixels: &[u8], w: usize, h: usize, format: PixelFormat, has_alpha: bool) -> Vec<u8> {
+    let blocks_x = w.div_ceil(4);
+    let blocks_y = h.div_ceil(4);
+    let mut result = Vec::new();
+    let mut texels = [[0u8; 4]; 16];
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            // edge blocks repeat the last row and column
+            for (i, texel) in texels.iter_mut().enumerate() {
+                let x = (bx * 4 + i % 4).min(w - 1);
+                let y = (by * 4 + i / 4).min(h - 1);
+                texel.copy_from_slice(&pixels[(y * w + x) * 4..(y * w + x) * 4 + 4]);
+            }
+            match format {
+                PixelFormat::Dxt1 => result.extend_from_slice(&encode_color_block(&texels, false, has_alpha)),
+                PixelFormat::Dxt3 => {
+                    let mut alpha = [0u8; 8];
+                    for (i, texel) in texels.iter().enumerate() {
+                        alpha[i / 2] |= (((texel[3] as u32 * 15 + 127) / 255) as u8) << ((i % 2) * 4);
+                    }
+                    result.extend_from_slice(&alpha);
+                    result.extend_from_slice(&encode_color_block(&texels, true, false));
+                },
+                _ => {
+                    result.extend_from_slice(&encode_alpha_block(&texels));
+                    result.extend_from_slice(&encode_color_block(&texels, true, false));
+                },
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a single mip BLP2 with the given encoding and mip data
+    fn build_blp2(encoding: u8, alpha_bit_depth: u8, format: u8, w: u32, h: u32, palette: &[u32], mip: &[u8]) -> Vec<u8> {
+        let mut data = b"BLP2".to_vec();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&[encoding, alpha_bit_depth, format, 0]);
+        data.extend_from_slice(&w.to_le_bytes());
+        data.extend_from_slice(&h.to_le_bytes());
+        let mut offsets = [0u32; 16];
+        let mut sizes = [0u32; 16];
+        offsets[0] = 1172;
+        sizes[0] = mip.len() as u32;
+        offsets.iter().chain(sizes.iter()).for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        for i in 0..256 {
+            data.extend_from_slice(&palette.get(i).copied().unwrap_or(0).to_le_bytes());
+        }
+        assert_eq!(data.len(), 1172);
+        data.extend_from_slice(mip);
+        data
+    }
+
+    #[test]
+    fn test_palettized_alpha() {
+        let palette = [0xffff0000, 0x0000ff00]; // red with alpha, green without
+        let blp = Blp::new(&build_blp2(1, 1, 8, 4, 1, &palette, &[0, 1, 0, 1, 0b0101])).unwrap();
+        let pixels = blp.get_mip_data(0).unwrap();
+        assert_eq!(&pixels[..8], &[255, 0, 0, 255, 0, 255, 0, 0]);
+        let blp = Blp::new(&build_blp2(1, 4, 8, 2, 1, &palette, &[0, 1, 0x3f])).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![255, 0, 0, 255, 0, 255, 0, 51]);
+        let blp = Blp::new(&build_blp2(1, 0, 8, 1, 1, &palette, &[1])).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![0, 255, 0, 255]);
+        assert!(Blp::new(&build_blp2(1, 8, 8, 2, 1, &palette, &[0, 1])).unwrap().get_mip_data(0).is_err());
+    }
+
+    #[test]
+    fn test_raw_formats() {
+        let blp = Blp::new(&build_blp2(3, 8, 2, 1, 1, &[], &[1, 2, 3, 4])).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![3, 2, 1, 4]);
+        let blp = Blp::new(&build_blp2(3, 0, 5, 1, 1, &[], &0xf800u16.to_le_bytes())).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![255, 0, 0, 255]);
+        let blp = Blp::new(&build_blp2(3, 1, 3, 1, 1, &[], &0x83e0u16.to_le_bytes())).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![0, 255, 0, 255]);
+        let blp = Blp::new(&build_blp2(3, 4, 4, 1, 1, &[], &0x800fu16.to_le_bytes())).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![0, 0, 255, 136]);
+        let blp = Blp::new(&build_blp2(3, 8, 6, 1, 1, &[], &[7])).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![255, 255, 255, 7]);
+    }
+
+    #[test]
+    fn test_dxt() {
+        // red and blue endpoints, with texels cycling through all 4 indices
+        let mut block = Vec::new();
+        block.extend_from_slice(&0xf800u16.to_le_bytes());
+        block.extend_from_slice(&0x001fu16.to_le_bytes());
+        block.extend_from_slice(&0xe4e4e4e4u32.to_le_bytes());
+        let blp = Blp::new(&build_blp2(2, 0, 0, 4, 4, &[], &block)).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap(), block);
+        let pixels = blp.get_mip_rgba(0).unwrap();
+        assert_eq!(&pixels[..16], &[255, 0, 0, 255, 0, 0, 255, 255, 170, 0, 85, 255, 85, 0, 170, 255]);
+
+        // DXT5 with alpha interpolated between 255 and 0
+        let mut dxt5 = vec![255, 0, 0b001_000, 0, 0, 0, 0, 0];
+        dxt5.extend_from_slice(&block);
+        let blp = Blp::new(&build_blp2(2, 8, 7, 4, 4, &[], &dxt5)).unwrap();
+        let pixels = blp.get_mip_rgba(0).unwrap();
+        assert_eq!(pixels[3], 255);
+        assert_eq!(pixels[7], 0);
+        assert_eq!(pixels[11], 255);
+    }
+
+    #[test]
+    fn test_blp1_palettized() {
+        let mut data = b"BLP1".to_vec();
+        for v in [1u32, 8, 2, 1, 4, 0] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        let mut offsets = [0u32; 16];
+        let mut sizes = [0u32; 16];
+        offsets[0] = 156 + 1024;
+        sizes[0] = 4;
+        offsets.iter().chain(sizes.iter()).for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+        data.extend_from_slice(&0x00ff0000u32.to_le_bytes());
+        data.extend_from_slice(&[0; 255 * 4]);
+        data.extend_from_slice(&[0, 0, 128, 64]);
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.header.color_encoding, ColorEncoding::Uncompressed);
+        assert_eq!(blp.get_num_mips(), 1);
+        assert_eq!(blp.get_mip_data(0).unwrap(), vec![255, 0, 0, 128, 255, 0, 0, 64]);
+    }
+
+    // a baseline 8x8 JPEG whose 4 components are flat, so each block only
+    // needs a DC coefficient
+    fn build_flat_jpeg(components: [u8; 4]) -> Vec<u8> {
+        let segment = |marker: u8, body: &[u8]| {
+            let mut result = vec![0xff, marker];
+            result.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+            result.extend_from_slice(body);
+            result
+        };
+        let mut jpeg = vec![0xff, 0xd8];
+        let mut quantization = vec![0];
+        quantization.extend_from_slice(&[1; 64]);
+        jpeg.extend(segment(0xdb, &quantization));
+        let mut frame = vec![8, 0, 8, 0, 8, 4];
+        for id in 1..=4 {
+            frame.extend_from_slice(&[id, 0x11, 0]);
+        }
+        jpeg.extend(segment(0xc0, &frame));
+        // DC categories 0, 8 and 10 get 2 bit codes, and the only AC symbol
+        // is end of block
+        let mut dc_table = vec![0x00, 0, 3];
+        dc_table.extend_from_slice(&[0; 14]);
+        dc_table.extend_from_slice(&[0, 8, 10]);
+        jpeg.extend(segment(0xc4, &dc_table));
+        let mut ac_table = vec![0x10, 1];
+        ac_table.extend_from_slice(&[0; 15]);
+        ac_table.push(0);
+        jpeg.extend(segment(0xc4, &ac_table));
+        jpeg.extend(segment(0xda, &[4, 1, 0, 2, 0, 3, 0, 4, 0, 0, 63, 0]));
+
+        let mut bits: Vec<bool> = Vec::new();
+        let mut push_bits = |value: u32, count: u32| (0..count).rev().for_each(|i| bits.push(value >> i & 1 != 0));
+        for &component in &components {
+            let dc = (component as i32 - 128) * 8;
+            let (code, category) = match dc.unsigned_abs() {
+                0 => (0b00, 0),
+                1..=255 => (0b01, 8),
+                _ => (0b10, 10),
+            };
+            push_bits(code, 2);
+            let value = if dc < 0 { dc + (1 << category) - 1 } else { dc };
+            push_bits(value as u32, category);
+            push_bits(0, 1); // end of block
+        }
+        while bits.len() % 8 != 0 {
+            bits.push(true);
+        }
+        for byte in bits.chunks(8) {
+            let byte = byte.iter().fold(0u8, |acc, &bit| acc << 1 | bit as u8);
+            jpeg.push(byte);
+            if byte == 0xff {
+                jpeg.push(0);
+            }
+        }
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        jpeg
+    }
+
+    #[test]
+    fn test_blp1_jpeg() {
+        // BGRA, as BLP1 stores it
+        let jpeg = build_flat_jpeg([144, 112, 200, 64]);
+        // the mips share everything up to the scan
+        let scan_start = jpeg.windows(2).position(|marker| marker == [0xff, 0xda]).unwrap();
+        let (jpeg_header, mip) = jpeg.split_at(scan_start);
+        let build = |alpha_bits: u32| {
+            let mut data = b"BLP1".to_vec();
+            for v in [0u32, alpha_bits, 8, 8, 4, 0] {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+            let mut offsets = [0u32; 16];
+            let mut sizes = [0u32; 16];
+            offsets[0] = 160 + jpeg_header.len() as u32;
+            sizes[0] = mip.len() as u32;
+            offsets.iter().chain(sizes.iter()).for_each(|v| data.extend_from_slice(&v.to_le_bytes()));
+            data.extend_from_slice(&(jpeg_header.len() as u32).to_le_bytes());
+            data.extend_from_slice(jpeg_header);
+            data.extend_from_slice(mip);
+            Blp::new(&data).unwrap()
+        };
+
+        let blp = build(8);
+        assert_eq!(blp.header.color_encoding, ColorEncoding::Jpeg);
+        let pixels = blp.get_mip_data(0).unwrap();
+        assert_eq!(pixels.len(), 8 * 8 * 4);
+        for pixel in pixels.chunks_exact(4) {
+            assert_eq!(pixel, [200, 112, 144, 64]);
+        }
+        // without alpha bits, the fourth component is ignored
+        assert_eq!(&build(0).get_mip_data(0).unwrap()[..4], &[200, 112, 144, 255]);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // a 4 color 4x2 texture with varying alpha
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 0], [0, 0, 255, 255], [255, 255, 255, 255]];
+        let pixels: Vec<u8> = (0..8).flat_map(|i| colors[i % 4]).collect();
+
+        let data = Blp::encode(&pixels, 4, 2, ColorEncoding::Uncompressed, PixelFormat::Argb8888, 8, MipFilter::Box).unwrap();
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.header.color_encoding, ColorEncoding::Uncompressed);
+        assert_eq!(blp.get_num_mips(), 3);
+        assert_eq!(blp.get_mip_rgba(0).unwrap(), pixels);
+        assert_eq!(blp.get_mip_width(2), 1);
+        assert_eq!(blp.get_mip_rgba(2).unwrap().len(), 4);
+
+        let data = Blp::encode(&pixels, 4, 2, ColorEncoding::Uncompressed, PixelFormat::Argb8888, 1, MipFilter::None).unwrap();
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.get_num_mips(), 1);
+        assert_eq!(blp.get_mip_rgba(0).unwrap(), pixels);
+
+        let data = Blp::encode(&pixels, 4, 2, ColorEncoding::A8R8G8B8, PixelFormat::Argb8888, 8, MipFilter::Kaiser).unwrap();
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.get_mip_rgba(0).unwrap(), pixels);
+
+        assert!(Blp::encode(&pixels, 4, 4, ColorEncoding::A8R8G8B8, PixelFormat::Argb8888, 8, MipFilter::None).is_err());
+        assert!(Blp::encode(&pixels, 4, 2, ColorEncoding::Jpeg, PixelFormat::Unspecified, 8, MipFilter::None).is_err());
+    }
+
+    #[test]
+    fn test_encode_mips() {
+        // black and white columns average to linear 50% gray
+        let pixels: Vec<u8> = (0..4).flat_map(|i| if i % 2 == 0 { [0, 0, 0, 255] } else { [255, 255, 255, 255] }).collect();
+        let data = Blp::encode(&pixels, 2, 2, ColorEncoding::A8R8G8B8, PixelFormat::Argb8888, 8, MipFilter::Box).unwrap();
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.get_mip_rgba(1).unwrap(), vec![188, 188, 188, 255]);
+
+        // a uniform texture stays uniform under the Kaiser filter
+        let pixels: Vec<u8> = (0..64).flat_map(|_| [10, 100, 200, 50]).collect();
+        let data = Blp::encode(&pixels, 8, 8, ColorEncoding::A8R8G8B8, PixelFormat::Argb8888, 8, MipFilter::Kaiser).unwrap();
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.get_num_mips(), 4);
+        assert_eq!(blp.get_mip_rgba(3).unwrap(), vec![10, 100, 200, 50]);
+    }
+
+    #[test]
+    fn test_encode_dxt() {
+        // red fading to blue, with a transparent first column
+        let pixels: Vec<u8> = (0..16).flat_map(|i| {
+            let t = (i % 4) as u8 * 85;
+            [255 - t, 0, t, if i % 4 == 0 { 0 } else { 255 }]
+        }).collect();
+        let max_error = |a: &[u8], b: &[u8]| a.iter().zip(b.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+
+        let data = Blp::encode(&pixels, 4, 4, ColorEncoding::Dxtc, PixelFormat::Dxt5, 8, MipFilter::None).unwrap();
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.get_mip_data(0).unwrap().len(), 16);
+        assert!(max_error(&blp.get_mip_rgba(0).unwrap(), &pixels) <= 4);
+
+        let data = Blp::encode(&pixels, 4, 4, ColorEncoding::Dxtc, PixelFormat::Dxt3, 8, MipFilter::None).unwrap();
+        assert!(max_error(&Blp::new(&data).unwrap().get_mip_rgba(0).unwrap(), &pixels) <= 4);
+
+        // DXT1 keeps the transparent column as punch-through alpha, and the
+        // remaining 3 opaque colors fit in 3 color mode
+        let data = Blp::encode(&pixels, 4, 4, ColorEncoding::Dxtc, PixelFormat::Dxt1, 1, MipFilter::Box).unwrap();
+        let blp = Blp::new(&data).unwrap();
+        assert_eq!(blp.get_num_mips(), 3);
+        let decoded = blp.get_mip_rgba(0).unwrap();
+        for (i, (texel, expected)) in decoded.chunks_exact(4).zip(pixels.chunks_exact(4)).enumerate() {
+            assert_eq!(texel[3], expected[3]);
+            if i % 4 != 0 {
+                assert!(max_error(texel, expected) <= 4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_palette_quantization() {
+        // 1024 distinct colors have to be reduced to 256
+        let pixels: Vec<u8> = (0..1024u32).flat_map(|i| [(i % 32 * 8) as u8, (i / 32 * 8) as u8, 128, 255]).collect();
+        let data = Blp::encode(&pixels, 32, 32, ColorEncoding::Uncompressed, PixelFormat::Argb8888, 0, MipFilter::None).unwrap();
+        let decoded = Blp::new(&data).unwrap().get_mip_rgba(0).unwrap();
+        let max_error = decoded.iter().zip(pixels.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+        assert!(max_error <= 8);
     }
 }
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use nalgebra_glm::{self as glm, Mat4};
+use wasm_bindgen::prelude::*;
+
+use super::{
+    db::{CustomizationChoice, ItemDisplay},
+    m2::M2Attachment,
+    skin::Skin,
+};
+
+// character models contain every possible hairstyle, sleeve, boot, etc. as
+// separate skin submeshes, where a submesh ID of 1302 means value 2 of
+// geoset group 13. only one value per group is shown at a time
+#[wasm_bindgen(js_name = "WowGeosetOverride")]
+#[derive(Debug, Clone, Copy)]
+pub struct GeosetOverride {
+    pub group: u32,
+    pub value: u32,
+}
+
+#[wasm_bindgen(js_name = "WowGeosetSelection")]
+#[derive(Debug, Clone, Default)]
+pub struct GeosetSelection {
+    values: HashMap<u32, u32>,
+}
+
+#[wasm_bindgen(js_class = "WowGeosetSelection")]
+impl GeosetSelection {
+    pub fn new() -> GeosetSelection {
+        GeosetSelection::default()
+    }
+
+    pub fn set(&mut self, group: u32, value: u32) {
+        self.values.insert(group, value);
+    }
+
+    // value 1 is generally the "bare" variant (no sleeves, no boots, etc)
+    pub fn get(&self, group: u32) -> u32 {
+        self.values.get(&group).copied().unwrap_or(1)
+    }
+
+    pub fn is_visible(&self, submesh_id: u16) -> bool {
+        // submesh 0 is the base body, and is always visible
+        if submesh_id == 0 {
+            return true;
+        }
+        let group = submesh_id as u32 / 100;
+        let value = submesh_id as u32 % 100;
+        self.get(group) == value
+    }
+
+    pub fn apply_customization(&mut self, choice: &CustomizationChoice) {
+        for geoset in &choice.geosets {
+            self.set(geoset.group, geoset.value);
+        }
+    }
+
+    // see https://wowdev.wiki/DB/ItemDisplayInfo#Geosets for how each item
+    // slot's geoset groups are laid out
+    pub fn apply_item(&mut self, item: &ItemDisplay, inventory_type: u32) {
+        for (&group, &value) in item_geoset_groups(inventory_type).iter().zip(item.geoset_groups.iter()) {
+            if value != 0 {
+                self.set(group, value + 1);
+            }
+        }
+    }
+
+    // returns the indices of the skin's visible submeshes
+    pub fn get_visible_submeshes(&self, skin: &Skin) -> Vec<u32> {
+        skin.submeshes.iter().enumerate()
+            .filter(|(_, submesh)| self.is_visible(submesh.skin_submesh_id))
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+}
+
+fn item_geoset_groups(inventory_type: u32) -> &'static [u32] {
+    match inventory_type {
+        4 | 5 | 20 => &[8, 10, 13, 22], // shirt, chest, robe
+        6 => &[18], // waist
+        7 => &[11, 9, 13], // legs
+        8 => &[5, 20], // feet
+        10 => &[4, 23], // hands
+        16 => &[15], // cloak
+        19 => &[12], // tabard
+        _ => &[],
+    }
+}
+
+#[wasm_bindgen(js_class = "WowItemDisplay")]
+impl ItemDisplay {
+    // which M2 attachment points an item of the given inventory type is drawn
+    // at, in the same order as `model_file_ids`
+    pub fn get_attachment_ids(inventory_type: u32) -> Vec<u32> {
+        match inventory_type {
+            1 => vec![11], // head
+            3 => vec![6, 5], // shoulders (left, right)
+            13 | 15 | 17 | 21 | 26 => vec![1], // right hand
+            22 | 23 => vec![2], // left hand
+            14 => vec![0], // shield
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[wasm_bindgen(js_class = "WowM2Attachment")]
+impl M2Attachment {
+    // `bone_matrices` is the output of WowM2AnimationManager::get_bone_matrices.
+    // returns the matrix to draw an attached model with
+    pub fn get_matrix(&self, bone_matrices: &[f32]) -> Vec<f32> {
+        let bone_matrices: Vec<Mat4> = bone_matrices.chunks_exact(16)
+            .map(Mat4::from_column_slice)
+            .collect();
+        self.calculate_matrix(&bone_matrices).as_slice().to_vec()
+    }
+}
+
+// rust-only interface
+impl M2Attachment {
+    pub fn calculate_matrix(&self, bone_matrices: &[Mat4]) -> Mat4 {
+        let bone_matrix = bone_matrices.get(self.bone as usize)
+            .copied()
+            .unwrap_or_else(Mat4::identity);
+        let position = glm::vec3(self.position.x, self.position.y, self.position.z);
+        glm::translate(&bone_matrix, &position)
+    }
+}
+
+#[wasm_bindgen(js_name = "WowCompositeBlendMode")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompositeBlendMode {
+    Opaque,
+    AlphaBlend,
+    Multiply,
+}
+
+impl CompositeBlendMode {
+    // from ChrModelTextureLayer's BlendMode
+    pub fn from_layer_blend_mode(blend_mode: u32) -> CompositeBlendMode {
+        match blend_mode {
+            0 => CompositeBlendMode::Opaque,
+            4 => CompositeBlendMode::Multiply,
+            _ => CompositeBlendMode::AlphaBlend,
+        }
+    }
+}
+
+// builds a character's skin texture (and similar composited textures) out of
+// RGBA component textures, each drawn into some section of the final texture
+#[wasm_bindgen(js_name = "WowCharacterTextureCompositor")]
+#[derive(Debug, Clone)]
+pub struct TextureCompositor {
+    pub width: u32,
+    pub height: u32,
+    pixels: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen(js_class = "WowCharacterTextureCompositor")]
+impl TextureCompositor {
+    pub fn new(width: u32, height: u32) -> TextureCompositor {
+        TextureCompositor {
+            width,
+            height,
+            pixels: Some(vec![0; width as usize * height as usize * 4]),
+        }
+    }
+
+    // draws `src` (RGBA, `src_width` by `src_height`) scaled into the given
+    // rectangle, clipped to the bounds of the texture
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        src: &[u8],
+        src_width: u32,
+        src_height: u32,
+        blend_mode: CompositeBlendMode,
+    ) -> Result<(), String> {
+        if src.len() != src_width as usize * src_height as usize * 4 {
+            return Err(format!("expected {}x{} RGBA pixels, got {} bytes", src_width, src_height, src.len()));
+        }
+        if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+            return Ok(());
+        }
+        let pixels = self.pixels.as_mut().expect("compositor pixels already taken");
+        for dy in 0..height.min(self.height.saturating_sub(y)) {
+            let sy = (dy as u64 * src_height as u64 / height as u64) as usize;
+            for dx in 0..width.min(self.width.saturating_sub(x)) {
+                let sx = (dx as u64 * src_width as u64 / width as u64) as usize;
+                let src_index = (sy * src_width as usize + sx) * 4;
+                let dst_index = ((y + dy) as usize * self.width as usize + (x + dx) as usize) * 4;
+                let s = &src[src_index..src_index + 4];
+                let d = &mut pixels[dst_index..dst_index + 4];
+                match blend_mode {
+                    CompositeBlendMode::Opaque => d.copy_from_slice(s),
+                    CompositeBlendMode::AlphaBlend => {
+                        let a = s[3] as u32;
+                        for c in 0..3 {
+                            d[c] = ((s[c] as u32 * a + d[c] as u32 * (255 - a)) / 255) as u8;
+                        }
+                        d[3] = (a + d[3] as u32 * (255 - a) / 255) as u8;
+                    },
+                    CompositeBlendMode::Multiply => {
+                        for c in 0..3 {
+                            d[c] = (s[c] as u32 * d[c] as u32 / 255) as u8;
+                        }
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn take_pixels(&mut self) -> Vec<u8> {
+        self.pixels.take().expect("compositor pixels already taken")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geoset_selection() {
+        let mut selection = GeosetSelection::new();
+        assert!(selection.is_visible(0));
+        assert!(selection.is_visible(401));
+        assert!(!selection.is_visible(402));
+        selection.set(4, 3);
+        assert!(selection.is_visible(403));
+        assert!(!selection.is_visible(401));
+        // hairstyles share group 0 with the base body
+        selection.set(0, 5);
+        assert!(selection.is_visible(0));
+        assert!(selection.is_visible(5));
+        assert!(!selection.is_visible(1));
+    }
+
+    #[test]
+    fn test_texture_compositor() {
+        let mut compositor = TextureCompositor::new(4, 4);
+        let red = [255, 0, 0, 255];
+        compositor.draw(0, 0, 4, 4, &red, 1, 1, CompositeBlendMode::Opaque).unwrap();
+        let half_blue = [0, 0, 255, 128, 0, 0, 255, 0];
+        compositor.draw(2, 2, 4, 2, &half_blue, 2, 1, CompositeBlendMode::AlphaBlend).unwrap();
+        assert!(compositor.draw(0, 0, 1, 1, &red, 2, 2, CompositeBlendMode::Opaque).is_err());
+        let pixels = compositor.take_pixels();
+        let pixel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), &red);
+        assert_eq!(pixel(2, 2), &[127, 0, 128, 255]);
+        // the rectangle is clipped, so (3, 2) still samples the first texel
+        assert_eq!(pixel(3, 2), &[127, 0, 128, 255]);
+        assert_eq!(pixel(1, 3), &red);
+    }
+}
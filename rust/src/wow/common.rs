@@ -111,6 +111,15 @@ impl<'a> Iterator for ChunkedData<'a> {
     }
 }
 
+// builds a chunk the way ChunkedData expects to read it, for tests
+#[cfg(test)]
+pub(crate) fn chunk(magic: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut result = magic.to_vec();
+    result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    result.extend_from_slice(data);
+    result
+}
+
 pub type WowCharArray = WowArray<u8>;
 
 impl WowArray<u8> {
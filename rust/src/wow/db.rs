@@ -5,11 +5,30 @@ use nalgebra_glm::Vec2;
 use crate::geometry::{point_dist_to_polygon, point_inside_polygon};
 
 use super::common::*;
+use super::character::GeosetOverride;
+use super::m2::{M2_NUM_TEXTURE_TYPES, M2_TEXTURE_TYPE_MONSTER_SKIN_1, M2_TEXTURE_TYPE_MONSTER_SKIN_2, M2_TEXTURE_TYPE_MONSTER_SKIN_3};
 use wasm_bindgen::prelude::*;
 
+// WDC3, WDC4 and WDC5 share everything we read, except that WDC5 adds a
+// version and schema string to the start of the header
+#[derive(DekuRead, Debug, Clone, Copy, PartialEq)]
+#[deku(id_type = "u32")]
+pub enum Db2Format {
+    #[deku(id = "0x33434457")] // "WDC3"
+    Wdc3,
+    #[deku(id = "0x34434457")] // "WDC4"
+    Wdc4,
+    #[deku(id = "0x35434457")] // "WDC5"
+    Wdc5,
+}
+
 #[derive(DekuRead, Debug, Clone)]
-#[deku(magic = b"WDC4")]
 pub struct Wdc4Db2Header {
+    pub format: Db2Format,
+    #[deku(cond = "*format == Db2Format::Wdc5")]
+    pub version: Option<u32>,
+    #[deku(cond = "*format == Db2Format::Wdc5")]
+    pub _schema_string: Option<[u8; 128]>,
     pub record_count: u32,
     pub field_count: u32,
     pub record_size: u32,
@@ -172,6 +191,33 @@ fn read_field_to_u32<R: std::io::Read + std::io::Seek>(reader: &mut Reader<R>, f
 }
 
 impl Wdc4Db2File {
+    // the byte range of a section's records, plus its string table for
+    // non-sparse tables
+    fn section_data_range(&self, section_index: usize) -> (usize, usize) {
+        let section = &self.section_headers[section_index];
+        let start = section.file_offset as usize;
+        let end = if self.header.flags & 0x01 != 0 {
+            section.offset_records_end as usize // sparse table
+        } else {
+            start + section.record_count as usize * self.header.record_size as usize + section.string_table_size as usize
+        };
+        (start, end)
+    }
+
+    // whether a section's records can be read. encrypted sections are
+    // decrypted at the BLTE layer when the file is extracted from CASC, and
+    // zeroed out if the key was unknown at the time, so we skip those
+    pub fn is_section_readable(&self, data: &[u8], section_index: usize) -> bool {
+        if self.section_headers[section_index].tact_key_hash == 0 {
+            return true;
+        }
+        let (start, end) = self.section_data_range(section_index);
+        match data.get(start..end) {
+            Some(section_data) => section_data.iter().any(|&b| b != 0),
+            None => false,
+        }
+    }
+
     pub fn print_palettes(&self) {
         for field_index in 0..self.field_storage_info.len() {
             let info = &self.field_storage_info[field_index];
@@ -281,7 +327,7 @@ impl Wdc4Db2File {
         Ok(result)
     }
 
-    fn get_common_data(&self, field_number: usize, needle: u32) -> Option<u32> {
+    pub(crate) fn get_common_data(&self, field_number: usize, needle: u32) -> Option<u32> {
         let mut offset: usize = 0;
         for field_number_i in 0..field_number {
             match &self.field_storage_info[field_number_i].storage_type {
@@ -311,7 +357,7 @@ impl Wdc4Db2File {
         None
     }
 
-    fn get_palette_data(&self, field_number: usize, palette_index: usize) -> u32 {
+    pub(crate) fn get_palette_data(&self, field_number: usize, palette_index: usize) -> u32 {
         let mut offset = 0;
         for field_number_i in 0..field_number {
             match &self.field_storage_info[field_number_i].storage_type {
@@ -337,6 +383,7 @@ pub struct DatabaseTable<T> {
     ids: Vec<u32>,
     foreign_keys: Option<Vec<u32>>,
     copies: HashMap<u32, u32>,
+    id_indices: HashMap<u32, usize>,
 }
 
 impl<T> DatabaseTable<T> {
@@ -345,65 +392,78 @@ impl<T> DatabaseTable<T> {
     {
         let (_, db2) = Wdc4Db2File::from_bytes((&data, 0))
             .map_err(|e| format!("{:?}", e))?;
-        assert!(db2.section_headers.len() == 1);
         let mut records: Vec<T> = Vec::with_capacity(db2.header.record_count as usize);
         let mut ids: Vec<u32> = Vec::with_capacity(db2.header.record_count as usize);
-        let records_start = db2.section_headers[0].file_offset as usize;
+        let mut foreign_keys: Option<Vec<u32>> = None;
+        let mut copies = HashMap::new();
         let mut cursor = Cursor::new(&data);
         let mut reader = Reader::new(&mut cursor);
-
-        reader.seek(std::io::SeekFrom::Start(records_start as u64))
-            .map_err(|err| err.to_string())?;
         let mut id = db2.header.min_id;
-        for _ in 0..db2.header.record_count {
-            let value = T::from_reader_with_ctx(&mut reader, db2.clone())
-                .map_err(|e| format!("{:?}", e))?;
-            // our abuse of Deku in the database system always puts the cursor back where it started, so advance to the next record manually
-            reader.seek(std::io::SeekFrom::Current(db2.header.record_size as i64))
-                .map_err(|err| err.to_string())?;
-            records.push(value);
-            ids.push(id);
-            id += 1;
-        }
-        let strings_start = records_start + (db2.header.record_count * db2.header.record_size) as usize;
-
-        // if a list of IDs is provided, correct our auto-generated IDs
-        let id_list_start: usize = strings_start + db2.header.string_table_size as usize;
-        let id_list_size: usize = db2.section_headers[0].id_list_size as usize;
-        if id_list_size > 0 {
-            reader.seek(std::io::SeekFrom::Start(id_list_start as u64))
+        for (section_index, section) in db2.section_headers.iter().enumerate() {
+            if !db2.is_section_readable(data, section_index) {
+                id += section.record_count;
+                continue;
+            }
+            let section_start = records.len();
+            let records_start = section.file_offset as usize;
+            reader.seek(std::io::SeekFrom::Start(records_start as u64))
                 .map_err(|err| err.to_string())?;
-            assert_eq!(id_list_size, records.len() * 4);
-            for i in 0..records.len() {
-                id = u32::from_reader_with_ctx(&mut reader, ())
+            for _ in 0..section.record_count {
+                let value = T::from_reader_with_ctx(&mut reader, db2.clone())
                     .map_err(|e| format!("{:?}", e))?;
-                ids[i] = id;
+                // our abuse of Deku in the database system always puts the cursor back where it started, so advance to the next record manually
+                reader.seek(std::io::SeekFrom::Current(db2.header.record_size as i64))
+                    .map_err(|err| err.to_string())?;
+                records.push(value);
+                ids.push(id);
+                id += 1;
+            }
+            let strings_start = records_start + (section.record_count * db2.header.record_size) as usize;
+
+            // if a list of IDs is provided, correct our auto-generated IDs
+            let id_list_start: usize = strings_start + section.string_table_size as usize;
+            let id_list_size: usize = section.id_list_size as usize;
+            if id_list_size > 0 {
+                reader.seek(std::io::SeekFrom::Start(id_list_start as u64))
+                    .map_err(|err| err.to_string())?;
+                assert_eq!(id_list_size, section.record_count as usize * 4);
+                for record_id in ids[section_start..].iter_mut() {
+                    id = u32::from_reader_with_ctx(&mut reader, ())
+                        .map_err(|e| format!("{:?}", e))?;
+                    *record_id = id;
+                }
             }
-        }
 
-        let mut foreign_keys = None;
-        let relationship_start = id_list_start + id_list_size + 12; // idk
-        if db2.section_headers[0].relationship_data_size > 0 {
-            let mut keys = vec![0; records.len()];
-            reader.seek(SeekFrom::Start(relationship_start as u64))
-                .map_err(|err| err.to_string())?;
-            for _ in 0..records.len() {
-                let foreign_key = u32::from_reader_with_ctx(&mut reader, ())
+            let relationship_start = id_list_start + id_list_size + 12; // idk
+            if section.relationship_data_size > 0 {
+                let keys = foreign_keys.get_or_insert_with(Vec::new);
+                keys.resize(records.len(), 0);
+                reader.seek(SeekFrom::Start(relationship_start as u64))
+                    .map_err(|err| err.to_string())?;
+                for _ in 0..section.record_count {
+                    let foreign_key = u32::from_reader_with_ctx(&mut reader, ())
+                        .map_err(|e| format!("{:?}", e))?;
+                    let id = u32::from_reader_with_ctx(&mut reader, ())
+                        .map_err(|e| format!("{:?}", e))?;
+                    keys[section_start + id as usize] = foreign_key;
+                }
+            }
+
+            for _ in 0..section.copy_table_count {
+                let id_of_new_row = u32::from_reader_with_ctx(&mut reader, ())
                     .map_err(|e| format!("{:?}", e))?;
-                let id = u32::from_reader_with_ctx(&mut reader, ())
+                let id_of_old_row = u32::from_reader_with_ctx(&mut reader, ())
                     .map_err(|e| format!("{:?}", e))?;
-                keys[id as usize] = foreign_key;
+                copies.insert(id_of_new_row, id_of_old_row);
             }
-            foreign_keys = Some(keys);
+        }
+        if let Some(keys) = foreign_keys.as_mut() {
+            keys.resize(records.len(), 0);
         }
 
-        let mut copies = HashMap::new();
-        for _ in 0..db2.section_headers[0].copy_table_count {
-            let id_of_new_row = u32::from_reader_with_ctx(&mut reader, ())
-                .map_err(|e| format!("{:?}", e))?;
-            let id_of_old_row = u32::from_reader_with_ctx(&mut reader, ())
-                .map_err(|e| format!("{:?}", e))?;
-            copies.insert(id_of_new_row, id_of_old_row);
+        let mut id_indices = HashMap::with_capacity(ids.len());
+        for (i, id) in ids.iter().enumerate() {
+            id_indices.entry(*id).or_insert(i);
         }
 
         Ok(DatabaseTable {
@@ -411,6 +471,7 @@ impl<T> DatabaseTable<T> {
             ids,
             foreign_keys,
             copies,
+            id_indices,
         })
     }
 
@@ -418,7 +479,7 @@ impl<T> DatabaseTable<T> {
         if let Some(id) = self.copies.get(&needle) {
             needle = *id;
         }
-        let index = self.ids.iter().position(|haystack| *haystack == needle)?;
+        let index = *self.id_indices.get(&needle)?;
         Some(&self.records[index])
     }
 }
@@ -857,6 +918,194 @@ pub struct LightSkyboxRecord {
     pub _celestial_skybox_file_data_id: u32,
 }
 
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct CreatureDisplayInfoRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub model_id: u16,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub _sound_id: u16,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub _size_class: i8,
+    #[deku(reader = "db2.read_field(deku::reader, 3)")]
+    pub creature_model_scale: f32,
+    #[deku(reader = "db2.read_field(deku::reader, 4)")]
+    pub creature_model_alpha: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 6)")]
+    pub _extended_display_info_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 14)")]
+    pub _flags: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 22)")]
+    pub texture_variation_file_data_ids: [u32; 4],
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct CreatureModelDataRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub _geo_box: [f32; 6],
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub _flags: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub file_data_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 19)")]
+    pub model_scale: f32,
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct ItemDisplayInfoRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 8)")]
+    pub _flags: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 9)")]
+    pub model_resources_ids: [u32; 2],
+    #[deku(reader = "db2.read_field(deku::reader, 10)")]
+    pub model_material_resources_ids: [u32; 2],
+    #[deku(reader = "db2.read_field(deku::reader, 12)")]
+    pub geoset_groups: [u32; 6],
+    #[deku(reader = "db2.read_field(deku::reader, 13)")]
+    pub attachment_geoset_groups: [u32; 6],
+    #[deku(reader = "db2.read_field(deku::reader, 14)")]
+    pub helmet_geoset_vis: [u32; 2],
+}
+
+// the ItemDisplayInfo each row belongs to is stored in the relationship map
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct ItemDisplayInfoMaterialResRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub component_section: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub material_resources_id: u32,
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct ModelFileDataRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub _flags: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub _lod_count: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub file_data_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 3)")]
+    pub model_resources_id: u32,
+}
+
+// the record ID is the texture's file ID
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct TextureFileDataRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub _usage_type: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub material_resources_id: u32,
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct ChrCustomizationElementRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub choice_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub related_choice_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub geoset_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 3)")]
+    pub _skinned_model_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 4)")]
+    pub material_id: u32,
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct ChrCustomizationGeosetRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub geoset_type: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub geoset_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub _modifier: u32,
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct ChrCustomizationMaterialRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub texture_target_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub material_resources_id: u32,
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct ChrModelTextureLayerRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub texture_type: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub layer: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub _flags: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 3)")]
+    pub blend_mode: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 4)")]
+    pub section_mask: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 6)")]
+    pub texture_target_ids: [u32; 2],
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct CharComponentTextureSectionsRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub layout_id: u32,
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub section_type: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub x: u16,
+    #[deku(reader = "db2.read_field(deku::reader, 3)")]
+    pub y: u16,
+    #[deku(reader = "db2.read_field(deku::reader, 4)")]
+    pub width: u16,
+    #[deku(reader = "db2.read_field(deku::reader, 5)")]
+    pub height: u16,
+}
+
+#[derive(DekuRead, Debug, Clone)]
+#[deku(ctx = "db2: Wdc4Db2File")]
+pub struct WeatherRecord {
+    #[deku(reader = "db2.read_field(deku::reader, 0)")]
+    pub intensity: [f32; 2],
+    #[deku(reader = "db2.read_field(deku::reader, 1)")]
+    pub weather_type: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 2)")]
+    pub effect_type: u8,
+    #[deku(reader = "db2.read_field(deku::reader, 3)")]
+    pub _ambience_id: u32,
+}
+
+#[wasm_bindgen(js_name = "WowWeatherResult")]
+#[derive(Debug, Clone, Copy)]
+pub struct WeatherResult {
+    pub id: u32,
+    pub weather_type: u8, // 1 = rain, 2 = snow, 3 = sandstorm
+    pub effect_type: u8,
+    pub intensity_min: f32,
+    pub intensity_max: f32,
+}
+
+impl WeatherResult {
+    fn new(id: u32, record: &WeatherRecord) -> Self {
+        WeatherResult {
+            id,
+            weather_type: record.weather_type,
+            effect_type: record.effect_type,
+            intensity_min: record.intensity[0],
+            intensity_max: record.intensity[1],
+        }
+    }
+}
+
 #[wasm_bindgen(js_name = "WowLiquidResult", getter_with_clone)]
 #[derive(Debug, Clone)]
 pub struct LiquidResult {
@@ -873,8 +1122,20 @@ pub struct LiquidResult {
 struct ZoneLightLookup {
     zone_lights: DatabaseTable<ZoneLightRecord>,
     points: HashMap<u32, Vec<Vec2>>, // zone light id -> points
+    areas: HashMap<u32, f32>, // zone light id -> polygon area
+    by_map: HashMap<u16, Vec<ZoneLightBounds>>,
+}
+
+// a zone light's polygon bounds, padded by ZONE_LIGHT_FALLOFF
+struct ZoneLightBounds {
+    index: usize,
+    min: Vec2,
+    max: Vec2,
 }
 
+// how far outside/inside of a zone light's polygon it fades in/out
+const ZONE_LIGHT_FALLOFF: f32 = 100.0;
+
 impl ZoneLightLookup {
     fn new(
         zone_lights: DatabaseTable<ZoneLightRecord>,
@@ -889,37 +1150,198 @@ impl ZoneLightLookup {
                 .or_insert(Vec::new())
                 .push(pt);
         }
+        let areas = points.iter()
+            .map(|(id, polygon)| (*id, polygon_area(polygon)))
+            .collect();
+        let mut by_map: HashMap<u16, Vec<ZoneLightBounds>> = HashMap::new();
+        for (index, record) in zone_lights.records.iter().enumerate() {
+            let polygon = match points.get(&zone_lights.ids[index]) {
+                Some(polygon) => polygon,
+                None => continue,
+            };
+            let padding = Vec2::new(ZONE_LIGHT_FALLOFF, ZONE_LIGHT_FALLOFF);
+            let min = polygon.iter().fold(Vec2::new(f32::INFINITY, f32::INFINITY), |acc, p| acc.inf(p)) - padding;
+            let max = polygon.iter().fold(Vec2::new(f32::NEG_INFINITY, f32::NEG_INFINITY), |acc, p| acc.sup(p)) + padding;
+            by_map.entry(record.map_id)
+                .or_default()
+                .push(ZoneLightBounds { index, min, max });
+        }
         ZoneLightLookup {
             zone_lights,
             points,
+            areas,
+            by_map,
         }
     }
 
-    pub fn lookup_light_id(&self, map_id: u16, x: f32, y: f32, z: f32) -> Option<(u16, f32)> {
+    // returns each zone light affecting the point along with its weight.
+    // weights crossfade from 1, ZONE_LIGHT_FALLOFF units inside the
+    // polygon's border, to 0, ZONE_LIGHT_FALLOFF units outside it, so two
+    // zones sharing a border each get 0.5 on it. where polygons overlap, the
+    // smaller (more specific) zone wins
+    pub fn lookup_light_ids(&self, map_id: u16, x: f32, y: f32, z: f32) -> Vec<(u16, f32)> {
         let p = Vec2::new(x, y);
-        for i in 0..self.zone_lights.records.len() {
-            let record = &self.zone_lights.records[i];
-            let zone_light_id = self.zone_lights.ids[i];
-            if record.map_id == map_id && z >= record.z_min && z <= record.z_max {
-                let points = self.points.get(&zone_light_id).unwrap();
-                if point_inside_polygon(&p, points) {
-                    let dist = point_dist_to_polygon(&p, points);
-                    return Some((record.light_id, dist));
+        let mut candidates = Vec::new();
+        let map_bounds = match self.by_map.get(&map_id) {
+            Some(map_bounds) => map_bounds,
+            None => return Vec::new(),
+        };
+        for bounds in map_bounds {
+            if p.x < bounds.min.x || p.y < bounds.min.y || p.x > bounds.max.x || p.y > bounds.max.y {
+                continue;
+            }
+            let record = &self.zone_lights.records[bounds.index];
+            let zone_light_id = self.zone_lights.ids[bounds.index];
+            if z < record.z_min || z > record.z_max {
+                continue;
+            }
+            let points = match self.points.get(&zone_light_id) {
+                Some(points) => points,
+                None => continue,
+            };
+            let dist = point_dist_to_polygon(&p, points);
+            let half_weight = 0.5 * (dist / ZONE_LIGHT_FALLOFF).min(1.0);
+            let weight = if point_inside_polygon(&p, points) {
+                0.5 + half_weight
+            } else {
+                0.5 - half_weight
+            };
+            if weight > 0.0 {
+                let area = self.areas.get(&zone_light_id).copied().unwrap_or(f32::INFINITY);
+                candidates.push((record.light_id, weight, area));
+            }
+        }
+        candidates.sort_by(|a, b| a.2.total_cmp(&b.2));
+        apply_priority(candidates.into_iter().map(|(id, weight, _)| (id, weight)).collect(), 1.0)
+    }
+}
+
+// buckets each map's spherical lights into a uniform XY grid, so a lookup
+// only has to consider lights whose falloff sphere could reach the point
+struct LightLookup {
+    default_lights: HashMap<u16, usize>, // map id -> index of the light at the origin
+    cells: HashMap<(u16, i32, i32), Vec<usize>>,
+    large_lights: HashMap<u16, Vec<usize>>, // lights spanning too many cells to bucket
+}
+
+const LIGHT_GRID_CELL_SIZE: f32 = 256.0;
+const LIGHT_GRID_MAX_SPAN: i32 = 16;
+
+impl LightLookup {
+    fn new(lights: &DatabaseTable<LightRecord>) -> Self {
+        let origin = Vec3::new(0.0);
+        let mut default_lights = HashMap::new();
+        let mut cells: HashMap<(u16, i32, i32), Vec<usize>> = HashMap::new();
+        let mut large_lights: HashMap<u16, Vec<usize>> = HashMap::new();
+        for (i, light) in lights.records.iter().enumerate() {
+            if light.coords == origin {
+                default_lights.entry(light.map_id).or_insert(i);
+            }
+            let radius = light.falloff_end.max(light.falloff_start);
+            let (x0, y0) = LightLookup::cell(light.coords.x - radius, light.coords.y - radius);
+            let (x1, y1) = LightLookup::cell(light.coords.x + radius, light.coords.y + radius);
+            if x1 - x0 >= LIGHT_GRID_MAX_SPAN || y1 - y0 >= LIGHT_GRID_MAX_SPAN {
+                large_lights.entry(light.map_id).or_default().push(i);
+                continue;
+            }
+            for cx in x0..=x1 {
+                for cy in y0..=y1 {
+                    cells.entry((light.map_id, cx, cy)).or_default().push(i);
                 }
             }
         }
-        None
+        LightLookup {
+            default_lights,
+            cells,
+            large_lights,
+        }
+    }
+
+    fn cell(x: f32, y: f32) -> (i32, i32) {
+        ((x / LIGHT_GRID_CELL_SIZE).floor() as i32, (y / LIGHT_GRID_CELL_SIZE).floor() as i32)
+    }
+
+    // indices of every light which might contain the point
+    fn query(&self, map_id: u16, coord: &Vec3) -> impl Iterator<Item = usize> + '_ {
+        let (cx, cy) = LightLookup::cell(coord.x, coord.y);
+        let cell = self.cells.get(&(map_id, cx, cy));
+        let large = self.large_lights.get(&map_id);
+        cell.into_iter().flatten()
+            .chain(large.into_iter().flatten())
+            .copied()
     }
 }
 
+fn polygon_area(points: &[Vec2]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        area += a.x * b.y - b.x * a.y;
+    }
+    (area * 0.5).abs()
+}
+
+// `candidates` must be ordered from highest to lowest priority. each one
+// gets at most whatever weight the ones before it left of `budget`
+fn apply_priority<T>(candidates: Vec<(T, f32)>, budget: f32) -> Vec<(T, f32)> {
+    let mut remaining = budget;
+    let mut result = Vec::new();
+    for (value, weight) in candidates {
+        if remaining <= 0.0 {
+            break;
+        }
+        let weight = weight.min(remaining);
+        remaining -= weight;
+        result.push((value, weight));
+    }
+    result
+}
+
+// the slots of LightRecord::light_param_ids
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LightParamSlot {
+    Clear = 0,
+    ClearUnderwater = 1,
+    Storm = 2,
+    StormUnderwater = 3,
+    Death = 4,
+}
+
+impl LightParamSlot {
+    fn fallbacks(self) -> &'static [LightParamSlot] {
+        use LightParamSlot::*;
+        match self {
+            Clear => &[Clear],
+            ClearUnderwater => &[ClearUnderwater, Clear],
+            Storm => &[Storm],
+            StormUnderwater => &[StormUnderwater],
+            Death => &[Death, Clear],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LightConditions {
+    weather_id: Option<u32>,
+    weather_intensity: f32,
+    underwater: bool,
+    dead: bool,
+}
+
 #[wasm_bindgen(js_name = "WowDatabase")]
 pub struct Database {
+    conditions: LightConditions,
+    weathers: Option<DatabaseTable<WeatherRecord>>,
     lights: DatabaseTable<LightRecord>,
     light_data: DatabaseTable<LightDataRecord>,
     light_params: DatabaseTable<LightParamsRecord>,
     light_skyboxes: DatabaseTable<LightSkyboxRecord>,
     liquid_types: DatabaseTable<LiquidType>,
     zone_light_lookup: ZoneLightLookup,
+    light_lookup: LightLookup,
+    light_param_indices: HashMap<u32, usize>, // LightParams ID -> index
+    light_data_by_param: HashMap<u32, Vec<usize>>, // LightParams ID -> LightData indices, sorted by time
 }
 
 #[wasm_bindgen(js_class = "WowDatabase")]
@@ -933,65 +1355,105 @@ impl Database {
         zone_lights_db: &[u8],
         zone_light_points_db: &[u8],
     ) -> Result<Database, String> {
-        let lights = DatabaseTable::new(lights_db)?;
-        let light_data = DatabaseTable::new(light_data_db)?;
-        let light_params = DatabaseTable::new(light_params_db)?;
+        let lights: DatabaseTable<LightRecord> = DatabaseTable::new(lights_db)?;
+        let light_data: DatabaseTable<LightDataRecord> = DatabaseTable::new(light_data_db)?;
+        let light_params: DatabaseTable<LightParamsRecord> = DatabaseTable::new(light_params_db)?;
         let liquid_types = DatabaseTable::new(liquid_types_db)?;
         let light_skyboxes = DatabaseTable::new(light_skybox_db)?;
         let zone_lights = DatabaseTable::new(zone_lights_db)?;
         let zone_light_points = DatabaseTable::new(zone_light_points_db)?;
         let zone_light_lookup = ZoneLightLookup::new(zone_lights, zone_light_points);
-        Ok(Self {
+        Ok(Self::from_tables(lights, light_data, light_params, liquid_types, light_skyboxes, zone_light_lookup))
+    }
+
+    fn from_tables(
+        lights: DatabaseTable<LightRecord>,
+        light_data: DatabaseTable<LightDataRecord>,
+        light_params: DatabaseTable<LightParamsRecord>,
+        liquid_types: DatabaseTable<LiquidType>,
+        light_skyboxes: DatabaseTable<LightSkyboxRecord>,
+        zone_light_lookup: ZoneLightLookup,
+    ) -> Self {
+        let light_lookup = LightLookup::new(&lights);
+
+        let mut light_param_indices = HashMap::new();
+        for (i, light_param) in light_params.records.iter().enumerate() {
+            light_param_indices.entry(light_param.id).or_insert(i);
+        }
+
+        let mut light_data_by_param: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, light_data) in light_data.records.iter().enumerate() {
+            light_data_by_param.entry(light_data.light_param_id).or_default().push(i);
+        }
+        for indices in light_data_by_param.values_mut() {
+            // stable, so ties keep the same order as the DB2
+            indices.sort_by_key(|&i| light_data.records[i].time);
+        }
+
+        Self {
+            conditions: LightConditions::default(),
+            weathers: None,
             lights,
             light_data,
             light_params,
             liquid_types,
             light_skyboxes,
             zone_light_lookup,
-        })
+            light_lookup,
+            light_param_indices,
+            light_data_by_param,
+        }
     }
 
     fn get_default_light(&self, map_id: u16, time: u32) -> LightResult {
-        let origin = Vec3::new(0.0);
-        let default_light = self.lights.records.iter()
-            .find(|light| light.map_id == map_id && light.coords == origin)
-            .unwrap_or(self.lights.get_record(1).unwrap());
+        let default_light = match self.light_lookup.default_lights.get(&map_id) {
+            Some(&index) => &self.lights.records[index],
+            None => self.lights.get_record(1).unwrap(),
+        };
         self.get_light_result(default_light, time).unwrap()
     }
 
     fn get_light_result(&self, light: &LightRecord, time: u32) -> Option<LightResult> {
-        // TODO: select based on weather conditions
-        let id = light.light_param_ids[0];
-        assert!(id != 0);
+        let conditions = &self.conditions;
+        if conditions.dead {
+            return self.get_light_result_for_slot(light, LightParamSlot::Death, time);
+        }
+        let (clear_slot, storm_slot) = if conditions.underwater {
+            (LightParamSlot::ClearUnderwater, LightParamSlot::StormUnderwater)
+        } else {
+            (LightParamSlot::Clear, LightParamSlot::Storm)
+        };
+        let clear = self.get_light_result_for_slot(light, clear_slot, time)?;
+        if conditions.weather_id.is_some() && conditions.weather_intensity > 0.0 {
+            if let Some(storm) = self.get_light_result_for_slot(light, storm_slot, time) {
+                return Some(clear.lerp(storm, conditions.weather_intensity));
+            }
+        }
+        Some(clear)
+    }
+
+    fn get_light_result_for_slot(&self, light: &LightRecord, slot: LightParamSlot, time: u32) -> Option<LightResult> {
+        // not every light fills in every slot, so fall back to the closest
+        // one that's present
+        let id = slot.fallbacks().iter()
+            .map(|slot| light.light_param_ids[*slot as usize])
+            .find(|id| *id != 0)?;
 
         let light_param = self.get_light_param(id as u32)?;
         let skybox = self.light_skyboxes.get_record(light_param.skybox_id);
 
         // based on the given time, find the current and next LightDataRecord
-        let mut current_light_data: Option<&LightDataRecord> = None;
-        let mut next_light_data: Option<&LightDataRecord> = None;
-        for light_data in &self.light_data.records {
-            if light_data.light_param_id != id as u32 {
-                continue;
-            }
-            if light_data.time <= time {
-                if let Some(current) = current_light_data {
-                    if light_data.time > current.time {
-                        current_light_data = Some(light_data);
-                    }
-                } else {
-                    current_light_data = Some(light_data);
-                }
-            } else if let Some(next) = next_light_data {
-                if light_data.time < next.time {
-                    next_light_data = Some(light_data);
-                }
-            } else {
-                next_light_data = Some(light_data);
-            }
+        let records = &self.light_data.records;
+        let indices = self.light_data_by_param.get(&(id as u32))?;
+        let next_index = indices.partition_point(|&i| records[i].time <= time);
+        let mut current_index = next_index.checked_sub(1)?;
+        // if several records share a time, use the first one
+        while current_index > 0 && records[indices[current_index - 1]].time == records[indices[current_index]].time {
+            current_index -= 1;
         }
+        let current_light_data = &records[indices[current_index]];
+        let next_light_data = indices.get(next_index).map(|&i| &records[i]);
 
-        let current_light_data = current_light_data?;
         let mut final_result = LightResult::new(current_light_data, light_param, skybox);
         if current_light_data.time != std::u32::MAX {
             if let Some(next) = next_light_data {
@@ -1004,6 +1466,52 @@ impl Database {
         Some(final_result)
     }
 
+    pub fn load_weathers(&mut self, weather_db: &[u8]) -> Result<(), String> {
+        self.weathers = Some(DatabaseTable::new(weather_db)?);
+        Ok(())
+    }
+
+    pub fn get_all_weathers(&self) -> Vec<WeatherResult> {
+        let weathers = match self.weathers.as_ref() {
+            Some(weathers) => weathers,
+            None => return Vec::new(),
+        };
+        weathers.ids.iter().zip(weathers.records.iter())
+            .map(|(&id, record)| WeatherResult::new(id, record))
+            .collect()
+    }
+
+    // `intensity` ranges from 0 (clear) to 1 (full storm lighting)
+    pub fn set_weather(&mut self, weather_id: u32, intensity: f32) -> Result<(), String> {
+        if let Some(weathers) = self.weathers.as_ref() {
+            if weathers.get_record(weather_id).is_none() {
+                return Err(format!("unknown weather id {}", weather_id));
+            }
+        }
+        self.conditions.weather_id = Some(weather_id);
+        self.conditions.weather_intensity = intensity.clamp(0.0, 1.0);
+        Ok(())
+    }
+
+    pub fn clear_weather(&mut self) {
+        self.conditions.weather_id = None;
+        self.conditions.weather_intensity = 0.0;
+    }
+
+    pub fn get_weather(&self) -> Option<WeatherResult> {
+        let weather_id = self.conditions.weather_id?;
+        let record = self.weathers.as_ref()?.get_record(weather_id)?;
+        Some(WeatherResult::new(weather_id, record))
+    }
+
+    pub fn set_underwater(&mut self, underwater: bool) {
+        self.conditions.underwater = underwater;
+    }
+
+    pub fn set_dead(&mut self, dead: bool) {
+        self.conditions.dead = dead;
+    }
+
     pub fn get_liquid_type(&self, liquid_type: u32) -> Option<LiquidResult> {
         let liquid = self.liquid_types.get_record(liquid_type)?;
         Some(LiquidResult {
@@ -1019,48 +1527,41 @@ impl Database {
     }
 
     fn get_light_param(&self, needle: u32) -> Option<&LightParamsRecord> {
-        self.light_params.records.iter()
-            .find(|param| param.id == needle)
-    }
-
-    fn lookup_zone_light(&self, map_id: u16, x: f32, y: f32, z:f32, time: u32) -> Option<(LightResult, f32)> {
-        let (zone_light, dist) = self.zone_light_lookup.lookup_light_id(map_id, x, y, z)?;
-        let light = self.lights.get_record(zone_light as u32)?;
-        Some((self.get_light_result(light, time)?, dist))
+        let index = *self.light_param_indices.get(&needle)?;
+        Some(&self.light_params.records[index])
     }
 
     pub fn get_lighting_data(&self, map_id: u16, x: f32, y: f32, z: f32, time: u32) -> LightResult {
         let coord = Vec3 { x, y, z };
         let mut result = LightResult::default();
 
-        for light in &self.lights.records {
-            if light.map_id == map_id {
-                match light.distance(&coord) {
-                    DistanceResult::Inner => {
-                        if let Some(outer_light) = self.get_light_result(light, time) {
-                            result.add_scaled(&outer_light, 1.0);
-                        }
-                    },
-                    DistanceResult::Outer(distance) => {
-                        if let Some(outer_light) = self.get_light_result(light, time) {
-                            let alpha = 1.0 - (distance - light.falloff_start) / (light.falloff_end - light.falloff_start);
-                            result.add_scaled(&outer_light, alpha);
-                        }
-                    },
-                    DistanceResult::None => {},
-                }
+        // smaller spherical lights take priority over the larger ones that
+        // contain them
+        let mut spherical_lights = Vec::new();
+        for index in self.light_lookup.query(map_id, &coord) {
+            let light = &self.lights.records[index];
+            let alpha = match light.distance(&coord) {
+                DistanceResult::Inner => 1.0,
+                DistanceResult::Outer(distance) => 1.0 - (distance - light.falloff_start) / (light.falloff_end - light.falloff_start),
+                DistanceResult::None => continue,
+            };
+            spherical_lights.push((light, alpha));
+        }
+        spherical_lights.sort_by(|a, b| a.0.falloff_end.total_cmp(&b.0.falloff_end));
+        for (light, alpha) in apply_priority(spherical_lights, 1.0) {
+            if let Some(light_result) = self.get_light_result(light, time) {
+                result.add_scaled(&light_result, alpha);
             }
         }
 
-        // zone lights are defined by polygonal zones, and are only used in WOTLK
-        if let Some((zone_light, dist)) = self.lookup_zone_light(map_id, x, y, z, time) {
-            let threshold = 100.0;
-            // if we're approaching the border of another zone, smoothly taper off to the non-zone lighting
-            if dist < threshold {
-                result.add_scaled(&zone_light, dist / threshold);
-            } else if result.total_alpha < 1.0 {
-                // otherwise, just accept whatever alpha hasn't been taken by spherical lights
-                result.add_scaled(&zone_light, 1.0 - result.total_alpha);
+        // zone lights are defined by polygonal zones, and are only used in
+        // WOTLK. they get whatever alpha hasn't been taken by spherical lights
+        let zone_lights = self.zone_light_lookup.lookup_light_ids(map_id, x, y, z);
+        for (light_id, alpha) in apply_priority(zone_lights, 1.0 - result.total_alpha) {
+            let light_result = self.lights.get_record(light_id as u32)
+                .and_then(|light| self.get_light_result(light, time));
+            if let Some(light_result) = light_result {
+                result.add_scaled(&light_result, alpha);
             }
         }
 
@@ -1118,6 +1619,271 @@ pub struct SkyboxMetadata {
     pub weight: f32,
 }
 
+#[wasm_bindgen(js_name = "WowCreatureDatabase")]
+pub struct CreatureDatabase {
+    display_infos: DatabaseTable<CreatureDisplayInfoRecord>,
+    model_data: DatabaseTable<CreatureModelDataRecord>,
+}
+
+#[wasm_bindgen(js_class = "WowCreatureDatabase")]
+impl CreatureDatabase {
+    pub fn new(
+        creature_display_info_db: &[u8],
+        creature_model_data_db: &[u8],
+    ) -> Result<CreatureDatabase, String> {
+        Ok(Self {
+            display_infos: DatabaseTable::new(creature_display_info_db)?,
+            model_data: DatabaseTable::new(creature_model_data_db)?,
+        })
+    }
+
+    pub fn get_creature_display(&self, display_id: u32) -> Option<CreatureDisplay> {
+        let display_info = self.display_infos.get_record(display_id)?;
+        let model_data = self.model_data.get_record(display_info.model_id as u32)?;
+        Some(CreatureDisplay {
+            display_id,
+            model_file_id: model_data.file_data_id,
+            scale: display_info.creature_model_scale * model_data.model_scale,
+            alpha: display_info.creature_model_alpha as f32 / 255.0,
+            texture_variation_ids: display_info.texture_variation_file_data_ids.to_vec(),
+        })
+    }
+}
+
+// everything needed to spawn a creature: which M2 to load, and which
+// textures to plug into its monster skin slots
+#[wasm_bindgen(js_name = "WowCreatureDisplay", getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct CreatureDisplay {
+    pub display_id: u32,
+    pub model_file_id: u32,
+    pub scale: f32,
+    pub alpha: f32,
+    pub texture_variation_ids: Vec<u32>,
+}
+
+#[wasm_bindgen(js_class = "WowCreatureDisplay")]
+impl CreatureDisplay {
+    // indexed by M2 texture type, for use with `WowM2::resolve_texture_ids`.
+    // there's no known texture type for the fourth variation, so it's unused
+    pub fn get_replaceable_texture_ids(&self) -> Vec<u32> {
+        let mut result = vec![0; M2_NUM_TEXTURE_TYPES];
+        let slots = [M2_TEXTURE_TYPE_MONSTER_SKIN_1, M2_TEXTURE_TYPE_MONSTER_SKIN_2, M2_TEXTURE_TYPE_MONSTER_SKIN_3];
+        for (slot, &file_id) in slots.iter().zip(self.texture_variation_ids.iter()) {
+            result[*slot as usize] = file_id;
+        }
+        result
+    }
+}
+
+#[wasm_bindgen(js_name = "WowCharacterDatabase")]
+pub struct CharacterDatabase {
+    item_display_infos: DatabaseTable<ItemDisplayInfoRecord>,
+    item_display_info_materials: DatabaseTable<ItemDisplayInfoMaterialResRecord>,
+    model_file_data: DatabaseTable<ModelFileDataRecord>,
+    texture_file_data: DatabaseTable<TextureFileDataRecord>,
+    customization_elements: DatabaseTable<ChrCustomizationElementRecord>,
+    customization_geosets: DatabaseTable<ChrCustomizationGeosetRecord>,
+    customization_materials: DatabaseTable<ChrCustomizationMaterialRecord>,
+    texture_layers: DatabaseTable<ChrModelTextureLayerRecord>,
+    texture_sections: DatabaseTable<CharComponentTextureSectionsRecord>,
+}
+
+#[wasm_bindgen(js_class = "WowCharacterDatabase")]
+impl CharacterDatabase {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        item_display_info_db: &[u8],
+        item_display_info_material_res_db: &[u8],
+        model_file_data_db: &[u8],
+        texture_file_data_db: &[u8],
+        chr_customization_element_db: &[u8],
+        chr_customization_geoset_db: &[u8],
+        chr_customization_material_db: &[u8],
+        chr_model_texture_layer_db: &[u8],
+        char_component_texture_sections_db: &[u8],
+    ) -> Result<CharacterDatabase, String> {
+        Ok(Self {
+            item_display_infos: DatabaseTable::new(item_display_info_db)?,
+            item_display_info_materials: DatabaseTable::new(item_display_info_material_res_db)?,
+            model_file_data: DatabaseTable::new(model_file_data_db)?,
+            texture_file_data: DatabaseTable::new(texture_file_data_db)?,
+            customization_elements: DatabaseTable::new(chr_customization_element_db)?,
+            customization_geosets: DatabaseTable::new(chr_customization_geoset_db)?,
+            customization_materials: DatabaseTable::new(chr_customization_material_db)?,
+            texture_layers: DatabaseTable::new(chr_model_texture_layer_db)?,
+            texture_sections: DatabaseTable::new(char_component_texture_sections_db)?,
+        })
+    }
+
+    // a ModelResourcesID can refer to several files (e.g. one helm per race),
+    // which the game picks between at runtime
+    pub fn get_model_file_ids(&self, model_resources_id: u32) -> Vec<u32> {
+        if model_resources_id == 0 {
+            return Vec::new();
+        }
+        self.model_file_data.records.iter()
+            .filter(|record| record.model_resources_id == model_resources_id)
+            .map(|record| record.file_data_id)
+            .collect()
+    }
+
+    pub fn get_texture_file_ids(&self, material_resources_id: u32) -> Vec<u32> {
+        if material_resources_id == 0 {
+            return Vec::new();
+        }
+        let mut result = Vec::new();
+        for i in 0..self.texture_file_data.records.len() {
+            if self.texture_file_data.records[i].material_resources_id == material_resources_id {
+                result.push(self.texture_file_data.ids[i]);
+            }
+        }
+        result
+    }
+
+    pub fn get_item_display(&self, display_id: u32) -> Option<ItemDisplay> {
+        let display_info = self.item_display_infos.get_record(display_id)?;
+        let first_texture = |id: u32| self.get_texture_file_ids(id).first().copied().unwrap_or(0);
+
+        let mut component_textures = Vec::new();
+        if let Some(foreign_keys) = self.item_display_info_materials.foreign_keys.as_ref() {
+            for (i, record) in self.item_display_info_materials.records.iter().enumerate() {
+                if foreign_keys[i] != display_id {
+                    continue;
+                }
+                let file_id = first_texture(record.material_resources_id);
+                if file_id != 0 {
+                    component_textures.push(ComponentTexture {
+                        section: record.component_section as u32,
+                        file_id,
+                    });
+                }
+            }
+        }
+
+        Some(ItemDisplay {
+            display_id,
+            model_file_ids: display_info.model_resources_ids.iter()
+                .map(|&id| self.get_model_file_ids(id).first().copied().unwrap_or(0))
+                .collect(),
+            model_texture_ids: display_info.model_material_resources_ids.iter()
+                .map(|&id| first_texture(id))
+                .collect(),
+            geoset_groups: display_info.geoset_groups.to_vec(),
+            attachment_geoset_groups: display_info.attachment_geoset_groups.to_vec(),
+            helmet_geoset_vis: display_info.helmet_geoset_vis.to_vec(),
+            component_textures,
+        })
+    }
+
+    // elements tied to another choice (e.g. skin color textures, which depend
+    // on the selected face) are only included if that choice is in
+    // `selected_choice_ids`
+    pub fn get_customization_choice(&self, choice_id: u32, selected_choice_ids: &[u32]) -> CustomizationChoice {
+        let mut geosets = Vec::new();
+        let mut materials = Vec::new();
+        for element in &self.customization_elements.records {
+            if element.choice_id != choice_id {
+                continue;
+            }
+            if element.related_choice_id != 0 && !selected_choice_ids.contains(&element.related_choice_id) {
+                continue;
+            }
+            if let Some(geoset) = self.customization_geosets.get_record(element.geoset_id) {
+                geosets.push(GeosetOverride {
+                    group: geoset.geoset_type,
+                    value: geoset.geoset_id,
+                });
+            }
+            if let Some(material) = self.customization_materials.get_record(element.material_id) {
+                let file_id = self.get_texture_file_ids(material.material_resources_id)
+                    .first().copied().unwrap_or(0);
+                if file_id == 0 {
+                    continue;
+                }
+                let layer = self.texture_layers.records.iter()
+                    .find(|layer| layer.texture_target_ids.contains(&material.texture_target_id));
+                if let Some(layer) = layer {
+                    materials.push(CustomizationMaterial {
+                        texture_type: layer.texture_type,
+                        layer: layer.layer,
+                        blend_mode: layer.blend_mode,
+                        section_mask: layer.section_mask,
+                        file_id,
+                    });
+                }
+            }
+        }
+        materials.sort_by_key(|material| material.layer);
+        CustomizationChoice {
+            choice_id,
+            geosets,
+            materials,
+        }
+    }
+
+    pub fn get_texture_sections(&self, layout_id: u32) -> Vec<TextureSection> {
+        self.texture_sections.records.iter()
+            .filter(|record| record.layout_id == layout_id)
+            .map(|record| TextureSection {
+                section_type: record.section_type as u32,
+                x: record.x as u32,
+                y: record.y as u32,
+                width: record.width as u32,
+                height: record.height as u32,
+            })
+            .collect()
+    }
+}
+
+#[wasm_bindgen(js_name = "WowItemDisplay", getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct ItemDisplay {
+    pub display_id: u32,
+    pub model_file_ids: Vec<u32>, // left and right models (e.g. for shoulders), 0 if unused
+    pub model_texture_ids: Vec<u32>,
+    pub geoset_groups: Vec<u32>,
+    pub attachment_geoset_groups: Vec<u32>,
+    pub helmet_geoset_vis: Vec<u32>,
+    pub component_textures: Vec<ComponentTexture>,
+}
+
+// a texture to be drawn into one section of the character's skin texture
+#[wasm_bindgen(js_name = "WowComponentTexture")]
+#[derive(Debug, Clone, Copy)]
+pub struct ComponentTexture {
+    pub section: u32,
+    pub file_id: u32,
+}
+
+#[wasm_bindgen(js_name = "WowCustomizationChoice", getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct CustomizationChoice {
+    pub choice_id: u32,
+    pub geosets: Vec<GeosetOverride>,
+    pub materials: Vec<CustomizationMaterial>, // sorted by layer
+}
+
+#[wasm_bindgen(js_name = "WowCustomizationMaterial")]
+#[derive(Debug, Clone, Copy)]
+pub struct CustomizationMaterial {
+    pub texture_type: u32,
+    pub layer: u32,
+    pub blend_mode: u32,
+    pub section_mask: u32, // 0 means the whole texture
+    pub file_id: u32,
+}
+
+#[wasm_bindgen(js_name = "WowTextureSection")]
+#[derive(Debug, Clone, Copy)]
+pub struct TextureSection {
+    pub section_type: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1155,4 +1921,211 @@ mod test {
         let db: DatabaseTable<LightSkyboxRecord> = DatabaseTable::new(&d5).unwrap();
         dbg!(&db.records[0..4]);
     }
+
+    #[test]
+    fn test_light_priority() {
+        // a light fully inside a more specific one gets nothing
+        let weights = apply_priority(vec![("inner", 1.0), ("outer", 1.0)], 1.0);
+        assert_eq!(weights, vec![("inner", 1.0)]);
+        // partially faded lights share the remainder
+        let weights = apply_priority(vec![("a", 0.25), ("b", 1.0), ("c", 1.0)], 0.75);
+        assert_eq!(weights, vec![("a", 0.25), ("b", 0.5)]);
+
+        let square = [Vec2::new(0.0, 0.0), Vec2::new(2.0, 0.0), Vec2::new(2.0, 2.0), Vec2::new(0.0, 2.0)];
+        assert_eq!(polygon_area(&square), 4.0);
+    }
+
+    #[test]
+    fn test_light_lookup() {
+        let light = |x: f32, falloff_end: f32| LightRecord {
+            coords: Vec3 { x, y: 0.0, z: 0.0 },
+            falloff_start: falloff_end / 2.0,
+            falloff_end,
+            map_id: 0,
+            light_param_ids: [0; 8],
+        };
+        let records = vec![light(0.0, 0.0), light(1000.0, 100.0), light(5000.0, 10000.0)];
+        let lights = DatabaseTable {
+            ids: vec![1, 2, 3],
+            records,
+            foreign_keys: None,
+            copies: HashMap::new(),
+            id_indices: HashMap::new(),
+        };
+        let lookup = LightLookup::new(&lights);
+        assert_eq!(lookup.default_lights.get(&0), Some(&0));
+        let query = |x: f32, map_id: u16| {
+            let mut result: Vec<usize> = lookup.query(map_id, &Vec3 { x, y: 0.0, z: 0.0 }).collect();
+            result.sort();
+            result
+        };
+        assert_eq!(query(1050.0, 0), vec![1, 2]);
+        assert_eq!(query(3000.0, 0), vec![2]);
+        assert_eq!(query(1050.0, 1), Vec::<usize>::new());
+    }
+
+    fn table<T>(records: Vec<(u32, T)>) -> DatabaseTable<T> {
+        let (ids, records): (Vec<u32>, Vec<T>) = records.into_iter().unzip();
+        let id_indices = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        DatabaseTable { ids, records, foreign_keys: None, copies: HashMap::new(), id_indices }
+    }
+
+    fn light_params(id: u32, glow: f32) -> LightParamsRecord {
+        LightParamsRecord {
+            _celestial_overrides: Vec3::new(0.0),
+            id,
+            highlight_sky: false,
+            skybox_id: 0,
+            glow,
+            water_shallow_alpha: 0.0,
+            water_deep_alpha: 0.0,
+            ocean_shallow_alpha: 0.0,
+            ocean_deep_alpha: 0.0,
+            flags: 0.0,
+            unk: 0,
+        }
+    }
+
+    fn light_data(light_param_id: u32, direct_color: u32) -> LightDataRecord {
+        LightDataRecord {
+            light_param_id,
+            time: 0,
+            direct_color,
+            ambient_color: 0,
+            sky_top_color: 0,
+            sky_middle_color: 0,
+            sky_band1_color: 0,
+            sky_band2_color: 0,
+            sky_smog_color: 0,
+            sky_fog_color: 0,
+            sun_color: 0,
+            cloud_sun_color: 0,
+            cloud_emissive_color: 0,
+            cloud_layer1_ambient_color: 0,
+            cloud_layer2_ambient_color: 0,
+            ocean_close_color: 0,
+            ocean_far_color: 0,
+            river_close_color: 0,
+            river_far_color: 0,
+            shadow_opacity: 0,
+            fog_end: 0.0,
+            fog_scaler: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_light_param_slots() {
+        // only the clear and storm slots are filled in
+        let light = LightRecord {
+            coords: Vec3::new(0.0),
+            falloff_start: 0.0,
+            falloff_end: 0.0,
+            map_id: 0,
+            light_param_ids: [1, 0, 2, 0, 0, 0, 0, 0],
+        };
+        let lights = table(vec![(1, light.clone())]);
+        let light_data = table(vec![(1, light_data(1, 0x000000)), (2, light_data(2, 0xff0000))]);
+        let light_params = table(vec![(1, light_params(1, 0.0)), (2, light_params(2, 1.0))]);
+        let zone_light_lookup = ZoneLightLookup::new(table(vec![]), table(vec![]));
+        let mut db = Database::from_tables(lights, light_data, light_params, table(vec![]), table(vec![]), zone_light_lookup);
+        let result = |db: &Database| {
+            let result = db.get_light_result(&light, 0).unwrap();
+            (result.direct_color.x, result.glow)
+        };
+        assert_eq!(result(&db), (0.0, 0.0));
+
+        // storm light params are blended in by the weather's intensity
+        db.set_weather(7, 0.25).unwrap();
+        assert_eq!(result(&db), (0.25, 0.25));
+        db.set_weather(7, 2.0).unwrap();
+        assert_eq!(result(&db), (1.0, 1.0));
+
+        // underwater falls back to the clear slot, but there's nothing to
+        // fall back to for the missing storm underwater slot
+        db.set_weather(7, 0.5).unwrap();
+        db.set_underwater(true);
+        assert_eq!(result(&db), (0.0, 0.0));
+        db.set_underwater(false);
+        assert_eq!(result(&db), (0.5, 0.5));
+
+        // death ignores the weather, and falls back to the clear slot
+        db.set_dead(true);
+        assert_eq!(result(&db), (0.0, 0.0));
+        db.set_dead(false);
+        db.clear_weather();
+        assert_eq!(result(&db), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_creature_display() {
+        let sheep_path = "../data/WorldOfWarcraft/sheep0";
+        let d1 = SheepfileManager::load_file_id_data(sheep_path, 1108759).unwrap(); // creatureDisplayInfo
+        let d2 = SheepfileManager::load_file_id_data(sheep_path, 1365368).unwrap(); // creatureModelData
+        let db = CreatureDatabase::new(&d1, &d2).unwrap();
+        let display = db.get_creature_display(10045).unwrap(); // murloc
+        dbg!(&display, display.get_replaceable_texture_ids());
+    }
+
+    // a single record TextureFileData table with the given format's header
+    fn build_texture_file_data(magic: &[u8; 4]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(magic);
+        if magic == b"WDC5" {
+            header.extend_from_slice(&5u32.to_le_bytes());
+            header.extend_from_slice(&[0; 128]);
+        }
+        let field_info_size = 2 * 24;
+        let records_start = header.len() + 68 + 40 + 2 * 4 + field_info_size;
+        let mut buf = header;
+        for v in [1u32, 2, 5, 0, 0, 0, 100, 100, 0] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0; 4]); // flags, id_index
+        for v in [2u32, 0, 0, field_info_size as u32, 0, 0, 1] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0; 8]); // tact_key_hash
+        for v in [records_start as u32, 1, 0, 0, 0, 0, 0, 0] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.extend_from_slice(&[24, 0, 0, 0, 0, 0, 1, 0]);
+        for (offset, size) in [(0u16, 8u16), (8, 32)] {
+            buf.extend_from_slice(&offset.to_le_bytes());
+            buf.extend_from_slice(&size.to_le_bytes());
+            buf.extend_from_slice(&[0; 20]); // no additional data, StorageType::None
+        }
+        assert_eq!(buf.len(), records_start);
+        buf.push(3);
+        buf.extend_from_slice(&1234u32.to_le_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_db2_formats() {
+        for magic in [b"WDC3", b"WDC4", b"WDC5"] {
+            let data = build_texture_file_data(magic);
+            let db: DatabaseTable<TextureFileDataRecord> = DatabaseTable::new(&data).unwrap();
+            let record = db.get_record(100).unwrap();
+            assert_eq!(record._usage_type, 3);
+            assert_eq!(record.material_resources_id, 1234);
+        }
+        let data = build_texture_file_data(b"WDC2");
+        assert!(DatabaseTable::<TextureFileDataRecord>::new(&data).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_sections() {
+        let mut data = build_texture_file_data(b"WDC4");
+        data[72..80].copy_from_slice(&0xFA505078126ACB3Eu64.to_le_bytes());
+        let records_start = data.len() - 5;
+
+        // the section was decrypted when the file was extracted
+        let db: DatabaseTable<TextureFileDataRecord> = DatabaseTable::new(&data).unwrap();
+        assert_eq!(db.get_record(100).unwrap().material_resources_id, 1234);
+
+        // the key was missing when the file was extracted
+        data[records_start..].fill(0);
+        let db: DatabaseTable<TextureFileDataRecord> = DatabaseTable::new(&data).unwrap();
+        assert!(db.get_record(100).is_none());
+    }
 }
@@ -0,0 +1,879 @@
+use std::collections::HashMap;
+
+use deku::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use super::db::{StorageType, Wdc4Db2File};
+
+// Reads any DB2 table into generic rows, using a definition from WoWDBDefs
+// (https://github.com/wowdev/WoWDBDefs) to name and type its columns. This
+// is slower than the hand-written records in db.rs, but works for tables we
+// haven't written structs for.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DbdType {
+    Int,
+    Float,
+    String,
+    LocString,
+}
+
+#[derive(Debug, Clone)]
+pub struct DbdColumn {
+    pub name: String,
+    pub column_type: DbdType,
+    pub foreign_key: Option<(String, String)>, // (table, column)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WowBuild {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub build: u32,
+}
+
+impl WowBuild {
+    // e.g. "3.4.3.54261"
+    pub fn parse(s: &str) -> Result<WowBuild, String> {
+        let parts = s.trim().split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|e| format!("invalid build {}: {:?}", s, e))?;
+        match parts.as_slice() {
+            &[major, minor, patch, build] => Ok(WowBuild { major, minor, patch, build }),
+            _ => Err(format!("invalid build {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DbdField {
+    pub name: String,
+    pub size: Option<u32>, // in bits, only present for ints
+    pub signed: bool,
+    pub array_size: Option<usize>,
+    pub is_id: bool,
+    pub is_inline: bool,
+    pub is_relation: bool,
+}
+
+impl DbdField {
+    fn element_count(&self) -> usize {
+        self.array_size.unwrap_or(1)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DbdDefinition {
+    pub builds: Vec<(WowBuild, WowBuild)>, // inclusive ranges
+    pub layout_hashes: Vec<u32>,
+    pub fields: Vec<DbdField>,
+}
+
+impl DbdDefinition {
+    pub fn matches_build(&self, build: &WowBuild) -> bool {
+        self.builds.iter().any(|(min, max)| min <= build && build <= max)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DbdFile {
+    pub columns: Vec<DbdColumn>,
+    pub definitions: Vec<DbdDefinition>,
+}
+
+impl DbdFile {
+    pub fn parse(text: &str) -> Result<DbdFile, String> {
+        let mut columns = Vec::new();
+        let mut definitions = Vec::new();
+        let mut current: Option<DbdDefinition> = None;
+        let mut in_columns = false;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                in_columns = false;
+                if let Some(definition) = current.take() {
+                    definitions.push(definition);
+                }
+                continue;
+            }
+            if line == "COLUMNS" {
+                in_columns = true;
+                continue;
+            }
+            if in_columns {
+                columns.push(DbdFile::parse_column(line)?);
+                continue;
+            }
+            let definition = current.get_or_insert_with(DbdDefinition::default);
+            if let Some(builds) = line.strip_prefix("BUILD ") {
+                for build in builds.split(',') {
+                    let range = match build.split_once('-') {
+                        Some((min, max)) => (WowBuild::parse(min)?, WowBuild::parse(max)?),
+                        None => (WowBuild::parse(build)?, WowBuild::parse(build)?),
+                    };
+                    definition.builds.push(range);
+                }
+            } else if let Some(hashes) = line.strip_prefix("LAYOUT ") {
+                for hash in hashes.split(',') {
+                    let hash = u32::from_str_radix(hash.trim(), 16)
+                        .map_err(|e| format!("invalid layout hash {}: {:?}", hash, e))?;
+                    definition.layout_hashes.push(hash);
+                }
+            } else if line.starts_with("COMMENT") {
+                continue;
+            } else {
+                definition.fields.push(DbdFile::parse_field(line)?);
+            }
+        }
+        if let Some(definition) = current.take() {
+            definitions.push(definition);
+        }
+        Ok(DbdFile { columns, definitions })
+    }
+
+    // e.g. "int<Map::ID> MapID // comment"
+    fn parse_column(line: &str) -> Result<DbdColumn, String> {
+        let line = strip_comment(line);
+        let (type_str, name) = line.split_once(' ')
+            .ok_or(format!("invalid column: {}", line))?;
+        let (type_name, foreign_key) = match type_str.split_once('<') {
+            Some((type_name, rest)) => {
+                let reference = rest.trim_end_matches('>');
+                let (table, column) = reference.split_once("::")
+                    .ok_or(format!("invalid foreign key: {}", reference))?;
+                (type_name, Some((table.to_string(), column.to_string())))
+            },
+            None => (type_str, None),
+        };
+        let column_type = match type_name {
+            "int" => DbdType::Int,
+            "float" => DbdType::Float,
+            "string" => DbdType::String,
+            "locstring" => DbdType::LocString,
+            _ => return Err(format!("unknown column type {}", type_name)),
+        };
+        Ok(DbdColumn {
+            // unverified names are marked with a trailing '?'
+            name: name.trim().trim_end_matches('?').to_string(),
+            column_type,
+            foreign_key,
+        })
+    }
+
+    // e.g. "$noninline,id$ID<32>", "Flags<u8>[2]", "Name_lang"
+    fn parse_field(line: &str) -> Result<DbdField, String> {
+        let mut rest = strip_comment(line);
+        let mut is_id = false;
+        let mut is_inline = true;
+        let mut is_relation = false;
+        if let Some(annotated) = rest.strip_prefix('$') {
+            let (annotations, after) = annotated.split_once('$')
+                .ok_or(format!("invalid field annotation: {}", line))?;
+            for annotation in annotations.split(',') {
+                match annotation {
+                    "id" => is_id = true,
+                    "noninline" => is_inline = false,
+                    "relation" => is_relation = true,
+                    _ => {},
+                }
+            }
+            rest = after;
+        }
+        let mut array_size = None;
+        if let Some((before, array)) = rest.split_once('[') {
+            let count = array.trim_end_matches(']').parse::<usize>()
+                .map_err(|e| format!("invalid array size in {}: {:?}", line, e))?;
+            array_size = Some(count);
+            rest = before;
+        }
+        let mut size = None;
+        let mut signed = true;
+        if let Some((before, size_str)) = rest.split_once('<') {
+            let mut size_str = size_str.trim_end_matches('>');
+            if let Some(unsigned_size) = size_str.strip_prefix('u') {
+                signed = false;
+                size_str = unsigned_size;
+            }
+            size = Some(size_str.parse::<u32>()
+                .map_err(|e| format!("invalid field size in {}: {:?}", line, e))?);
+            rest = before;
+        }
+        Ok(DbdField {
+            name: rest.to_string(),
+            size,
+            signed,
+            array_size,
+            is_id,
+            is_inline,
+            is_relation,
+        })
+    }
+
+    pub fn get_column(&self, name: &str) -> Option<&DbdColumn> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    // prefers a definition matching both the layout hash and build, then
+    // either one alone
+    pub fn find_definition(&self, build: &WowBuild, layout_hash: Option<u32>) -> Option<&DbdDefinition> {
+        let has_layout = |definition: &DbdDefinition| match layout_hash {
+            Some(hash) => definition.layout_hashes.contains(&hash),
+            None => false,
+        };
+        self.definitions.iter().find(|definition| has_layout(definition) && definition.matches_build(build))
+            .or_else(|| self.definitions.iter().find(|definition| has_layout(definition)))
+            .or_else(|| self.definitions.iter().find(|definition| definition.matches_build(build)))
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.split_once("//") {
+        Some((before, _)) => before.trim(),
+        None => line.trim(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Db2Value {
+    Int(i64),
+    UInt(u64),
+    Float(f32),
+    String(String),
+    Array(Vec<Db2Value>),
+}
+
+impl Db2Value {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Db2Value::Int(v) => Some(*v as f64),
+            Db2Value::UInt(v) => Some(*v as f64),
+            Db2Value::Float(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Db2Value::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    // treats non-arrays as single element arrays
+    pub fn get(&self, index: usize) -> Option<&Db2Value> {
+        match self {
+            Db2Value::Array(values) => values.get(index),
+            v if index == 0 => Some(v),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Db2Row {
+    pub id: u32,
+    pub values: Vec<Db2Value>, // one per field of the definition
+}
+
+#[wasm_bindgen(js_name = "WowDb2Table")]
+#[derive(Debug, Clone)]
+pub struct Db2Table {
+    columns: Vec<String>,
+    rows: Vec<Db2Row>,
+    id_indices: HashMap<u32, usize>,
+}
+
+#[wasm_bindgen(js_class = "WowDb2Table")]
+impl Db2Table {
+    // `build` is e.g. "3.4.3.54261"
+    pub fn new(data: &[u8], dbd_text: &str, build: &str) -> Result<Db2Table, String> {
+        let dbd = DbdFile::parse(dbd_text)?;
+        Db2Table::from_dbd(data, &dbd, &WowBuild::parse(build)?)
+    }
+
+    pub fn get_ids(&self) -> Vec<u32> {
+        self.rows.iter().map(|row| row.id).collect()
+    }
+
+    pub fn get_column_names(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    pub fn get_number(&self, id: u32, column: &str, index: usize) -> Option<f64> {
+        self.get_value(id, column)?.get(index)?.as_f64()
+    }
+
+    pub fn get_string(&self, id: u32, column: &str, index: usize) -> Option<String> {
+        self.get_value(id, column)?.get(index)?.as_str().map(|s| s.to_string())
+    }
+}
+
+// rust-only interface
+impl Db2Table {
+    pub fn from_dbd(data: &[u8], dbd: &DbdFile, build: &WowBuild) -> Result<Db2Table, String> {
+        let (_, db2) = Wdc4Db2File::from_bytes((data, 0))
+            .map_err(|e| format!("{:?}", e))?;
+        let definition = dbd.find_definition(build, Some(db2.header.layout_hash))
+            .ok_or(format!("no definition for build {:?} or layout {:08X}", build, db2.header.layout_hash))?;
+        let mut field_types = Vec::with_capacity(definition.fields.len());
+        for field in &definition.fields {
+            let column = dbd.get_column(&field.name)
+                .ok_or(format!("definition field {} has no column", field.name))?;
+            field_types.push(column.column_type);
+        }
+        let reader = TableReader::new(data, &db2, definition, field_types)?;
+        let rows = reader.read_rows()?;
+
+        let mut id_indices = HashMap::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            id_indices.entry(row.id).or_insert(i);
+        }
+        Ok(Db2Table {
+            columns: definition.fields.iter().map(|field| field.name.clone()).collect(),
+            rows,
+            id_indices,
+        })
+    }
+
+    pub fn rows(&self) -> &[Db2Row] {
+        &self.rows
+    }
+
+    pub fn get_row(&self, id: u32) -> Option<&Db2Row> {
+        Some(&self.rows[*self.id_indices.get(&id)?])
+    }
+
+    pub fn column_index(&self, column: &str) -> Option<usize> {
+        self.columns.iter().position(|name| name == column)
+    }
+
+    pub fn get_value(&self, id: u32, column: &str) -> Option<&Db2Value> {
+        let index = self.column_index(column)?;
+        self.get_row(id)?.values.get(index)
+    }
+}
+
+const SPARSE_TABLE_FLAG: u16 = 0x01;
+
+struct TableReader<'a> {
+    data: &'a [u8],
+    db2: &'a Wdc4Db2File,
+    definition: &'a DbdDefinition,
+    field_types: Vec<DbdType>,
+    // for each definition field, its index among the file's fields if inline
+    file_fields: Vec<Option<usize>>,
+    strings: Vec<u8>, // every section's string table, concatenated
+    total_record_count: usize,
+}
+
+// where each part of a section lives in the file
+struct SectionLayout {
+    records_start: usize,
+    id_list_start: usize,
+    copy_table_start: usize,
+    offset_map_start: usize,
+    relationship_start: usize,
+    offset_map_id_list_start: usize,
+}
+
+impl<'a> TableReader<'a> {
+    fn new(data: &'a [u8], db2: &'a Wdc4Db2File, definition: &'a DbdDefinition, field_types: Vec<DbdType>) -> Result<Self, String> {
+        let mut file_fields = Vec::with_capacity(definition.fields.len());
+        let mut next_file_field = 0;
+        for field in &definition.fields {
+            if field.is_inline {
+                file_fields.push(Some(next_file_field));
+                next_file_field += 1;
+            } else {
+                file_fields.push(None);
+            }
+        }
+        if next_file_field != db2.header.field_count as usize {
+            return Err(format!("definition has {} inline fields, but the file has {}", next_file_field, db2.header.field_count));
+        }
+
+        let sparse = db2.header.flags & SPARSE_TABLE_FLAG != 0;
+        let mut strings = Vec::new();
+        let mut total_record_count = 0;
+        for section in &db2.section_headers {
+            if sparse {
+                continue;
+            }
+            let start = section.file_offset as usize + section.record_count as usize * db2.header.record_size as usize;
+            let string_table = data.get(start..start + section.string_table_size as usize)
+                .ok_or("string table out of bounds")?;
+            strings.extend_from_slice(string_table);
+            total_record_count += section.record_count as usize;
+        }
+
+        Ok(TableReader {
+            data,
+            db2,
+            definition,
+            field_types,
+            file_fields,
+            strings,
+            total_record_count,
+        })
+    }
+
+    fn read_u32(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.data.get(offset..offset + 4).ok_or("read out of bounds")?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_rows(&self) -> Result<Vec<Db2Row>, String> {
+        let sparse = self.db2.header.flags & SPARSE_TABLE_FLAG != 0;
+        let record_size = self.db2.header.record_size as usize;
+        let mut rows = Vec::new();
+        let mut global_record_index = 0;
+        for (section_index, section) in self.db2.section_headers.iter().enumerate() {
+            let record_count = if sparse { section.offset_map_id_count } else { section.record_count } as usize;
+            if !self.db2.is_section_readable(self.data, section_index) {
+                global_record_index += record_count;
+                continue;
+            }
+            let layout = self.section_layout(section, sparse);
+
+            let mut ids = Vec::new();
+            for i in 0..section.id_list_size as usize / 4 {
+                ids.push(self.read_u32(layout.id_list_start + i * 4)?);
+            }
+            if sparse && ids.is_empty() {
+                for i in 0..record_count {
+                    ids.push(self.read_u32(layout.offset_map_id_list_start + i * 4)?);
+                }
+            }
+
+            let mut relations = HashMap::new();
+            if section.relationship_data_size > 0 {
+                let entry_count = self.read_u32(layout.relationship_start)? as usize;
+                for i in 0..entry_count {
+                    let entry = layout.relationship_start + 12 + i * 8;
+                    relations.insert(self.read_u32(entry + 4)?, self.read_u32(entry)?);
+                }
+            }
+
+            let section_start = rows.len();
+            for i in 0..record_count {
+                let record_id = ids.get(i).copied();
+                let relation = relations.get(&(i as u32)).copied();
+                let row = if sparse {
+                    let offset = self.read_u32(layout.offset_map_start + i * 6)? as usize;
+                    self.read_sparse_record(offset, record_id, relation)?
+                } else {
+                    let offset = layout.records_start + i * record_size;
+                    self.read_record(offset, global_record_index + i, record_id, relation)?
+                };
+                rows.push(row);
+            }
+            global_record_index += record_count;
+
+            for i in 0..section.copy_table_count as usize {
+                let entry = layout.copy_table_start + i * 8;
+                let new_id = self.read_u32(entry)?;
+                let old_id = self.read_u32(entry + 4)?;
+                if let Some(old_row) = rows[section_start..].iter().find(|row| row.id == old_id) {
+                    let mut new_row = old_row.clone();
+                    new_row.id = new_id;
+                    if let Some(id_index) = self.definition.fields.iter().position(|field| field.is_id) {
+                        new_row.values[id_index] = Db2Value::UInt(new_id as u64);
+                    }
+                    rows.push(new_row);
+                }
+            }
+        }
+        Ok(rows)
+    }
+
+    fn section_layout(&self, section: &super::db::Wdc4Db2SectionHeader, sparse: bool) -> SectionLayout {
+        let records_start = section.file_offset as usize;
+        let id_list_start = if sparse {
+            section.offset_records_end as usize
+        } else {
+            records_start
+                + section.record_count as usize * self.db2.header.record_size as usize
+                + section.string_table_size as usize
+        };
+        let copy_table_start = id_list_start + section.id_list_size as usize;
+        let offset_map_start = copy_table_start + section.copy_table_count as usize * 8;
+        let relationship_start = offset_map_start + section.offset_map_id_count as usize * 6;
+        let offset_map_id_list_start = relationship_start + section.relationship_data_size as usize;
+        SectionLayout {
+            records_start,
+            id_list_start,
+            copy_table_start,
+            offset_map_start,
+            relationship_start,
+            offset_map_id_list_start,
+        }
+    }
+
+    fn finish_row(&self, mut values: Vec<Db2Value>, record_id: Option<u32>, relation: Option<u32>) -> Result<Db2Row, String> {
+        let mut id = record_id;
+        for (field, value) in self.definition.fields.iter().zip(values.iter_mut()) {
+            if field.is_relation && !field.is_inline {
+                *value = Db2Value::UInt(relation.unwrap_or(0) as u64);
+            }
+            if field.is_id {
+                if field.is_inline {
+                    id = id.or(match value {
+                        Db2Value::Int(v) => Some(*v as u32),
+                        Db2Value::UInt(v) => Some(*v as u32),
+                        _ => None,
+                    });
+                } else {
+                    *value = Db2Value::UInt(record_id.unwrap_or(0) as u64);
+                }
+            }
+        }
+        let id = id.ok_or("couldn't determine record ID")?;
+        Ok(Db2Row { id, values })
+    }
+
+    fn read_record(&self, offset: usize, global_record_index: usize, record_id: Option<u32>, relation: Option<u32>) -> Result<Db2Row, String> {
+        let record = self.data.get(offset..offset + self.db2.header.record_size as usize)
+            .ok_or("record out of bounds")?;
+
+        // common data is keyed by record ID, so resolve the ID first if it's
+        // inline
+        let mut id = record_id;
+        if id.is_none() {
+            let id_field = self.definition.fields.iter().position(|field| field.is_id && field.is_inline)
+                .ok_or("table has no ID list or inline ID")?;
+            id = Some(self.read_field_raw(record, id_field, 0, 0)? as u32);
+        }
+
+        let mut values = Vec::with_capacity(self.definition.fields.len());
+        for (i, field) in self.definition.fields.iter().enumerate() {
+            if self.file_fields[i].is_none() {
+                values.push(Db2Value::UInt(0)); // filled in by finish_row
+                continue;
+            }
+            let mut elements = Vec::with_capacity(field.element_count());
+            for j in 0..field.element_count() {
+                let raw = self.read_field_raw(record, i, j, id.unwrap_or(0))?;
+                let value = match self.field_types[i] {
+                    DbdType::String | DbdType::LocString => {
+                        let field_offset = self.element_offset_bits(i, j) / 8;
+                        let virtual_offset = global_record_index * record.len() + field_offset + raw as usize;
+                        let string_offset = virtual_offset.checked_sub(self.total_record_count * record.len());
+                        match string_offset {
+                            Some(string_offset) if raw != 0 => Db2Value::String(read_c_string(&self.strings, string_offset)?),
+                            _ => Db2Value::String(String::new()),
+                        }
+                    },
+                    DbdType::Float => Db2Value::Float(f32::from_bits(raw as u32)),
+                    DbdType::Int => self.int_value(i, raw),
+                };
+                elements.push(value);
+            }
+            values.push(pack_elements(field, elements));
+        }
+        self.finish_row(values, record_id.or(id), relation)
+    }
+
+    fn element_bits(&self, field_index: usize) -> usize {
+        let field = &self.definition.fields[field_index];
+        match self.field_types[field_index] {
+            DbdType::Int => field.size.unwrap_or(32) as usize,
+            _ => 32,
+        }
+    }
+
+    fn element_offset_bits(&self, field_index: usize, element: usize) -> usize {
+        let file_field = self.file_fields[field_index].expect("non-inline field");
+        let info = &self.db2.field_storage_info[file_field];
+        info.field_offset_bits as usize + element * self.element_bits(field_index)
+    }
+
+    fn read_field_raw(&self, record: &[u8], field_index: usize, element: usize, record_id: u32) -> Result<u64, String> {
+        let file_field = self.file_fields[field_index].ok_or("non-inline field")?;
+        let info = &self.db2.field_storage_info[file_field];
+        let field_offset = info.field_offset_bits as usize;
+        let field_size = info.field_size_bits as usize;
+        let result = match &info.storage_type {
+            StorageType::None { .. } => {
+                read_bits(record, self.element_offset_bits(field_index, element), self.element_bits(field_index))?
+            },
+            StorageType::Bitpacked { size_bits, .. } => read_bits(record, field_offset, *size_bits as usize)?,
+            StorageType::BitpackedSigned { size_bits, .. } => {
+                let v = read_bits(record, field_offset, *size_bits as usize)?;
+                sign_extend(v, *size_bits) as u64
+            },
+            StorageType::CommonData { default_value, .. } => {
+                self.db2.get_common_data(file_field, record_id).unwrap_or(*default_value) as u64
+            },
+            StorageType::BitpackedIndexed { .. } => {
+                let index = read_bits(record, field_offset, field_size)? as usize;
+                self.palette_value(file_field, index)? as u64
+            },
+            StorageType::BitpackedIndexedArray { array_count, .. } => {
+                let index = read_bits(record, field_offset, field_size)? as usize;
+                self.palette_value(file_field, index * *array_count as usize + element)? as u64
+            },
+        };
+        Ok(result)
+    }
+
+    fn palette_value(&self, file_field: usize, palette_index: usize) -> Result<u32, String> {
+        let palette_size = self.db2.field_storage_info[file_field].additional_data_size as usize / 4;
+        if palette_index >= palette_size {
+            return Err(format!("palette index {} out of bounds for field {}", palette_index, file_field));
+        }
+        Ok(self.db2.get_palette_data(file_field, palette_index))
+    }
+
+    fn int_value(&self, field_index: usize, raw: u64) -> Db2Value {
+        let field = &self.definition.fields[field_index];
+        let size = field.size.unwrap_or(32);
+        if field.signed {
+            Db2Value::Int(sign_extend(raw, size))
+        } else {
+            Db2Value::UInt(raw)
+        }
+    }
+
+    // sparse records are packed back to back, with strings stored inline
+    fn read_sparse_record(&self, offset: usize, record_id: Option<u32>, relation: Option<u32>) -> Result<Db2Row, String> {
+        let mut position = offset;
+        let mut values = Vec::with_capacity(self.definition.fields.len());
+        for (i, field) in self.definition.fields.iter().enumerate() {
+            let file_field = match self.file_fields[i] {
+                Some(file_field) => file_field,
+                None => {
+                    values.push(Db2Value::UInt(0));
+                    continue;
+                },
+            };
+            let field_bytes = (32 - self.db2.field_structs[file_field].size as i32) as usize / 8;
+            let mut elements = Vec::with_capacity(field.element_count());
+            for _ in 0..field.element_count() {
+                let value = match self.field_types[i] {
+                    DbdType::String | DbdType::LocString => {
+                        let string = read_c_string(self.data, position)?;
+                        position += string.len() + 1;
+                        Db2Value::String(string)
+                    },
+                    field_type => {
+                        let element_bytes = (self.element_bits(i) / 8).min(field_bytes.max(1));
+                        let bytes = self.data.get(position..position + element_bytes)
+                            .ok_or("sparse record out of bounds")?;
+                        position += element_bytes;
+                        let raw = read_bits(bytes, 0, element_bytes * 8)?;
+                        match field_type {
+                            DbdType::Float => Db2Value::Float(f32::from_bits(raw as u32)),
+                            _ => self.int_value(i, raw),
+                        }
+                    },
+                };
+                elements.push(value);
+            }
+            values.push(pack_elements(field, elements));
+        }
+        self.finish_row(values, record_id, relation)
+    }
+}
+
+fn pack_elements(field: &DbdField, mut elements: Vec<Db2Value>) -> Db2Value {
+    if field.array_size.is_some() {
+        Db2Value::Array(elements)
+    } else {
+        elements.remove(0)
+    }
+}
+
+// reads `bit_count` (up to 64) bits, least significant bit first
+fn read_bits(data: &[u8], bit_offset: usize, bit_count: usize) -> Result<u64, String> {
+    if bit_count > 64 {
+        return Err(format!("field at bit {} is {} bits wide, more than 64", bit_offset, bit_count));
+    }
+    let start = bit_offset / 8;
+    let shift = bit_offset % 8;
+    let byte_count = (shift + bit_count).div_ceil(8);
+    let bytes = data.get(start..start + byte_count)
+        .ok_or(format!("field at bit {} out of bounds", bit_offset))?;
+    let mut v: u128 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        v |= (*byte as u128) << (i * 8);
+    }
+    let mask = if bit_count == 64 { u64::MAX } else { (1u64 << bit_count) - 1 };
+    Ok((v >> shift) as u64 & mask)
+}
+
+fn sign_extend(v: u64, bits: u32) -> i64 {
+    if bits == 0 || bits >= 64 {
+        return v as i64;
+    }
+    let shift = 64 - bits;
+    ((v << shift) as i64) >> shift
+}
+
+fn read_c_string(data: &[u8], offset: usize) -> Result<String, String> {
+    let bytes = data.get(offset..).ok_or(format!("string offset {} out of bounds", offset))?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DBD: &str = "COLUMNS
+int ID
+float Scale
+string Name
+int<Map::ID> MapID
+int Flags? // unverified
+int ParentID
+
+LAYOUT 12345678
+BUILD 3.4.0.43659-3.4.3.54261
+$noninline,id$ID<32>
+Scale
+Name
+MapID<u16>
+Flags<8>[2]
+$noninline,relation$ParentID<32>
+
+LAYOUT 87654321
+BUILD 1.13.2.31446, 1.13.2.31650
+$id$ID<32>
+Name
+";
+
+    #[test]
+    fn test_parse_dbd() {
+        let dbd = DbdFile::parse(TEST_DBD).unwrap();
+        assert_eq!(dbd.columns.len(), 6);
+        assert_eq!(dbd.get_column("MapID").unwrap().foreign_key, Some(("Map".to_string(), "ID".to_string())));
+        assert_eq!(dbd.get_column("Flags").unwrap().column_type, DbdType::Int);
+        assert_eq!(dbd.definitions.len(), 2);
+
+        let build = WowBuild::parse("3.4.3.54261").unwrap();
+        let definition = dbd.find_definition(&build, None).unwrap();
+        assert_eq!(definition.fields.len(), 6);
+        assert!(definition.fields[0].is_id && !definition.fields[0].is_inline);
+        assert_eq!(definition.fields[3].size, Some(16));
+        assert!(!definition.fields[3].signed);
+        assert_eq!(definition.fields[4].array_size, Some(2));
+        assert!(definition.fields[5].is_relation);
+
+        let classic = WowBuild::parse("1.13.2.31650").unwrap();
+        assert_eq!(dbd.find_definition(&classic, None).unwrap().fields.len(), 2);
+        assert_eq!(dbd.find_definition(&build, Some(0x87654321)).unwrap().fields.len(), 2);
+        assert!(dbd.find_definition(&WowBuild::parse("2.0.0.1").unwrap(), None).is_none());
+    }
+
+    fn push_u32(buf: &mut Vec<u8>, v: u32) {
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    // a two-record WDC4 table with plain, bitpacked and palette fields, an ID
+    // list, a relationship map and a copy table
+    fn build_test_table() -> Vec<u8> {
+        let record_size = 12;
+        let strings = b"\0first\0second\0";
+        let field_count = 4;
+        let header_size = 72;
+        let section_header_size = 40;
+        let field_struct_size = 4 * field_count;
+        let field_info_size = 24 * field_count;
+        let palette = [7u32, 9, 11, 13]; // two entries of Flags[2]
+        let records_start = header_size + section_header_size + field_struct_size + field_info_size + palette.len() * 4;
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"WDC4");
+        for v in [2, field_count as u32, record_size, strings.len() as u32, 0, 0x12345678, 10, 11, 0] {
+            push_u32(&mut buf, v);
+        }
+        buf.extend_from_slice(&0u16.to_le_bytes()); // flags
+        buf.extend_from_slice(&0u16.to_le_bytes()); // id_index
+        for v in [field_count as u32, 0, 0, field_info_size as u32, 0, palette.len() as u32 * 4, 1] {
+            push_u32(&mut buf, v);
+        }
+
+        // section header
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        for v in [records_start as u32, 2, strings.len() as u32, 0, 8, 12 + 8, 0, 1] {
+            push_u32(&mut buf, v);
+        }
+
+        // field structs: Scale (32 bits), Name (32), MapID (16), Flags (palette)
+        for size in [0i16, 0, 16, 0] {
+            buf.extend_from_slice(&size.to_le_bytes());
+            buf.extend_from_slice(&0u16.to_le_bytes());
+        }
+
+        // field storage info
+        let field_infos: [(u16, u16, u32, u32, [u32; 3]); 4] = [
+            (0, 32, 0, 0, [0, 0, 0]),
+            (32, 32, 0, 0, [0, 0, 0]),
+            (64, 16, 0, 1, [64, 16, 0]),
+            (80, 1, palette.len() as u32 * 4, 4, [80, 1, 2]),
+        ];
+        for (offset, size, additional, storage_type, params) in field_infos {
+            buf.extend_from_slice(&offset.to_le_bytes());
+            buf.extend_from_slice(&size.to_le_bytes());
+            push_u32(&mut buf, additional);
+            push_u32(&mut buf, storage_type);
+            for v in params {
+                push_u32(&mut buf, v);
+            }
+        }
+        for v in palette {
+            push_u32(&mut buf, v);
+        }
+        assert_eq!(buf.len(), records_start);
+
+        // records. string offsets are relative to the field's own position
+        let name_offsets = [1 + 24 - 4, 7 + 24 - 16];
+        let map_ids = [530u16, 571];
+        let flag_indices = [1u8, 0];
+        for i in 0..2 {
+            buf.extend_from_slice(&(1.5f32 * (i + 1) as f32).to_le_bytes());
+            push_u32(&mut buf, name_offsets[i]);
+            buf.extend_from_slice(&map_ids[i].to_le_bytes());
+            buf.extend_from_slice(&[flag_indices[i], 0]);
+        }
+        buf.extend_from_slice(strings);
+
+        // ID list
+        push_u32(&mut buf, 10);
+        push_u32(&mut buf, 11);
+        // copy table: row 20 is a copy of row 11
+        push_u32(&mut buf, 20);
+        push_u32(&mut buf, 11);
+        // relationship map: record 1 -> ParentID 99
+        for v in [1, 99, 99, 99, 1] {
+            push_u32(&mut buf, v);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_read_table() {
+        let dbd = DbdFile::parse(TEST_DBD).unwrap();
+        let data = build_test_table();
+        let table = Db2Table::from_dbd(&data, &dbd, &WowBuild::parse("3.4.1.47014").unwrap()).unwrap();
+        assert_eq!(table.get_ids(), vec![10, 11, 20]);
+        assert_eq!(table.get_value(10, "ID"), Some(&Db2Value::UInt(10)));
+        assert_eq!(table.get_value(10, "Scale"), Some(&Db2Value::Float(1.5)));
+        assert_eq!(table.get_string(10, "Name", 0), Some("first".to_string()));
+        assert_eq!(table.get_string(11, "Name", 0), Some("second".to_string()));
+        assert_eq!(table.get_number(11, "MapID", 0), Some(571.0));
+        assert_eq!(table.get_number(10, "Flags", 0), Some(11.0));
+        assert_eq!(table.get_number(10, "Flags", 1), Some(13.0));
+        assert_eq!(table.get_number(11, "Flags", 1), Some(9.0));
+        assert_eq!(table.get_number(10, "ParentID", 0), Some(0.0));
+        assert_eq!(table.get_number(11, "ParentID", 0), Some(99.0));
+        assert_eq!(table.get_string(20, "Name", 0), Some("second".to_string()));
+        assert_eq!(table.get_number(20, "ID", 0), Some(20.0));
+
+        // field sizes come from the file, so oversized ones are errors
+        assert_eq!(read_bits(&[0xff; 16], 4, 64), Ok(u64::MAX));
+        assert!(read_bits(&[0xff; 16], 0, 65).is_err());
+    }
+}
@@ -0,0 +1,1297 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::fmt;
+
+use nalgebra_glm as glm;
+
+use super::{
+    adt::{Adt, ADT_VBO_INFO},
+    animation::{AnimationManager, M2CompBone, M2Track},
+    blp::Blp,
+    common::Vec3,
+    m2::{M2MaterialFlags, M2Vertex, M2},
+    skin::Skin,
+    terrain::{TerrainTextureBaker, MAP_ORIGIN, VERTICES_PER_CHUNK},
+    wmo::{Wmo, WmoGroup, WmoMaterialFlags},
+};
+
+// A minimal glTF 2.0 writer, producing self-contained binary (.glb) files
+// with all buffers and textures embedded in the BIN chunk. See
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+const CLAMP_TO_EDGE: u32 = 33071;
+const REPEAT: u32 = 10497;
+
+// WoW is Z-up, while glTF is Y-up
+const Z_UP_TO_Y_UP: [f32; 4] = [-std::f32::consts::FRAC_1_SQRT_2, 0.0, 0.0, std::f32::consts::FRAC_1_SQRT_2];
+
+// the alpha test threshold the model shader uses for AlphaKey materials
+const ALPHA_KEY_CUTOFF: f32 = 224.0 / 255.0;
+
+#[derive(Debug, Clone)]
+enum Json {
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    // for optional properties, which glTF wants left out rather than null
+    fn set(&mut self, key: &str, value: Json) {
+        if let Json::Object(fields) = self {
+            fields.push((key.to_string(), value));
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) if value.is_finite() => write!(f, "{}", value),
+            Json::Number(_) => write!(f, "0"),
+            Json::String(value) => {
+                write!(f, "\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            },
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::String(key.clone()), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self { Json::Bool(value) }
+}
+
+impl From<f32> for Json {
+    fn from(value: f32) -> Self { Json::Number(value as f64) }
+}
+
+impl From<u32> for Json {
+    fn from(value: u32) -> Self { Json::Number(value as f64) }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self { Json::Number(value as f64) }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self { Json::String(value.to_string()) }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self { Json::String(value) }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Self { Json::Array(values.into_iter().map(|value| value.into()).collect()) }
+}
+
+// GLB chunks and buffer views all need to start on 4 byte boundaries
+fn pad_to_4(data: &mut Vec<u8>, value: u8) {
+    data.resize((data.len() + 3) & !3, value);
+}
+
+fn push(list: &mut Vec<Json>, item: Json) -> usize {
+    list.push(item);
+    list.len() - 1
+}
+
+fn component_count(accessor_type: &str) -> usize {
+    match accessor_type {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" => 4,
+        "MAT4" => 16,
+        _ => unreachable!("unknown accessor type {}", accessor_type),
+    }
+}
+
+#[derive(Default)]
+struct GltfBuilder {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Json>,
+    accessors: Vec<Json>,
+    images: Vec<Json>,
+    samplers: Vec<Json>,
+    textures: Vec<Json>,
+    materials: Vec<Json>,
+    meshes: Vec<Json>,
+    nodes: Vec<Json>,
+    skins: Vec<Json>,
+    animations: Vec<Json>,
+    scene_nodes: Vec<usize>,
+    extensions_used: Vec<&'static str>,
+    // keyframe timestamps are shared between lots of tracks
+    keyframe_time_accessors: HashMap<Vec<u32>, usize>,
+}
+
+impl GltfBuilder {
+    fn add_buffer_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        pad_to_4(&mut self.buffer, 0);
+        let mut view = Json::object(vec![
+            ("buffer", 0usize.into()),
+            ("byteOffset", self.buffer.len().into()),
+            ("byteLength", data.len().into()),
+        ]);
+        if let Some(target) = target {
+            view.set("target", target.into());
+        }
+        self.buffer.extend_from_slice(data);
+        push(&mut self.buffer_views, view)
+    }
+
+    fn add_floats(&mut self, data: &[f32], accessor_type: &str, target: Option<u32>) -> usize {
+        let components = component_count(accessor_type);
+        let bytes: Vec<u8> = data.iter().flat_map(|value| value.to_le_bytes()).collect();
+        let view = self.add_buffer_view(&bytes, target);
+        let mut min = vec![f32::MAX; components];
+        let mut max = vec![f32::MIN; components];
+        for element in data.chunks_exact(components) {
+            for (i, &value) in element.iter().enumerate() {
+                min[i] = min[i].min(value);
+                max[i] = max[i].max(value);
+            }
+        }
+        let mut accessor = Json::object(vec![
+            ("bufferView", view.into()),
+            ("componentType", FLOAT.into()),
+            ("count", (data.len() / components).into()),
+            ("type", accessor_type.into()),
+        ]);
+        if data.len() >= components {
+            accessor.set("min", min.into());
+            accessor.set("max", max.into());
+        }
+        push(&mut self.accessors, accessor)
+    }
+
+    fn add_bytes(&mut self, data: &[u8], accessor_type: &str, normalized: bool) -> usize {
+        let view = self.add_buffer_view(data, Some(ARRAY_BUFFER));
+        let mut accessor = Json::object(vec![
+            ("bufferView", view.into()),
+            ("componentType", UNSIGNED_BYTE.into()),
+            ("count", (data.len() / component_count(accessor_type)).into()),
+            ("type", accessor_type.into()),
+        ]);
+        if normalized {
+            accessor.set("normalized", true.into());
+        }
+        push(&mut self.accessors, accessor)
+    }
+
+    fn add_indices(&mut self, indices: &[u32]) -> usize {
+        let bytes: Vec<u8> = indices.iter().flat_map(|index| index.to_le_bytes()).collect();
+        let view = self.add_buffer_view(&bytes, Some(ELEMENT_ARRAY_BUFFER));
+        push(&mut self.accessors, Json::object(vec![
+            ("bufferView", view.into()),
+            ("componentType", UNSIGNED_INT.into()),
+            ("count", indices.len().into()),
+            ("type", "SCALAR".into()),
+        ]))
+    }
+
+    // timestamps are in milliseconds, and must be strictly increasing
+    fn add_keyframe_times(&mut self, times: &[u32]) -> usize {
+        if let Some(&accessor) = self.keyframe_time_accessors.get(times) {
+            return accessor;
+        }
+        let seconds: Vec<f32> = times.iter().map(|&time| time as f32 / 1000.0).collect();
+        let accessor = self.add_floats(&seconds, "SCALAR", None);
+        self.keyframe_time_accessors.insert(times.to_vec(), accessor);
+        accessor
+    }
+
+    fn add_png_texture(&mut self, png: &[u8], wrap_s: u32, wrap_t: u32) -> usize {
+        let view = self.add_buffer_view(png, None);
+        let image = push(&mut self.images, Json::object(vec![
+            ("bufferView", view.into()),
+            ("mimeType", "image/png".into()),
+        ]));
+        let sampler = push(&mut self.samplers, Json::object(vec![
+            ("wrapS", wrap_s.into()),
+            ("wrapT", wrap_t.into()),
+        ]));
+        push(&mut self.textures, Json::object(vec![
+            ("source", image.into()),
+            ("sampler", sampler.into()),
+        ]))
+    }
+
+    fn use_extension(&mut self, name: &'static str) {
+        if !self.extensions_used.contains(&name) {
+            self.extensions_used.push(name);
+        }
+    }
+
+    fn into_glb(self) -> Vec<u8> {
+        let mut root = Json::object(vec![
+            ("asset", Json::object(vec![
+                ("version", "2.0".into()),
+                ("generator", "noclip.website".into()),
+            ])),
+            ("scene", 0usize.into()),
+            ("scenes", vec![Json::object(vec![("nodes", self.scene_nodes.into())])].into()),
+        ]);
+        let lists = vec![
+            ("bufferViews", self.buffer_views),
+            ("accessors", self.accessors),
+            ("images", self.images),
+            ("samplers", self.samplers),
+            ("textures", self.textures),
+            ("materials", self.materials),
+            ("meshes", self.meshes),
+            ("nodes", self.nodes),
+            ("skins", self.skins),
+            ("animations", self.animations),
+        ];
+        for (key, list) in lists {
+            if !list.is_empty() {
+                root.set(key, list.into());
+            }
+        }
+        if !self.extensions_used.is_empty() {
+            root.set("extensionsUsed", self.extensions_used.into());
+        }
+
+        let mut bin = self.buffer;
+        if !bin.is_empty() {
+            pad_to_4(&mut bin, 0);
+            root.set("buffers", vec![Json::object(vec![("byteLength", bin.len().into())])].into());
+        }
+        let mut json = root.to_string().into_bytes();
+        pad_to_4(&mut json, b' ');
+
+        let mut length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            length += 8 + bin.len();
+        }
+        let mut result = Vec::with_capacity(length);
+        result.extend_from_slice(b"glTF");
+        result.extend_from_slice(&2u32.to_le_bytes());
+        result.extend_from_slice(&(length as u32).to_le_bytes());
+        result.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        result.extend_from_slice(b"JSON");
+        result.extend_from_slice(&json);
+        if !bin.is_empty() {
+            result.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            result.extend_from_slice(b"BIN\0");
+            result.extend_from_slice(&bin);
+        }
+        result
+    }
+}
+
+#[derive(Default)]
+struct AnimationBuilder {
+    samplers: Vec<Json>,
+    channels: Vec<Json>,
+}
+
+impl AnimationBuilder {
+    // `values` holds one translation/scale (3 floats) or rotation (4 floats)
+    // per timestamp. Repeated timestamps are dropped, since glTF requires
+    // them to be strictly increasing
+    fn add_channel(&mut self, builder: &mut GltfBuilder, node: usize, path: &str, times: &[u32], interpolation_type: u16, values: &[f32]) {
+        let accessor_type = if path == "rotation" { "VEC4" } else { "VEC3" };
+        let components = component_count(accessor_type);
+        let mut kept_times: Vec<u32> = Vec::with_capacity(times.len());
+        let mut kept_values = Vec::with_capacity(values.len());
+        for (&time, value) in times.iter().zip(values.chunks_exact(components)) {
+            if kept_times.last().is_some_and(|&last| time <= last) {
+                continue;
+            }
+            kept_times.push(time);
+            kept_values.extend_from_slice(value);
+        }
+        if kept_times.is_empty() {
+            return;
+        }
+
+        let input = builder.add_keyframe_times(&kept_times);
+        let output = builder.add_floats(&kept_values, accessor_type, None);
+        // the renderer only supports none (0) and linear (1) interpolation,
+        // so treat the spline types as linear too
+        let interpolation = if interpolation_type == 0 { "STEP" } else { "LINEAR" };
+        let sampler = push(&mut self.samplers, Json::object(vec![
+            ("input", input.into()),
+            ("output", output.into()),
+            ("interpolation", interpolation.into()),
+        ]));
+        self.channels.push(Json::object(vec![
+            ("sampler", sampler.into()),
+            ("target", Json::object(vec![
+                ("node", node.into()),
+                ("path", path.into()),
+            ])),
+        ]));
+    }
+}
+
+// the keyframes a track has for the given sequence, or for global sequence
+// tracks, their single looping timeline
+fn sequence_keyframes<T>(track: &M2Track<T>, sequence_index: usize) -> Option<(&[u32], &[T])> {
+    let index = if track.global_sequence >= 0 { 0 } else { sequence_index };
+    let times = track.timestamps().get(index)?;
+    let values = track.values().get(index)?;
+    if times.is_empty() || times.len() != values.len() {
+        return None;
+    }
+    Some((times, values))
+}
+
+// bones are sorted such that parents always come first
+fn bone_parent(bones: &[M2CompBone], index: usize) -> Option<usize> {
+    let parent = bones[index].parent_bone;
+    if parent >= 0 && (parent as usize) < index {
+        Some(parent as usize)
+    } else {
+        None
+    }
+}
+
+// M2 bones rotate and scale around their pivot, so each bone becomes a node
+// sitting at its pivot. Relative to its parent's node, that's the offset
+// between their pivots plus the bone's animated translation
+fn bone_pivot_offset(bones: &[M2CompBone], index: usize) -> [f32; 3] {
+    let pivot = bones[index].pivot;
+    match bone_parent(bones, index) {
+        Some(parent) => {
+            let parent_pivot = bones[parent].pivot;
+            [pivot.x - parent_pivot.x, pivot.y - parent_pivot.y, pivot.z - parent_pivot.z]
+        },
+        None => [pivot.x, pivot.y, pivot.z],
+    }
+}
+
+// glTF wants exactly four influences per vertex that sum to one. Influences
+// on bones that don't exist are dropped, any rounding error goes to the
+// heaviest one, and vertices with no weights at all stick to the root bone
+fn vertex_joints_and_weights(vertex: &M2Vertex, num_bones: usize) -> ([u8; 4], [u8; 4]) {
+    let mut joints = [0; 4];
+    let mut weights = [0; 4];
+    for i in 0..4 {
+        if vertex.bone_weights[i] > 0 && (vertex.bone_indices[i] as usize) < num_bones {
+            joints[i] = vertex.bone_indices[i];
+            weights[i] = vertex.bone_weights[i];
+        }
+    }
+    let total: u32 = weights.iter().map(|&weight| weight as u32).sum();
+    if total == 0 {
+        return ([0; 4], [255, 0, 0, 0]);
+    }
+    let mut normalized_total = 0;
+    for weight in weights.iter_mut() {
+        *weight = (*weight as u32 * 255 / total) as u8;
+        normalized_total += *weight as u32;
+    }
+    let heaviest = (0..4).max_by_key(|&i| weights[i]).unwrap();
+    weights[heaviest] += (255 - normalized_total) as u8;
+    (joints, weights)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+impl AlphaMode {
+    // M2s and WMOs share blend modes. glTF has no additive or modulating
+    // blending, so those end up as regular alpha blending
+    fn from_blend_mode(blend_mode: u32) -> AlphaMode {
+        match blend_mode {
+            0 => AlphaMode::Opaque,
+            1 => AlphaMode::Mask,
+            _ => AlphaMode::Blend,
+        }
+    }
+}
+
+fn add_material(builder: &mut GltfBuilder, name: String, texture: Option<usize>, alpha_mode: AlphaMode, double_sided: bool, unlit: bool) -> usize {
+    let mut pbr = Json::object(vec![
+        ("metallicFactor", 0.0f32.into()),
+        ("roughnessFactor", 1.0f32.into()),
+    ]);
+    if let Some(texture) = texture {
+        pbr.set("baseColorTexture", Json::object(vec![("index", texture.into())]));
+    }
+    let mut material = Json::object(vec![
+        ("name", name.into()),
+        ("pbrMetallicRoughness", pbr),
+        ("doubleSided", double_sided.into()),
+    ]);
+    match alpha_mode {
+        AlphaMode::Opaque => material.set("alphaMode", "OPAQUE".into()),
+        AlphaMode::Mask => {
+            material.set("alphaMode", "MASK".into());
+            material.set("alphaCutoff", ALPHA_KEY_CUTOFF.into());
+        },
+        AlphaMode::Blend => material.set("alphaMode", "BLEND".into()),
+    }
+    if unlit {
+        builder.use_extension("KHR_materials_unlit");
+        material.set("extensions", Json::object(vec![("KHR_materials_unlit", Json::object(vec![]))]));
+    }
+    push(&mut builder.materials, material)
+}
+
+fn add_blp_texture(builder: &mut GltfBuilder, blp: &Blp, repeat_s: bool, repeat_t: bool) -> Result<usize, String> {
+    let wrap_s = if repeat_s { REPEAT } else { CLAMP_TO_EDGE };
+    let wrap_t = if repeat_t { REPEAT } else { CLAMP_TO_EDGE };
+    Ok(builder.add_png_texture(&blp.get_mip_png(0)?, wrap_s, wrap_t))
+}
+
+// An M2 along with the skin and textures needed to export it. `textures` is
+// indexed like `M2::texture_ids`, with None for textures that should be left
+// off, e.g. replaceable ones
+pub struct M2Model {
+    pub m2: M2,
+    pub skin: Skin,
+    pub textures: Vec<Option<Blp>>,
+}
+
+// adds a mesh with a primitive per skin batch. Skinned meshes get joint
+// attributes for bones 0..num_bones
+fn add_m2_mesh(builder: &mut GltfBuilder, model: &mut M2Model, num_bones: Option<usize>) -> Result<usize, String> {
+    let m2 = &mut model.m2;
+    let vertices = M2::parse_vertex_data(&m2.take_vertex_data())?;
+    let mut positions = Vec::with_capacity(vertices.len() * 3);
+    let mut normals = Vec::with_capacity(vertices.len() * 3);
+    let mut tex_coords = [Vec::with_capacity(vertices.len() * 2), Vec::with_capacity(vertices.len() * 2)];
+    let mut joints = Vec::with_capacity(vertices.len() * 4);
+    let mut weights = Vec::with_capacity(vertices.len() * 4);
+    for vertex in &vertices {
+        positions.extend_from_slice(&[vertex.position.x, vertex.position.y, vertex.position.z]);
+        normals.extend_from_slice(&unit_normal(vertex.normal.x, vertex.normal.y, vertex.normal.z));
+        for (i, coords) in tex_coords.iter_mut().enumerate() {
+            coords.extend_from_slice(&[vertex.tex_coords[i].x, vertex.tex_coords[i].y]);
+        }
+        if let Some(num_bones) = num_bones {
+            let (vertex_joints, vertex_weights) = vertex_joints_and_weights(vertex, num_bones);
+            joints.extend_from_slice(&vertex_joints);
+            weights.extend_from_slice(&vertex_weights);
+        }
+    }
+    let mut attributes = Json::object(vec![
+        ("POSITION", builder.add_floats(&positions, "VEC3", Some(ARRAY_BUFFER)).into()),
+        ("NORMAL", builder.add_floats(&normals, "VEC3", Some(ARRAY_BUFFER)).into()),
+        ("TEXCOORD_0", builder.add_floats(&tex_coords[0], "VEC2", Some(ARRAY_BUFFER)).into()),
+        ("TEXCOORD_1", builder.add_floats(&tex_coords[1], "VEC2", Some(ARRAY_BUFFER)).into()),
+    ]);
+    if num_bones.is_some() {
+        attributes.set("JOINTS_0", builder.add_bytes(&joints, "VEC4", false).into());
+        attributes.set("WEIGHTS_0", builder.add_bytes(&weights, "VEC4", true).into());
+    }
+
+    let texture_lookup = m2.take_texture_lookup();
+    let texture_flags: Vec<u32> = m2.take_legacy_textures().iter().map(|texture| texture.flags).collect();
+    let skin = &mut model.skin;
+    let indices = skin.take_indices();
+    let mut index_accessors: HashMap<usize, usize> = HashMap::new();
+    let mut gltf_textures: HashMap<usize, usize> = HashMap::new();
+    let mut gltf_materials: HashMap<(usize, Option<usize>), usize> = HashMap::new();
+    let mut primitives = Vec::with_capacity(skin.batches.len());
+    for batch in &skin.batches {
+        let submesh_index = batch.skin_submesh_index as usize;
+        let index_accessor = match index_accessors.get(&submesh_index) {
+            Some(&accessor) => accessor,
+            None => {
+                let submesh = skin.submeshes.get(submesh_index)
+                    .ok_or(format!("batch references missing submesh {}", submesh_index))?;
+                let start = submesh.get_index_start() as usize;
+                let end = start + submesh.index_count as usize;
+                let submesh_indices: Vec<u32> = indices.get(start..end)
+                    .ok_or(format!("submesh {} indices {}..{} are out of bounds", submesh_index, start, end))?
+                    .iter().map(|&index| index as u32).collect();
+                let accessor = builder.add_indices(&submesh_indices);
+                index_accessors.insert(submesh_index, accessor);
+                accessor
+            },
+        };
+
+        // only the batch's first texture maps onto a glTF base color
+        let texture_index = texture_lookup.get(batch.texture_combo_index as usize).map(|&index| index as usize);
+        let mut texture = None;
+        if let Some(texture_index) = texture_index {
+            if let Some(&gltf_texture) = gltf_textures.get(&texture_index) {
+                texture = Some(gltf_texture);
+            } else if let Some(Some(blp)) = model.textures.get(texture_index) {
+                let flags = texture_flags.get(texture_index).copied().unwrap_or(0);
+                let gltf_texture = add_blp_texture(builder, blp, flags & 0x1 > 0, flags & 0x2 > 0)?;
+                gltf_textures.insert(texture_index, gltf_texture);
+                texture = Some(gltf_texture);
+            }
+        }
+
+        let material_index = batch.material_index as usize;
+        let material = match gltf_materials.get(&(material_index, texture)) {
+            Some(&material) => material,
+            None => {
+                let source = m2.materials.get(material_index)
+                    .ok_or(format!("batch references missing material {}", material_index))?;
+                let flags = M2MaterialFlags::new(source.flags);
+                let alpha_mode = AlphaMode::from_blend_mode(source.blending_mode as u32);
+                let name = format!("{}_material{}", m2.name, material_index);
+                let material = add_material(builder, name, texture, alpha_mode, flags.two_sided, flags.unlit);
+                gltf_materials.insert((material_index, texture), material);
+                material
+            },
+        };
+
+        primitives.push(Json::object(vec![
+            ("attributes", attributes.clone()),
+            ("indices", index_accessor.into()),
+            ("material", material.into()),
+        ]));
+    }
+    Ok(push(&mut builder.meshes, Json::object(vec![
+        ("name", m2.name.clone().into()),
+        ("primitives", primitives.into()),
+    ])))
+}
+
+fn unit_normal(x: f32, y: f32, z: f32) -> [f32; 3] {
+    let length = (x * x + y * y + z * z).sqrt();
+    if length > 0.0 {
+        [x / length, y / length, z / length]
+    } else {
+        [0.0, 0.0, 1.0]
+    }
+}
+
+// Exports an M2 as a binary glTF. The mesh is skinned to a node hierarchy
+// built from `animation_manager`'s bones (so set its skeleton and add any
+// .anim files first), and every sequence with loaded keyframes becomes an
+// animation, with the stand sequence named "Stand". Billboarded bones are
+// exported unbillboarded.
+pub fn export_m2(mut model: M2Model, animation_manager: &AnimationManager) -> Result<Vec<u8>, String> {
+    let mut builder = GltfBuilder::default();
+    let bones = animation_manager.get_bones();
+    let num_bones = Some(bones.len()).filter(|&count| count > 0);
+    let skinned = num_bones.is_some();
+    let mesh = add_m2_mesh(&mut builder, &mut model, num_bones)?;
+    let name = model.m2.name.clone();
+
+    // bone i is node i, so skin joints and animation targets line up with
+    // bone indices
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); bones.len()];
+    let mut root_bones = Vec::new();
+    for i in 0..bones.len() {
+        match bone_parent(bones, i) {
+            Some(parent) => children[parent].push(i),
+            None => root_bones.push(i),
+        }
+    }
+    let mut inverse_bind_matrices = Vec::with_capacity(bones.len() * 16);
+    for (i, bone) in bones.iter().enumerate() {
+        let mut node = Json::object(vec![
+            ("name", format!("bone{}", i).into()),
+            ("translation", bone_pivot_offset(bones, i).to_vec().into()),
+        ]);
+        if !children[i].is_empty() {
+            node.set("children", children[i].clone().into());
+        }
+        push(&mut builder.nodes, node);
+
+        // in the bind pose, each bone's node just sits at its pivot
+        let pivot = bone.pivot;
+        inverse_bind_matrices.extend_from_slice(&[
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            -pivot.x, -pivot.y, -pivot.z, 1.0,
+        ]);
+    }
+
+    let mut mesh_node = Json::object(vec![
+        ("name", name.clone().into()),
+        ("mesh", mesh.into()),
+    ]);
+    if skinned {
+        let inverse_bind_matrices = builder.add_floats(&inverse_bind_matrices, "MAT4", None);
+        let skin = push(&mut builder.skins, Json::object(vec![
+            ("joints", (0..bones.len()).collect::<Vec<usize>>().into()),
+            ("inverseBindMatrices", inverse_bind_matrices.into()),
+        ]));
+        mesh_node.set("skin", skin.into());
+    }
+    let mesh_node = push(&mut builder.nodes, mesh_node);
+    let mut root_children = vec![mesh_node];
+    root_children.extend(root_bones);
+    let root = push(&mut builder.nodes, Json::object(vec![
+        ("name", name.clone().into()),
+        ("rotation", Z_UP_TO_Y_UP.to_vec().into()),
+        ("children", root_children.into()),
+    ]));
+    builder.scene_nodes.push(root);
+
+    for (sequence_index, sequence) in animation_manager.get_sequences().iter().enumerate() {
+        if !animation_manager.is_sequence_index_loaded(sequence_index) {
+            continue;
+        }
+        let mut animation = AnimationBuilder::default();
+        for (i, bone) in bones.iter().enumerate() {
+            if let Some((times, values)) = sequence_keyframes(&bone.translation, sequence_index) {
+                let offset = bone_pivot_offset(bones, i);
+                let values: Vec<f32> = values.iter()
+                    .flat_map(|value| [value.x + offset[0], value.y + offset[1], value.z + offset[2]])
+                    .collect();
+                animation.add_channel(&mut builder, i, "translation", times, bone.translation.interpolation_type, &values);
+            }
+            if let Some(rotation) = bone.rotation.as_ref() {
+                if let Some((times, values)) = sequence_keyframes(rotation, sequence_index) {
+                    let values: Vec<f32> = values.iter()
+                        .flat_map(|value| {
+                            let quat = glm::quat(value.x, value.y, value.z, value.w).normalize();
+                            [quat.i, quat.j, quat.k, quat.w]
+                        })
+                        .collect();
+                    animation.add_channel(&mut builder, i, "rotation", times, rotation.interpolation_type, &values);
+                }
+            }
+            if let Some((times, values)) = sequence_keyframes(&bone.scaling, sequence_index) {
+                let values: Vec<f32> = values.iter()
+                    .flat_map(|value| [value.x, value.y, value.z])
+                    .collect();
+                animation.add_channel(&mut builder, i, "scale", times, bone.scaling.interpolation_type, &values);
+            }
+        }
+        // aliases and sequences that don't move any bones have nothing to export
+        if animation.channels.is_empty() {
+            continue;
+        }
+        let name = if sequence.id == 0 && sequence.sub_id == 0 {
+            "Stand".to_string()
+        } else {
+            format!("Sequence{}_{}", sequence.id, sequence.sub_id)
+        };
+        push(&mut builder.animations, Json::object(vec![
+            ("name", name.into()),
+            ("samplers", animation.samplers.into()),
+            ("channels", animation.channels.into()),
+        ]));
+    }
+
+    Ok(builder.into_glb())
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+// WMO materials are shared between groups, so they're only added once
+struct WmoMaterialCache<'a> {
+    wmo: &'a Wmo,
+    textures: &'a HashMap<u32, Blp>,
+    gltf_textures: HashMap<(u32, bool, bool), usize>,
+    gltf_materials: HashMap<usize, usize>,
+}
+
+impl<'a> WmoMaterialCache<'a> {
+    fn new(wmo: &'a Wmo, textures: &'a HashMap<u32, Blp>) -> Self {
+        WmoMaterialCache {
+            wmo,
+            textures,
+            gltf_textures: HashMap::new(),
+            gltf_materials: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, builder: &mut GltfBuilder, material_index: usize) -> Result<usize, String> {
+        if let Some(&material) = self.gltf_materials.get(&material_index) {
+            return Ok(material);
+        }
+        let source = self.wmo.textures.get(material_index)
+            .ok_or(format!("batch references missing material {}", material_index))?;
+        let flags = WmoMaterialFlags::new(source.flags);
+
+        // only the first texture maps onto a glTF base color
+        let mut texture = None;
+        if let Some(blp) = self.textures.get(&source.texture_1) {
+            let key = (source.texture_1, !flags.clamp_s, !flags.clamp_t);
+            texture = Some(match self.gltf_textures.get(&key) {
+                Some(&texture) => texture,
+                None => {
+                    let texture = add_blp_texture(builder, blp, !flags.clamp_s, !flags.clamp_t)?;
+                    self.gltf_textures.insert(key, texture);
+                    texture
+                },
+            });
+        }
+
+        let alpha_mode = AlphaMode::from_blend_mode(source.blend_mode);
+        let material = add_material(builder, format!("material{}", material_index), texture, alpha_mode, flags.unculled, flags.unlit);
+        self.gltf_materials.insert(material_index, material);
+        Ok(material)
+    }
+}
+
+// adds a mesh with a primitive per material batch, or nothing if the group
+// has no geometry
+fn add_wmo_group_mesh(builder: &mut GltfBuilder, group: &WmoGroup, name: String, materials: &mut WmoMaterialCache) -> Result<Option<usize>, String> {
+    let num_vertices = group.num_vertices;
+    if num_vertices == 0 || group.batches.is_empty() {
+        return Ok(None);
+    }
+    let positions = group.get_vertices().get(..num_vertices * 3)
+        .ok_or(format!("{} has fewer positions than vertices", name))?;
+    let normals: Vec<f32> = read_f32s(group.get_normals()).chunks_exact(3)
+        .flat_map(|normal| unit_normal(normal[0], normal[1], normal[2]))
+        .collect();
+    if normals.len() < num_vertices * 3 {
+        return Err(format!("{} has fewer normals than vertices", name));
+    }
+    let mut attributes = Json::object(vec![
+        ("POSITION", builder.add_floats(positions, "VEC3", Some(ARRAY_BUFFER)).into()),
+        ("NORMAL", builder.add_floats(&normals[..num_vertices * 3], "VEC3", Some(ARRAY_BUFFER)).into()),
+    ]);
+    let uvs = read_f32s(group.get_uvs());
+    for (i, uv_buf) in uvs.chunks_exact(num_vertices * 2).take(2).enumerate() {
+        attributes.set(&format!("TEXCOORD_{}", i), builder.add_floats(uv_buf, "VEC2", Some(ARRAY_BUFFER)).into());
+    }
+    // MOCV is baked lighting, which is just what glTF does with vertex
+    // colors. Its alpha blends between interior and exterior lighting
+    // rather than being transparency, so it's left out
+    if let Some(colors) = group.get_colors().get(..num_vertices * 4) {
+        let colors: Vec<u8> = colors.chunks_exact(4)
+            .flat_map(|bgra| [bgra[2], bgra[1], bgra[0], 255])
+            .collect();
+        attributes.set("COLOR_0", builder.add_bytes(&colors, "VEC4", true).into());
+    }
+
+    let indices = group.get_indices();
+    let mut primitives = Vec::with_capacity(group.batches.len());
+    for batch in &group.batches {
+        let start = batch.start_index as usize;
+        let end = start + batch.index_count as usize;
+        let batch_indices: Vec<u32> = indices.get(start..end)
+            .ok_or(format!("{} batch indices {}..{} are out of bounds", name, start, end))?
+            .iter().map(|&index| index as u32).collect();
+        let material_index = if batch.use_material_id_large > 0 {
+            batch.material_id_large as usize
+        } else {
+            batch.material_id as usize
+        };
+        primitives.push(Json::object(vec![
+            ("attributes", attributes.clone()),
+            ("indices", builder.add_indices(&batch_indices).into()),
+            ("material", materials.get(builder, material_index)?.into()),
+        ]));
+    }
+    Ok(Some(push(&mut builder.meshes, Json::object(vec![
+        ("name", name.into()),
+        ("primitives", primitives.into()),
+    ]))))
+}
+
+// M2 meshes are added the first time a doodad references them, and then
+// shared by every doodad placing the same model
+struct M2MeshCache {
+    models: HashMap<u32, M2Model>,
+    meshes: HashMap<u32, usize>,
+}
+
+impl M2MeshCache {
+    fn new(models: HashMap<u32, M2Model>) -> Self {
+        M2MeshCache {
+            models,
+            meshes: HashMap::new(),
+        }
+    }
+
+    // returns None if the model wasn't provided
+    fn get(&mut self, builder: &mut GltfBuilder, file_id: u32) -> Result<Option<usize>, String> {
+        if let Some(&mesh) = self.meshes.get(&file_id) {
+            return Ok(Some(mesh));
+        }
+        let model = match self.models.get_mut(&file_id) {
+            Some(model) => model,
+            None => return Ok(None),
+        };
+        let mesh = add_m2_mesh(builder, model, None)?;
+        self.meshes.insert(file_id, mesh);
+        Ok(Some(mesh))
+    }
+}
+
+// adds a mesh for each of the WMO's loaded groups (see `Wmo::append_group`;
+// this has to happen before the vertex data is taken), returning them along
+// with their names
+fn add_wmo_group_meshes(builder: &mut GltfBuilder, wmo: &Wmo, textures: &HashMap<u32, Blp>) -> Result<Vec<(String, usize)>, String> {
+    let mut materials = WmoMaterialCache::new(wmo, textures);
+    let mut meshes = Vec::new();
+    for (i, &file_id) in wmo.group_file_ids.iter().enumerate() {
+        let group = match wmo.get_loaded_group(file_id) {
+            Some(group) => group,
+            None => continue,
+        };
+        let name = format!("group{}", i);
+        if let Some(mesh) = add_wmo_group_mesh(builder, group, name.clone(), &mut materials)? {
+            meshes.push((name, mesh));
+        }
+    }
+    Ok(meshes)
+}
+
+// adds a node for each group mesh, and one for each doodad in the given
+// doodad set (plus the default set, like the renderer) that instances its
+// M2's mesh. Doodads whose model is missing are left out. Returns the nodes,
+// which are in the WMO's model space
+fn add_wmo_nodes(builder: &mut GltfBuilder, wmo: &Wmo, group_meshes: &[(String, usize)], doodad_set_id: usize, m2_meshes: &mut M2MeshCache) -> Result<Vec<usize>, String> {
+    let mut nodes = Vec::new();
+    for (name, mesh) in group_meshes {
+        nodes.push(push(&mut builder.nodes, Json::object(vec![
+            ("name", name.as_str().into()),
+            ("mesh", (*mesh).into()),
+        ])));
+    }
+
+    if wmo.get_num_doodad_sets() == 0 {
+        return Ok(nodes);
+    }
+    for doodad_index in wmo.get_doodad_set_refs(doodad_set_id) {
+        let doodad = match wmo.doodad_defs.get(doodad_index as usize) {
+            Some(doodad) => doodad,
+            None => continue,
+        };
+        if doodad.name_index < 0 {
+            continue;
+        }
+        let file_id = match wmo.doodad_file_ids.get(doodad.name_index as usize) {
+            Some(&file_id) => file_id,
+            None => continue,
+        };
+        let mesh = match m2_meshes.get(builder, file_id)? {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let position = doodad.position;
+        let orientation = doodad.orientation;
+        let rotation = glm::quat(orientation.x, orientation.y, orientation.z, orientation.w).normalize();
+        nodes.push(push(&mut builder.nodes, Json::object(vec![
+            ("name", format!("doodad{}", doodad_index).into()),
+            ("mesh", mesh.into()),
+            ("translation", vec![position.x, position.y, position.z].into()),
+            ("rotation", vec![rotation.i, rotation.j, rotation.k, rotation.w].into()),
+            ("scale", vec![doodad.scale; 3].into()),
+        ])));
+    }
+    Ok(nodes)
+}
+
+// Exports a WMO as one binary glTF scene, with a node for each loaded group
+// and each doodad in the given doodad set (see `add_wmo_nodes`). `textures`
+// maps texture file IDs to BLPs, and `models` maps doodad file IDs to their
+// M2s. Doodads are exported unanimated.
+pub fn export_wmo(wmo: &Wmo, doodad_set_id: usize, textures: &HashMap<u32, Blp>, models: HashMap<u32, M2Model>) -> Result<Vec<u8>, String> {
+    let mut builder = GltfBuilder::default();
+    let group_meshes = add_wmo_group_meshes(&mut builder, wmo, textures)?;
+    let mut m2_meshes = M2MeshCache::new(models);
+    let children = add_wmo_nodes(&mut builder, wmo, &group_meshes, doodad_set_id, &mut m2_meshes)?;
+
+    let mut root = Json::object(vec![
+        ("name", format!("wmo{}", wmo.header.wmo_id).into()),
+        ("rotation", Z_UP_TO_Y_UP.to_vec().into()),
+    ]);
+    if !children.is_empty() {
+        root.set("children", children.into());
+    }
+    let root = push(&mut builder.nodes, root);
+    builder.scene_nodes.push(root);
+    Ok(builder.into_glb())
+}
+
+// the width and height of each chunk in the baked terrain texture, so a
+// tile's texture is 16 times that
+const TERRAIN_TEXELS_PER_CHUNK: usize = 128;
+
+// ADT doodads and WMOs are positioned in placement space, which is Y-up and
+// has the map's corner at the origin, while terrain vertices (and so the
+// exported scene) are in ADT space. See src/WorldOfWarcraft/scenes.ts
+fn adt_space_from_placement_space() -> glm::Mat4 {
+    glm::mat4(
+        0.0, 0.0, -1.0, MAP_ORIGIN,
+        -1.0, 0.0, 0.0, MAP_ORIGIN,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+    )
+}
+
+fn placement_space_from_model_space() -> glm::Mat4 {
+    glm::mat4(
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 1.0, 0.0,
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+    )
+}
+
+// doodads and WMOs apply their rotations (in degrees) in different orders
+fn doodad_rotation(rotation: &Vec3) -> glm::Mat4 {
+    glm::rotation(rotation.y.to_radians(), &glm::Vec3::y())
+        * glm::rotation(rotation.x.to_radians(), &glm::Vec3::x())
+        * glm::rotation(rotation.z.to_radians(), &glm::Vec3::z())
+}
+
+fn map_object_rotation(rotation: &Vec3) -> glm::Mat4 {
+    glm::rotation(rotation.z.to_radians(), &glm::Vec3::z())
+        * glm::rotation(rotation.y.to_radians(), &glm::Vec3::y())
+        * glm::rotation(rotation.x.to_radians(), &glm::Vec3::x())
+}
+
+// converts a placement's position and rotation into an ADT space
+// translation and rotation. placements only ever use uniform scales, so the
+// scale can stay separate
+fn placement_transform(position: &Vec3, rotation: &glm::Mat4) -> (glm::Vec3, glm::Quat) {
+    let transform = adt_space_from_placement_space()
+        * glm::translation(&glm::vec3(position.x, position.y, position.z))
+        * rotation
+        * placement_space_from_model_space();
+    (transform.column(3).xyz(), glm::to_quat(&transform).normalize())
+}
+
+fn placement_node(name: String, position: &Vec3, rotation: &glm::Mat4, scale: f32) -> Json {
+    let (translation, rotation) = placement_transform(position, rotation);
+    Json::object(vec![
+        ("name", name.into()),
+        ("translation", vec![translation.x, translation.y, translation.z].into()),
+        ("rotation", vec![rotation.i, rotation.j, rotation.k, rotation.w].into()),
+        ("scale", vec![scale; 3].into()),
+    ])
+}
+
+// maps a terrain vertex onto the baked texture, which has each chunk's
+// pixels in a 16x16 grid, see `TerrainTextureBaker::bake_adt`
+fn terrain_uv(chunk_index: usize, vertex_index: usize) -> [f32; 2] {
+    let mut row = (vertex_index / 17) as f32;
+    let mut col = (vertex_index % 17) as f32;
+    if col > 8.0 {
+        row += 0.5;
+        col -= 8.5;
+    }
+    [
+        ((chunk_index % 16) as f32 + col / 8.0) / 16.0,
+        ((chunk_index / 16) as f32 + row / 8.0) / 16.0,
+    ]
+}
+
+fn encode_png(pixels: &[u8], width: usize, height: usize) -> Result<Vec<u8>, String> {
+    let mut result = Vec::new();
+    let mut encoder = png::Encoder::new(&mut result, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(|e| format!("failed to encode PNG: {}", e))?;
+    Ok(result)
+}
+
+// adds the terrain as a single mesh, textured with its layers baked into one
+// texture. textures missing from `textures` are baked as flat grey
+fn add_terrain_mesh(builder: &mut GltfBuilder, adt: &Adt, adt_has_big_alpha: bool, adt_has_height_texturing: bool, textures: &HashMap<u32, Blp>) -> Result<usize, String> {
+    let mut baker = TerrainTextureBaker::new(TERRAIN_TEXELS_PER_CHUNK);
+    for chunk in adt.get_map_chunks() {
+        for layer in &chunk.texture_layers {
+            let texture_index = layer.texture_index as usize;
+            if let Some(file_id) = adt.get_diffuse_texture_id(texture_index) {
+                if !baker.has_texture(file_id) {
+                    match textures.get(&file_id) {
+                        Some(blp) => baker.add_texture(file_id, blp.get_mip_rgba(0)?, blp.get_mip_width(0) as usize, blp.get_mip_height(0) as usize)?,
+                        None => baker.add_texture(file_id, vec![128, 128, 128, 255], 1, 1)?,
+                    }
+                }
+            }
+            if let Some(file_id) = adt.get_height_texture_id(texture_index) {
+                if let (false, Some(blp)) = (baker.has_texture(file_id), textures.get(&file_id)) {
+                    baker.add_texture(file_id, blp.get_mip_rgba(0)?, blp.get_mip_width(0) as usize, blp.get_mip_height(0) as usize)?;
+                }
+            }
+        }
+    }
+    let size = 16 * TERRAIN_TEXELS_PER_CHUNK;
+    let pixels = baker.bake_adt(adt, adt_has_big_alpha, adt_has_height_texturing)?;
+    let texture = builder.add_png_texture(&encode_png(&pixels, size, size)?, CLAMP_TO_EDGE, CLAMP_TO_EDGE);
+    let material = add_material(builder, "terrain".to_string(), Some(texture), AlphaMode::Opaque, false, false);
+
+    let mut render_result = adt.get_render_result(adt_has_big_alpha, adt_has_height_texturing);
+    let vertex_buffer = render_result.take_vertex_buffer();
+    let info = &ADT_VBO_INFO;
+    let (stride, position, normal, color) = (info.stride / 4, info.vertex_offset / 4, info.normal_offset / 4, info.color_offset / 4);
+    let num_vertices = vertex_buffer.len() / stride;
+    let mut positions = Vec::with_capacity(num_vertices * 3);
+    let mut normals = Vec::with_capacity(num_vertices * 3);
+    let mut colors = Vec::with_capacity(num_vertices * 4);
+    let mut tex_coords = Vec::with_capacity(num_vertices * 2);
+    for (i, vertex) in vertex_buffer.chunks_exact(stride).enumerate() {
+        positions.extend_from_slice(&vertex[position..position + 3]);
+        normals.extend_from_slice(&unit_normal(vertex[normal], vertex[normal + 1], vertex[normal + 2]));
+        // the terrain shader doubles MCCV, so that 0x7f leaves the texture
+        // as is. its alpha isn't used
+        colors.extend(vertex[color..color + 3].iter().map(|&c| (c * 2.0 * 255.0).round().min(255.0) as u8));
+        colors.push(255);
+        // the first float is the vertex's index within its chunk
+        tex_coords.extend_from_slice(&terrain_uv(i / VERTICES_PER_CHUNK, vertex[0] as usize));
+    }
+    let indices: Vec<u32> = render_result.take_index_buffer().iter().map(|&index| index as u32).collect();
+
+    let attributes = Json::object(vec![
+        ("POSITION", builder.add_floats(&positions, "VEC3", Some(ARRAY_BUFFER)).into()),
+        ("NORMAL", builder.add_floats(&normals, "VEC3", Some(ARRAY_BUFFER)).into()),
+        ("TEXCOORD_0", builder.add_floats(&tex_coords, "VEC2", Some(ARRAY_BUFFER)).into()),
+        ("COLOR_0", builder.add_bytes(&colors, "VEC4", true).into()),
+    ]);
+    let primitive = Json::object(vec![
+        ("attributes", attributes),
+        ("indices", builder.add_indices(&indices).into()),
+        ("material", material.into()),
+    ]);
+    Ok(push(&mut builder.meshes, Json::object(vec![
+        ("name", "terrain".into()),
+        ("primitives", vec![primitive].into()),
+    ])))
+}
+
+// Exports an ADT tile (with its obj0 and tex0 files appended) as one binary
+// glTF scene: the terrain with its texture layers baked, plus a node for
+// every doodad and WMO placement. The flags are the WDT's, as for
+// `Adt::get_render_result`. `textures` maps terrain and WMO texture file IDs
+// to BLPs, `wmos` maps WMO file IDs to WMOs with their groups appended, and
+// `models` maps M2 file IDs (for both ADT and WMO doodads) to their M2s.
+// Placements whose model is missing are left out. Everything keeps its world
+// coordinates, so neighbouring tiles line up when imported together.
+pub fn export_adt(adt: &Adt, adt_has_big_alpha: bool, adt_has_height_texturing: bool, textures: &HashMap<u32, Blp>, wmos: &HashMap<u32, Wmo>, models: HashMap<u32, M2Model>) -> Result<Vec<u8>, String> {
+    let mut builder = GltfBuilder::default();
+    let terrain = add_terrain_mesh(&mut builder, adt, adt_has_big_alpha, adt_has_height_texturing, textures)?;
+    let mut children = vec![push(&mut builder.nodes, Json::object(vec![
+        ("name", "terrain".into()),
+        ("mesh", terrain.into()),
+    ]))];
+
+    let mut m2_meshes = M2MeshCache::new(models);
+    for doodad in adt.get_doodads(0) {
+        let mesh = match m2_meshes.get(&mut builder, doodad.name_id)? {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let name = format!("doodad{}", doodad.unique_id);
+        let mut node = placement_node(name, &doodad.position, &doodad_rotation(&doodad.rotation), doodad.scale as f32 / 1024.0);
+        node.set("mesh", mesh.into());
+        children.push(push(&mut builder.nodes, node));
+    }
+
+    // WMOs placed more than once share their group meshes
+    let mut wmo_group_meshes: HashMap<u32, Vec<(String, usize)>> = HashMap::new();
+    for def in adt.get_wmo_defs(0) {
+        let wmo = match wmos.get(&def.name_id) {
+            Some(wmo) => wmo,
+            None => continue,
+        };
+        let group_meshes = match wmo_group_meshes.entry(def.name_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(add_wmo_group_meshes(&mut builder, wmo, textures)?),
+        };
+        let wmo_children = add_wmo_nodes(&mut builder, wmo, group_meshes, def.doodad_set as usize, &mut m2_meshes)?;
+        let name = format!("wmo{}", def.unique_id);
+        let mut node = placement_node(name, &def.position, &map_object_rotation(&def.rotation), def.scale as f32 / 1024.0);
+        if !wmo_children.is_empty() {
+            node.set("children", wmo_children.into());
+        }
+        children.push(push(&mut builder.nodes, node));
+    }
+
+    let root = push(&mut builder.nodes, Json::object(vec![
+        ("name", "adt".into()),
+        ("rotation", Z_UP_TO_Y_UP.to_vec().into()),
+        ("children", children.into()),
+    ]));
+    builder.scene_nodes.push(root);
+    Ok(builder.into_glb())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use crate::wow::common::{chunk, Vec2, Vec3};
+
+    #[test]
+    fn test_json() {
+        let mut json = Json::object(vec![
+            ("name", "a \"quoted\"\nname".into()),
+            ("values", vec![1.5f32, 2.0].into()),
+        ]);
+        json.set("flag", true.into());
+        assert_eq!(json.to_string(), r#"{"name":"a \"quoted\"\u000aname","values":[1.5,2],"flag":true}"#);
+    }
+
+    #[test]
+    fn test_glb_layout() {
+        let mut builder = GltfBuilder::default();
+        let accessor = builder.add_floats(&[0.0, 1.0, 2.0, -1.0, 5.0, 0.5], "VEC3", Some(ARRAY_BUFFER));
+        builder.add_indices(&[0, 1, 0]);
+        builder.scene_nodes.push(0);
+        assert_eq!(accessor, 0);
+        let glb = builder.into_glb();
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        assert_eq!(json_length % 4, 0);
+        let json = std::str::from_utf8(&glb[20..20 + json_length]).unwrap();
+        assert!(json.contains(r#""min":[-1,1,0.5],"max":[0,5,2]"#));
+        assert!(json.contains(r#""buffers":[{"byteLength":36}]"#));
+        let bin = &glb[20 + json_length..];
+        assert_eq!(u32::from_le_bytes(bin[0..4].try_into().unwrap()), 36);
+        assert_eq!(&bin[4..8], b"BIN\0");
+        assert_eq!(bin.len(), 8 + 36);
+    }
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    // a WMO with a single group holding one triangle, with one material and
+    // no doodads, portals or fogs
+    fn build_wmo() -> Wmo {
+        let mut root = chunk(b"REVM", &17u32.to_le_bytes());
+        root.extend(chunk(b"DHOM", &[0; 0x40]));
+        root.extend(chunk(b"TMOM", &[0; 0x40]));
+        root.extend(chunk(b"IGOM", &[0; 0x20]));
+        root.extend(chunk(b"DDOM", &[]));
+        root.extend(chunk(b"GOFM", &[]));
+        root.extend(chunk(b"DIFG", &1234u32.to_le_bytes()));
+        root.extend(chunk(b"VPOM", &[]));
+        root.extend(chunk(b"TPOM", &[]));
+        root.extend(chunk(b"RPOM", &[]));
+        let mut wmo = Wmo::new(&root).unwrap();
+
+        let mut batch = vec![0; 0xA];
+        batch.extend_from_slice(&0u16.to_le_bytes()); // material_id_large
+        batch.extend_from_slice(&0u32.to_le_bytes()); // start_index
+        batch.extend_from_slice(&3u16.to_le_bytes()); // index_count
+        batch.extend_from_slice(&0u16.to_le_bytes()); // first_vertex
+        batch.extend_from_slice(&2u16.to_le_bytes()); // last_vertex
+        batch.extend_from_slice(&[0, 0]); // use_material_id_large, material_id
+        let mut subchunks = chunk(b"TVOM", &f32_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]));
+        subchunks.extend(chunk(b"RNOM", &f32_bytes(&[0.0, 0.0, 2.0, 0.0, 0.0, 2.0, 0.0, 0.0, 2.0])));
+        subchunks.extend(chunk(b"VTOM", &f32_bytes(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0])));
+        subchunks.extend(chunk(b"VCOM", &[10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120]));
+        subchunks.extend(chunk(b"IVOM", &[0, 0, 1, 0, 2, 0]));
+        subchunks.extend(chunk(b"ABOM", &batch));
+        let mut mogp = vec![0; 0x44];
+        mogp.extend(subchunks);
+        let mut group = chunk(b"REVM", &17u32.to_le_bytes());
+        group.extend(chunk(b"PGOM", &mogp));
+        wmo.append_group(1234, &group).unwrap();
+        wmo
+    }
+
+    #[test]
+    fn test_export_wmo() {
+        let glb = export_wmo(&build_wmo(), 0, &HashMap::new(), HashMap::new()).unwrap();
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        let json = std::str::from_utf8(&glb[20..20 + json_length]).unwrap();
+        let bin = &glb[20 + json_length + 8..];
+
+        assert!(json.contains(r#""attributes":{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2,"COLOR_0":3},"indices":4,"material":0"#));
+        for (accessor, accessor_type) in [(0, "VEC3"), (1, "VEC3"), (2, "VEC2"), (3, "VEC4"), (4, "SCALAR")] {
+            let expected = format!(r#"{{"bufferView":{},"componentType":"#, accessor);
+            let start = json.find(&expected).unwrap();
+            let end = start + json[start..].find('}').unwrap();
+            assert!(json[start..end].contains(&format!(r#""count":3,"type":"{}""#, accessor_type)), "{}", &json[start..end]);
+        }
+        assert!(json.contains(r#""nodes":[{"name":"group0","mesh":0},{"name":"wmo0""#));
+
+        // normals are renormalized, and MOCV's BGRA becomes opaque RGBA
+        assert_eq!(&bin[36..48], f32_bytes(&[0.0, 0.0, 1.0]).as_slice());
+        assert!(json.contains(r#"{"buffer":0,"byteOffset":96,"byteLength":12,"target":34962}"#));
+        assert_eq!(&bin[96..108], &[30, 20, 10, 255, 70, 60, 50, 255, 110, 100, 90, 255]);
+    }
+
+    #[test]
+    fn test_vertex_weights() {
+        let vertex = |bone_weights: [u8; 4], bone_indices: [u8; 4]| M2Vertex {
+            position: Vec3::new(0.0),
+            bone_weights,
+            bone_indices,
+            normal: Vec3::new(0.0),
+            tex_coords: [Vec2 { x: 0.0, y: 0.0 }; 2],
+        };
+        assert_eq!(vertex_joints_and_weights(&vertex([255, 0, 0, 0], [3, 0, 0, 0]), 4), ([3, 0, 0, 0], [255, 0, 0, 0]));
+        // bone 7 doesn't exist, so its weight is redistributed
+        assert_eq!(vertex_joints_and_weights(&vertex([100, 155, 0, 0], [1, 7, 0, 0]), 4), ([1, 0, 0, 0], [255, 0, 0, 0]));
+        assert_eq!(vertex_joints_and_weights(&vertex([85, 85, 84, 0], [0, 1, 2, 0]), 4), ([0, 1, 2, 0], [85, 86, 84, 0]));
+        assert_eq!(vertex_joints_and_weights(&vertex([0; 4], [2; 4]), 4), ([0; 4], [255, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_placement_transform() {
+        let assert_near = |a: glm::Vec3, b: glm::Vec3| assert!(glm::distance(&a, &b) < 1e-3, "{:?} != {:?}", a, b);
+        let position = Vec3 { x: MAP_ORIGIN - 100.0, y: 50.0, z: MAP_ORIGIN - 200.0 };
+
+        // an unrotated model faces the opposite way in ADT space
+        let (translation, rotation) = placement_transform(&position, &doodad_rotation(&Vec3::new(0.0)));
+        assert_near(translation, glm::vec3(200.0, 100.0, 50.0));
+        assert_near(glm::quat_rotate_vec3(&rotation, &glm::Vec3::x()), glm::vec3(-1.0, 0.0, 0.0));
+        assert_near(glm::quat_rotate_vec3(&rotation, &glm::Vec3::z()), glm::Vec3::z());
+
+        // placement space's Y is ADT space's Z
+        let (_, rotation) = placement_transform(&position, &doodad_rotation(&Vec3 { x: 0.0, y: 90.0, z: 0.0 }));
+        assert_near(glm::quat_rotate_vec3(&rotation, &glm::Vec3::x()), glm::vec3(0.0, -1.0, 0.0));
+        let (_, rotation) = placement_transform(&position, &map_object_rotation(&Vec3 { x: 0.0, y: 90.0, z: 0.0 }));
+        assert_near(glm::quat_rotate_vec3(&rotation, &glm::Vec3::x()), glm::vec3(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn test_terrain_uv() {
+        assert_eq!(terrain_uv(0, 0), [0.0, 0.0]);
+        // the last outer vertex of chunk 0 is the first of chunk 17
+        assert_eq!(terrain_uv(0, 144), [1.0 / 16.0, 1.0 / 16.0]);
+        assert_eq!(terrain_uv(17, 0), [1.0 / 16.0, 1.0 / 16.0]);
+        // inner vertices sit in the middle of their quad
+        assert_eq!(terrain_uv(1, 9), [(1.0 + 0.5 / 8.0) / 16.0, 0.5 / 8.0 / 16.0]);
+    }
+}
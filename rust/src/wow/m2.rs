@@ -1,13 +1,12 @@
 
-use std::marker::PhantomData;
-
 use deku::prelude::*;
 
 use wasm_bindgen::prelude::*;
+use crate::geometry::TriangleMesh;
 use crate::wow::{animation::*, common::parse, particles::Emitter};
 
 use super::common::{
-    fixed_precision_6_9_to_f32, parse_array, AABBox, ChunkedData, Fixedi16, Quat, Vec2, Vec3, WowArray, WowCharArray
+    fixed_precision_6_9_to_f32, parse_array, AABBox, ChunkedData, Fixedi16, Vec2, Vec3, WowArray, WowCharArray
 };
 
 #[derive(Debug, DekuRead, Clone)]
@@ -27,7 +26,7 @@ pub struct M2Header {
     textures: WowArray<M2Texture>,
     texture_weights: WowArray<M2Track<Fixedi16>>,
     texture_transforms: WowArray<M2TextureTransform>,
-    _replacable_texture_lookup: WowArray<u8>,
+    replaceable_texture_lookup: WowArray<i16>,
     materials: WowArray<M2Material>,
     bone_lookup_table: WowArray<u16>,
     texture_lookup_table: WowArray<u16>,
@@ -36,13 +35,13 @@ pub struct M2Header {
     texture_transforms_lookup_table: WowArray<u16>,
     pub bounding_box: AABBox,
     pub bounding_sphere_radius: f32,
-    pub _collision_box: AABBox,
-    pub _collision_sphere_radius: f32,
-    _collision_triangles: WowArray<u16>,
-    _collision_vertices: WowArray<Vec3>,
-    _collision_normals: WowArray<Vec3>,
-    _attachments: WowArray<()>,
-    _attachment_lookup_table: WowArray<u16>,
+    pub collision_box: AABBox,
+    pub collision_sphere_radius: f32,
+    collision_triangles: WowArray<u16>,
+    collision_vertices: WowArray<Vec3>,
+    collision_normals: WowArray<Vec3>,
+    attachments: WowArray<M2Attachment>,
+    attachment_lookup_table: WowArray<i16>,
     _events: WowArray<()>,
     lights: WowArray<M2Light>,
     _cameras: WowArray<()>,
@@ -84,34 +83,11 @@ impl M2Header {
         Ok(texture_transforms)
     }
 
-    fn get_bones(&self, m2_data: &[u8]) -> Result<Vec<M2CompBone>, String> {
+    fn get_bones(&self, m2_data: &[u8], sequences: &[M2Sequence]) -> Result<Vec<M2CompBone>, String> {
         let mut bones: Vec<M2CompBone> = self.bones.to_vec(m2_data)?;
         for bone in bones.iter_mut() {
-            bone.rotation_quat16.allocate(m2_data)?;
-            bone.translation.allocate(m2_data)?;
-            bone.scaling.allocate(m2_data)?;
-
-            // convert the quat16s into quats so we don't have to do the
-            // math countless times per frame
-            let mut quat_values = Vec::new();
-            for quats in bone.rotation_quat16.values() {
-                let mut values = Vec::new();
-                for quat16 in quats {
-                    values.push(Quat::from(*quat16));
-                }
-                quat_values.push(values);
-            }
-
-            bone.rotation = Some(M2Track {
-                interpolation_type: bone.rotation_quat16.interpolation_type,
-                global_sequence: bone.rotation_quat16.global_sequence,
-                timestamps: Some(bone.rotation_quat16.timestamps().clone()),
-                values: Some(quat_values),
-
-                // hack: put in some fake pointers
-                timestamps_unallocated: WowArray { count: 0, offset: 0, element_type: PhantomData },
-                values_unallocated: WowArray { count: 0, offset: 0, element_type: PhantomData },
-            });
+            // keyframes for sequences in .anim files get filled in later
+            bone.allocate(m2_data, sequences)?;
         }
         Ok(bones)
     }
@@ -133,6 +109,18 @@ impl M2Header {
         Ok(m2_data[vertex_data_start..vertex_data_end].to_vec())
     }
 
+    fn get_replaceable_texture_lookup(&self, m2_data: &[u8]) -> Result<Vec<i16>, String> {
+        self.replaceable_texture_lookup.to_vec(m2_data)
+    }
+
+    fn get_attachments(&self, m2_data: &[u8]) -> Result<Vec<M2Attachment>, String> {
+        self.attachments.to_vec(m2_data)
+    }
+
+    fn get_attachment_lookup_table(&self, m2_data: &[u8]) -> Result<Vec<i16>, String> {
+        self.attachment_lookup_table.to_vec(m2_data)
+    }
+
     fn get_texture_lookup_table(&self, m2_data: &[u8]) -> Result<Vec<u16>, String> {
         self.texture_lookup_table.to_vec(m2_data)
     }
@@ -149,6 +137,24 @@ impl M2Header {
         self.transparency_lookup_table.to_vec(m2_data)
     }
 
+    fn get_collision_mesh(&self, m2_data: &[u8]) -> Result<TriangleMesh, String> {
+        let vertices: Vec<_> = self.collision_vertices.to_vec(m2_data)?
+            .drain(..)
+            .map(|v| v.into())
+            .collect();
+        let indices: Vec<u32> = self.collision_triangles.to_vec(m2_data)?
+            .drain(..)
+            .map(|i| i as u32)
+            .collect();
+        TriangleMesh::check_indices(&indices, vertices.len())
+            .map_err(|e| format!("invalid collision mesh: {}", e))?;
+        let normals = self.collision_normals.to_vec(m2_data)?
+            .drain(..)
+            .map(|n| n.into())
+            .collect();
+        Ok(TriangleMesh::new(vertices, indices, Some(normals)))
+    }
+
     fn get_lights(&self, m2_data: &[u8]) -> Result<Vec<M2Light>, String> {
         let mut lights: Vec<M2Light> = self.lights.to_vec(m2_data)?;
         for light in lights.iter_mut() {
@@ -199,7 +205,13 @@ pub struct M2 {
     pub skin_ids: Vec<u32>,
     pub name: String,
     pub materials: Vec<M2Material>,
+    pub anim_file_ids: Vec<AnimFileId>,
+    pub skeleton_file_id: Option<u32>,
+    pub attachments: Vec<M2Attachment>,
+    attachment_lookup_table: Vec<i16>, // attachment ID -> attachment index, or -1
     txac: Option<Vec<u16>>, // seems to be used in some particle emitter shader logic
+    texture_types: Vec<u32>,
+    replaceable_texture_lookup: Vec<i16>, // texture type -> texture index, or -1
     legacy_textures: Option<Vec<LegacyTexture>>,
     vertex_data: Option<Vec<u8>>,
     texture_lookup_table: Option<Vec<u16>>,
@@ -208,6 +220,7 @@ pub struct M2 {
     transparency_lookup_table: Option<Vec<u16>>,
     animation_manager: Option<AnimationManager>,
     particle_emitters: Option<Vec<Emitter>>,
+    collision_mesh: Option<TriangleMesh>,
 }
 
 #[wasm_bindgen(js_class = "WowM2")]
@@ -223,12 +236,16 @@ impl M2 {
         let mut txid: Option<Vec<u32>> = None;
         let mut sfid: Option<Vec<u32>> = None;
         let mut txac: Option<Vec<u16>> = None;
+        let mut afid: Option<Vec<AnimFileId>> = None;
+        let mut skid: Option<u32> = None;
         let mut exp2_unallocated: Option<WowArray<Exp2Record>> = None;
         for (chunk, chunk_data) in &mut chunked_data {
             match &chunk.magic {
                 b"TXID" => txid = Some(parse_array(chunk_data, 4)?),
                 b"SFID" => sfid = Some(parse_array(chunk_data, 4)?),
                 b"TXAC" => txac = Some(parse_array(chunk_data, 2)?),
+                b"AFID" => afid = Some(parse_array(chunk_data, 8)?),
+                b"SKID" => skid = Some(parse(chunk_data)?),
                 b"EXP2" => exp2_unallocated = Some(parse(chunk_data)?),
                 _ => {},
             }
@@ -252,34 +269,46 @@ impl M2 {
             if let Some(exp2) = exp2_allocated.as_ref() {
                 emitter_z_source = Some(exp2[i].z_source);
             }
-            particle_emitters.push(Emitter::new(emitter, emitter_txac, emitter_z_source));
+            // seeded by index so every run looks the same, instances can reseed
+            particle_emitters.push(Emitter::new(emitter, emitter_txac, emitter_z_source, i as u32));
         }
 
+        let sequences: Vec<M2Sequence> = header.sequences.to_vec(m2_data)?;
+        let bones = header.get_bones(m2_data, &sequences)?;
         let animation_manager = Some(AnimationManager::new(
             header.global_sequence_durations.to_vec(m2_data)?,
-            header.sequences.to_vec(m2_data)?,
+            sequences,
             header.get_texture_weights(m2_data)?,
             header.get_texture_transforms(m2_data)?,
             header.get_vertex_colors(m2_data)?,
-            header.get_bones(m2_data)?,
+            bones,
             header.get_lights(m2_data)?,
         ));
 
         let mut legacy_textures = Vec::new();
+        let mut texture_types = Vec::new();
         for tex in header.get_textures(m2_data)? {
             let filename = tex.filename.to_string(m2_data)?;
             legacy_textures.push(LegacyTexture {
                 filename,
                 flags: tex.flags,
+                texture_type: tex.texture_type,
             });
+            texture_types.push(tex.texture_type);
         }
 
         Ok(M2 {
             texture_ids: txid.unwrap_or_default(),
             skin_ids: sfid.ok_or("M2 didn't have SFID chunk!".to_string())?,
+            anim_file_ids: afid.unwrap_or_default(),
+            skeleton_file_id: skid,
+            attachments: header.get_attachments(m2_data)?,
+            attachment_lookup_table: header.get_attachment_lookup_table(m2_data)?,
             animation_manager,
             flags: header.flags,
             txac,
+            texture_types,
+            replaceable_texture_lookup: header.get_replaceable_texture_lookup(m2_data)?,
             name: header.get_name(m2_data)?,
             materials: header.get_materials(m2_data)?,
             vertex_data: Some(header.get_vertex_data(m2_data)?),
@@ -289,6 +318,8 @@ impl M2 {
             legacy_textures: Some(legacy_textures),
             texture_transforms_lookup_table: Some(header.get_texture_transforms_lookup_table(m2_data)?),
             transparency_lookup_table: Some(header.get_transparency_lookup_table(m2_data)?),
+            // bad collision data shouldn't stop the model from rendering
+            collision_mesh: header.get_collision_mesh(m2_data).ok(),
             header,
         })
     }
@@ -309,6 +340,57 @@ impl M2 {
         self.header.bounding_sphere_radius
     }
 
+    pub fn get_collision_box(&self) -> AABBox {
+        self.header.collision_box
+    }
+
+    pub fn get_collision_radius(&self) -> f32 {
+        self.header.collision_sphere_radius
+    }
+
+    // in modelspace. doodads without collision return an empty mesh, and
+    // ones with invalid collision data (or whose mesh was already taken)
+    // return None
+    pub fn take_collision_mesh(&mut self) -> Option<TriangleMesh> {
+        self.collision_mesh.take()
+    }
+
+    // see https://wowdev.wiki/M2#Attachments for the list of attachment IDs
+    pub fn get_attachment(&self, attachment_id: u32) -> Option<M2Attachment> {
+        let index = *self.attachment_lookup_table.get(attachment_id as usize)?;
+        if index < 0 {
+            return None;
+        }
+        self.attachments.get(index as usize).cloned()
+    }
+
+    pub fn get_texture_types(&self) -> Vec<u32> {
+        self.texture_types.clone()
+    }
+
+    // returns the index of the texture which uses the given replaceable
+    // texture type, if any
+    pub fn get_replaceable_texture_index(&self, texture_type: u32) -> Option<u16> {
+        let index = *self.replaceable_texture_lookup.get(texture_type as usize)?;
+        if index < 0 || index as usize >= self.texture_types.len() {
+            return None;
+        }
+        Some(index as u16)
+    }
+
+    // `replaceable_texture_ids` maps texture types to file IDs (e.g. as
+    // returned by `WowCreatureDisplay::get_replaceable_texture_ids`), with 0
+    // marking a slot that isn't filled in. hardcoded textures resolve to
+    // their TXID entry. unresolved textures are returned as 0
+    pub fn resolve_texture_ids(&self, replaceable_texture_ids: &[u32]) -> Vec<u32> {
+        self.texture_types.iter().enumerate()
+            .map(|(i, &texture_type)| match texture_type {
+                M2_TEXTURE_TYPE_HARDCODED => self.texture_ids.get(i).copied().unwrap_or(0),
+                _ => replaceable_texture_ids.get(texture_type as usize).copied().unwrap_or(0),
+            })
+            .collect()
+    }
+
     pub fn take_legacy_textures(&mut self) -> Vec<LegacyTexture> {
         self.legacy_textures.take().expect("M2 legacy textures already taken")
     }
@@ -343,6 +425,22 @@ impl M2 {
     }
 }
 
+// rust-only interface
+impl M2 {
+    pub fn parse_vertex_data(vertex_data: &[u8]) -> Result<Vec<M2Vertex>, String> {
+        parse_array(vertex_data, M2::get_vertex_stride())
+    }
+}
+
+#[derive(DekuRead, Debug, Clone, Copy)]
+pub struct M2Vertex {
+    pub position: Vec3,
+    pub bone_weights: [u8; 4],
+    pub bone_indices: [u8; 4],
+    pub normal: Vec3,
+    pub tex_coords: [Vec2; 2],
+}
+
 #[derive(DekuRead)]
 pub struct Exp2Record {
     pub z_source: f32,
@@ -351,6 +449,16 @@ pub struct Exp2Record {
     pub _unk3: M2TrackPartial<Fixedi16>,
 }
 
+#[wasm_bindgen(js_name = "WowM2Attachment")]
+#[derive(DekuRead, Debug, Clone)]
+pub struct M2Attachment {
+    pub id: u32,
+    pub bone: u16,
+    _unknown: u16,
+    pub position: Vec3, // in model space, before the bone is transformed
+    _animate_attached: M2Track<u8>,
+}
+
 #[derive(DekuRead, Debug, Clone)]
 pub struct M2Light {
     pub _light_type: u16, // should be 1 (point light) in all cases except the login screen
@@ -440,11 +548,21 @@ impl M2MaterialFlags {
 pub struct LegacyTexture {
     pub filename: String,
     pub flags: u32,
+    pub texture_type: u32,
 }
 
+// see https://wowdev.wiki/M2#Textures. anything other than HARDCODED is
+// filled in at runtime, either from the creature/item DB2s or by character
+// customization
+pub const M2_TEXTURE_TYPE_HARDCODED: u32 = 0;
+pub const M2_TEXTURE_TYPE_MONSTER_SKIN_1: u32 = 11;
+pub const M2_TEXTURE_TYPE_MONSTER_SKIN_2: u32 = 12;
+pub const M2_TEXTURE_TYPE_MONSTER_SKIN_3: u32 = 13;
+pub const M2_NUM_TEXTURE_TYPES: usize = 27;
+
 #[derive(Debug, DekuRead, Clone)]
 pub struct M2Texture {
-    pub _type: u32,
+    pub texture_type: u32,
     pub flags: u32,
     pub filename: WowCharArray,
 }
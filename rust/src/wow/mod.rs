@@ -1,11 +1,17 @@
 mod animation;
-mod wdt;
-mod common;
-mod m2;
-mod skin;
-mod blp;
-mod adt;
-mod wmo;
-mod db;
-mod sheep;
+pub mod wdt;
+pub mod common;
+pub mod m2;
+pub mod skin;
+pub mod blp;
+pub mod adt;
+pub mod wmo;
+pub mod db;
+pub mod sheep;
 mod particles;
+pub mod skel;
+mod character;
+mod dbd;
+mod tact;
+mod terrain;
+pub mod gltf;
@@ -1,17 +1,24 @@
 use js_sys::Float32Array;
 use nalgebra_glm::{mat4_to_mat3, pi, quat_angle_axis, quat_to_mat3, vec2, vec3, vec3_to_vec4, Mat4, Vec2, Vec3, Vec4};
-use rand::{rngs::ThreadRng, thread_rng, Rng};
 use wasm_bindgen::prelude::*;
 
+use crate::geometry::CollisionGeometry;
 use crate::spline::BezierSpline;
 
 use super::{
-    animation::AnimationManager,
+    animation::{AnimationManager, LcgRng},
     common::{Vec3 as WowVec3, Vec2 as WowVec2, Fixedi16},
     m2::{M2BlendingMode, ParticleEmitter as M2ParticleEmitter, ParticleShaderType},
 };
 
 pub const TEXELS_PER_PARTICLE: usize = 4;
+// past this many fixed steps per update we drop the remaining time, rather
+// than spiral when the page has been in the background for a while
+const MAX_FIXED_STEPS_PER_UPDATE: usize = 60;
+// how far above and below a clamped-to-ground particle we look for the ground
+const GROUND_PROBE_DISTANCE: f32 = 100.0;
+// keeps bounced particles from starting their next step inside the surface
+const COLLISION_OFFSET: f32 = 0.001;
 const PARTICLE_COORDINATE_FIX_SLICE: &[f32] = &[
     0.0, 1.0, 0.0, 0.0,
     -1.0, 0.0, 0.0, 0.0,
@@ -64,7 +71,7 @@ impl Particle {
     
     fn create_spherical(emitter: &mut Emitter) -> Self {
         let emission_area = emitter.params.emission_area_width - emitter.params.emission_area_length;
-        let radius = emitter.params.emission_area_length + emitter.rng.gen_range(0.0..1.0) * emission_area;
+        let radius = emitter.params.emission_area_length + emitter.rng.next_f32() * emission_area;
         let polar = emitter.random_range(1.0) * emitter.params.vertical_range;
         let azimuth = emitter.random_range(1.0) * emitter.params.horizontal_range;
         let emission_dir = vec3(
@@ -170,11 +177,47 @@ pub struct EmitterParams {
     pub z_source: f32,
 }
 
+// what happens to particles that hit the collision geometry. none of the
+// known M2 particle flags select a response (0x2000 only clamps spawning to
+// the ground), so emitters default to None and the caller opts in per model
+#[wasm_bindgen(js_name = "WowM2ParticleCollisionResponse")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParticleCollisionResponse {
+    None,
+    Bounce,
+    Die,
+}
+
+// the world geometry particles collide with, plus where the model sits in it
+struct ParticleCollider<'a> {
+    geometry: &'a CollisionGeometry,
+    model_matrix: Mat4,
+    inv_model_matrix: Mat4,
+}
+
+impl<'a> ParticleCollider<'a> {
+    fn new(geometry: &'a CollisionGeometry, model_matrix: Mat4) -> Self {
+        let inv_model_matrix = model_matrix.try_inverse().unwrap_or_else(Mat4::identity);
+        ParticleCollider { geometry, model_matrix, inv_model_matrix }
+    }
+
+    // drops a model space position onto the ground below (or just above) it
+    fn project_to_ground(&self, position: &Vec3) -> Option<Vec3> {
+        let world_position = transform(position, &self.model_matrix);
+        let start = world_position + vec3(0.0, 0.0, GROUND_PROBE_DISTANCE);
+        let end = world_position - vec3(0.0, 0.0, GROUND_PROBE_DISTANCE);
+        let hit = self.geometry.intersect_segment(&start, &end)?;
+        Some(transform(&(start + (end - start) * hit.t), &self.inv_model_matrix))
+    }
+}
+
 #[wasm_bindgen(js_name = "WowM2ParticleEmitter", getter_with_clone)]
 #[derive(Debug, Clone)]
 pub struct Emitter {
     inner: M2ParticleEmitter,
-    rng: ThreadRng,
+    rng: LcgRng,
+    fixed_timestep_ms: Option<f32>,
+    time_accumulator_ms: f32,
     model_mat: Mat4,
     particle_coordinate_fix: Mat4,
     particles: Vec<Particle>,
@@ -193,10 +236,12 @@ pub struct Emitter {
     pub blend_mode: M2BlendingMode,
     pub frag_shader_type: ParticleShaderType,
     pub bone: u16,
+    pub collision_response: ParticleCollisionResponse,
+    pub collision_bounciness: f32, // the fraction of speed kept when bouncing
 }
 
 impl Emitter {
-    pub fn new(mut m2_emitter: M2ParticleEmitter, txac: u16, z_source: Option<f32>) -> Self {
+    pub fn new(mut m2_emitter: M2ParticleEmitter, txac: u16, z_source: Option<f32>, seed: u32) -> Self {
         let model_mat = Mat4::identity();
         let wind = m2_emitter.wind_vector.into();
         let position = m2_emitter.position.into();
@@ -223,7 +268,9 @@ impl Emitter {
 
         Emitter {
             model_mat,
-            rng: thread_rng(),
+            rng: LcgRng::new(seed),
+            fixed_timestep_ms: None,
+            time_accumulator_ms: 0.0,
             max_particles,
             particle_coordinate_fix: Mat4::from_column_slice(PARTICLE_COORDINATE_FIX_SLICE),
             particles: Vec::with_capacity(max_particles as usize),
@@ -241,6 +288,8 @@ impl Emitter {
             blend_mode,
             bone,
             z_source,
+            collision_response: ParticleCollisionResponse::None,
+            collision_bounciness: 0.5,
             inner: m2_emitter,
         }
     }
@@ -250,10 +299,10 @@ impl Emitter {
         if a == 0.0 {
             return 0.0;
         }
-        self.rng.gen_range(-a..a)
+        (self.rng.next_f32() * 2.0 - 1.0) * a
     }
 
-    fn update_params(&mut self, animation_manager: &mut AnimationManager) {
+    fn update_params(&mut self, animation_manager: &AnimationManager) {
         let mut enabled: u8 = 1;
         if self.inner.enabled.timestamps().len() > 0 {
             enabled = animation_manager.get_current_value_with_blend(&self.inner.enabled, 0);
@@ -290,7 +339,7 @@ impl Emitter {
         }
     }
 
-    fn create_particle(&mut self) {
+    fn create_particle(&mut self, collider: Option<&ParticleCollider>) {
         let mut particle = match self.inner.emitter_type {
             1 => Particle::create_planar(self),
             2 => Particle::create_spherical(self),
@@ -302,7 +351,10 @@ impl Emitter {
             particle.position = transform(&particle.position, &self.model_mat);
             particle.velocity = mat4_to_mat3(&self.model_mat) * particle.velocity;
             if self.inner.check_flag(0x2000) {
-                particle.position[2] = 0.0;
+                match collider.and_then(|collider| collider.project_to_ground(&particle.position)) {
+                    Some(position) => particle.position = position,
+                    None => particle.position[2] = 0.0,
+                }
             }
         }
         if self.inner.check_flag(0x40) {
@@ -331,35 +383,14 @@ impl Emitter {
         let y_int = cell as u32 >> self.tex_col_bits;
         vec2(x_int as f32 * self.tex_scale_x, y_int as f32 * self.tex_scale_y)
     }
-}
-
-#[wasm_bindgen(js_class = "WowM2ParticleEmitter")]
-impl Emitter {
-    pub fn update(
-        &mut self,
-        dt_ms: f32,
-        animation_manager: &mut AnimationManager,
-        bone_transform_slice: &[f32],
-        bone_post_billboard_transform_slice: &[f32]
-    ) {
-        assert_eq!(bone_transform_slice.len(), 16);
-        assert_eq!(bone_post_billboard_transform_slice.len(), 16);
-
-        let dt_secs = dt_ms / 1000.0;
-        self.update_params(animation_manager);
-
-        let bone_transform = Mat4::from_column_slice(bone_transform_slice);
-        let bone_post_billboard_transform = Mat4::from_column_slice(bone_post_billboard_transform_slice);
-        self.model_mat.fill_with_identity();
-        self.model_mat.append_translation_mut(&self.position);
-        self.model_mat = bone_post_billboard_transform * bone_transform * self.model_mat;
-        self.model_mat = self.model_mat * self.particle_coordinate_fix;
 
+    // emits and simulates particles over `dt_secs`
+    fn step(&mut self, dt_secs: f32, collider: Option<&ParticleCollider>) {
         if self.params.enabled {
             self.particles_to_emit += self.emission_rate() * dt_secs;
             while self.particles_to_emit > 1.0 {
                 if self.particles.len() < self.max_particles {
-                    self.create_particle();
+                    self.create_particle(collider);
                 }
                 self.particles_to_emit -= 1.0;
             }
@@ -373,6 +404,21 @@ impl Emitter {
             particle.is_alive()
         });
 
+        // particles that move with their bone are only placed in the model
+        // when drawn, so collisions have to go through the bone too
+        let collision_space = match collider {
+            Some(collider) if self.collision_response != ParticleCollisionResponse::None => {
+                let to_world = if self.inner.translate_particle_with_bone() {
+                    collider.model_matrix * self.model_mat
+                } else {
+                    collider.model_matrix
+                };
+                let from_world = to_world.try_inverse().unwrap_or_else(Mat4::identity);
+                Some((collider.geometry, to_world, from_world))
+            },
+            _ => None,
+        };
+
         for i in 0..self.particles.len() {
             let age = self.particles[i].age;
             let age_pct = (age / max_lifespan) as f64;
@@ -381,13 +427,13 @@ impl Emitter {
             let default_scale = WowVec2 { x: 1.0, y: 1.0 };
             let default_head_cell = 0;
             let default_tail_cell = 0;
-            let mut rgb: Vec3 = animation_manager.get_particle_value(
+            let mut rgb: Vec3 = AnimationManager::get_particle_value(
                 age_pct,
                 &self.inner.color,
                 default_color
             ).into();
             rgb /= 255.0;
-            let alpha: f32 = animation_manager.get_particle_value(
+            let alpha: f32 = AnimationManager::get_particle_value(
                 age_pct,
                 &self.inner.alpha,
                 default_alpha
@@ -396,18 +442,18 @@ impl Emitter {
             self.particles[i].color[1] = rgb[1];
             self.particles[i].color[2] = rgb[2];
             self.particles[i].color[3] = alpha;
-            self.particles[i].scale = animation_manager.get_particle_value(
+            self.particles[i].scale = AnimationManager::get_particle_value(
                 age_pct,
                 &self.inner.scale,
                 default_scale
             ).into();
-            let head_cell = animation_manager.get_particle_value(
+            let head_cell = AnimationManager::get_particle_value(
                 age_pct,
                 &self.inner.head_cell,
                 default_head_cell
             );
             self.particles[i].tex_coord_head = self.extract_tex_coords(head_cell);
-            let tail_cell = animation_manager.get_particle_value(
+            let tail_cell = AnimationManager::get_particle_value(
                 age_pct,
                 &self.inner.tail_cell,
                 default_tail_cell
@@ -419,12 +465,61 @@ impl Emitter {
                 self.particles[i].velocity *= (1.0 - self.inner.drag).powf(dt_secs);
             }
             let dist = self.particles[i].velocity * dt_secs;
+            let start = self.particles[i].position;
             self.particles[i].position += dist;
+
+            if let Some((geometry, to_world, from_world)) = &collision_space {
+                let world_start = transform(&start, to_world);
+                let world_end = transform(&self.particles[i].position, to_world);
+                if let Some(hit) = geometry.intersect_segment(&world_start, &world_end) {
+                    let particle = &mut self.particles[i];
+                    if self.collision_response == ParticleCollisionResponse::Die {
+                        particle.age = particle.lifespan;
+                    } else {
+                        let point = world_start + (world_end - world_start) * hit.t + hit.normal * COLLISION_OFFSET;
+                        let velocity = mat4_to_mat3(to_world) * particle.velocity;
+                        let velocity = velocity - hit.normal * (1.0 + self.collision_bounciness) * velocity.dot(&hit.normal);
+                        particle.position = transform(&point, from_world);
+                        particle.velocity = mat4_to_mat3(from_world) * velocity;
+                    }
+                }
+            }
+        }
+        if collision_space.is_some() {
+            self.particles.retain(|particle| particle.is_alive());
         }
     }
 
-    pub fn fill_texture(&self, texture: &Float32Array) {
-        let mut data = vec![0.0; self.max_particles * TEXELS_PER_PARTICLE * 4];
+    fn set_bone_transform(&mut self, bone_transform: &Mat4, bone_post_billboard_transform: &Mat4) {
+        self.model_mat.fill_with_identity();
+        self.model_mat.append_translation_mut(&self.position);
+        self.model_mat = bone_post_billboard_transform * bone_transform * self.model_mat;
+        self.model_mat = self.model_mat * self.particle_coordinate_fix;
+    }
+
+    // steps the simulation by `dt_ms`, in fixed increments if a fixed
+    // timestep is set
+    fn advance(&mut self, dt_ms: f32, collider: Option<&ParticleCollider>) {
+        match self.fixed_timestep_ms {
+            Some(step_ms) => {
+                self.time_accumulator_ms += dt_ms;
+                let mut steps = 0;
+                while self.time_accumulator_ms >= step_ms && steps < MAX_FIXED_STEPS_PER_UPDATE {
+                    self.step(step_ms / 1000.0, collider);
+                    self.time_accumulator_ms -= step_ms;
+                    steps += 1;
+                }
+                if steps == MAX_FIXED_STEPS_PER_UPDATE {
+                    self.time_accumulator_ms = 0.0;
+                }
+            },
+            None => self.step(dt_ms / 1000.0, collider),
+        }
+    }
+
+    // writes TEXELS_PER_PARTICLE RGBA texels for each particle slot into
+    // `data`, which must hold `max_particles` worth
+    fn write_texels(&self, data: &mut [f32]) {
         for (i, particle) in self.particles.iter().enumerate() {
             let mut offs = TEXELS_PER_PARTICLE * i * 4;
             let mut pos = particle.position.clone();
@@ -447,6 +542,72 @@ impl Emitter {
                 }
             }
         }
+    }
+}
+
+#[wasm_bindgen(js_class = "WowM2ParticleEmitter")]
+impl Emitter {
+    pub fn update(
+        &mut self,
+        dt_ms: f32,
+        animation_manager: &AnimationManager,
+        bone_transform_slice: &[f32],
+        bone_post_billboard_transform_slice: &[f32]
+    ) {
+        assert_eq!(bone_transform_slice.len(), 16);
+        assert_eq!(bone_post_billboard_transform_slice.len(), 16);
+
+        self.update_params(animation_manager);
+        let bone_transform = Mat4::from_column_slice(bone_transform_slice);
+        let bone_post_billboard_transform = Mat4::from_column_slice(bone_post_billboard_transform_slice);
+        self.set_bone_transform(&bone_transform, &bone_post_billboard_transform);
+        self.advance(dt_ms, None);
+    }
+
+    // like `update()`, but particles collide with `geometry` according to
+    // `collision_response`. `model_matrix_slice` places the model in the
+    // geometry's space
+    pub fn update_with_collision(
+        &mut self,
+        dt_ms: f32,
+        animation_manager: &AnimationManager,
+        bone_transform_slice: &[f32],
+        bone_post_billboard_transform_slice: &[f32],
+        geometry: &CollisionGeometry,
+        model_matrix_slice: &[f32],
+    ) {
+        assert_eq!(bone_transform_slice.len(), 16);
+        assert_eq!(bone_post_billboard_transform_slice.len(), 16);
+        assert_eq!(model_matrix_slice.len(), 16);
+
+        self.update_params(animation_manager);
+        let bone_transform = Mat4::from_column_slice(bone_transform_slice);
+        let bone_post_billboard_transform = Mat4::from_column_slice(bone_post_billboard_transform_slice);
+        self.set_bone_transform(&bone_transform, &bone_post_billboard_transform);
+        let collider = ParticleCollider::new(geometry, Mat4::from_column_slice(model_matrix_slice));
+        self.advance(dt_ms, Some(&collider));
+    }
+
+    // reseeds the emitter and restarts its simulation, so that the same seed
+    // and sequence of updates always produces the same particles
+    pub fn set_seed(&mut self, seed: u32) {
+        self.rng = LcgRng::new(seed);
+        self.particles.clear();
+        self.particles_to_emit = 0.0;
+        self.time_accumulator_ms = 0.0;
+    }
+
+    // advances the simulation in fixed steps of `step_ms`, carrying over any
+    // leftover time to the next update. A step of 0 goes back to simulating
+    // each update's full dt
+    pub fn set_fixed_timestep(&mut self, step_ms: f32) {
+        self.fixed_timestep_ms = if step_ms > 0.0 { Some(step_ms) } else { None };
+        self.time_accumulator_ms = 0.0;
+    }
+
+    pub fn fill_texture(&self, texture: &Float32Array) {
+        let mut data = vec![0.0; self.max_particles * TEXELS_PER_PARTICLE * 4];
+        self.write_texels(&mut data);
         texture.copy_from(&data);
     }
 
@@ -471,8 +632,381 @@ impl Emitter {
     }
 }
 
+// each bone takes up its transform followed by its post-billboard transform
+const FLOATS_PER_BONE: usize = 32;
+
+// the emitters of one M2, which share its bones and animation state
+#[derive(Debug)]
+struct EmitterGroup {
+    handle: u32,
+    emitters: Vec<Emitter>,
+    num_bones: usize,
+    bone_offset: usize,
+    particle_offsets: Vec<usize>,
+    model_matrix: Option<Mat4>,
+}
+
+// owns the emitters of every loaded M2 so that they can be updated in one
+// call. The bones of all groups are packed into a single buffer, and all
+// particles into a single texture with a row per particle
+#[wasm_bindgen(js_name = "WowParticleSystemManager")]
+#[derive(Debug, Default)]
+pub struct ParticleSystemManager {
+    groups: Vec<EmitterGroup>,
+    next_handle: u32,
+    bone_buffer_size: usize,
+    num_particle_slots: usize,
+    fixed_timestep_ms: f32,
+}
+
+#[wasm_bindgen(js_class = "WowParticleSystemManager")]
+impl ParticleSystemManager {
+    pub fn new() -> ParticleSystemManager {
+        ParticleSystemManager::default()
+    }
+
+    // adds an M2's emitters, returning the handle the other methods take.
+    // `num_bones` is the number of bones the M2 has
+    pub fn add_emitters(&mut self, mut emitters: Vec<Emitter>, num_bones: usize) -> Result<u32, String> {
+        if let Some(emitter) = emitters.iter().find(|emitter| emitter.bone as usize >= num_bones) {
+            return Err(format!("emitter bone {} is out of range for {} bones", emitter.bone, num_bones));
+        }
+        emitters.iter_mut().for_each(|emitter| emitter.set_fixed_timestep(self.fixed_timestep_ms));
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.groups.push(EmitterGroup {
+            handle,
+            emitters,
+            num_bones,
+            bone_offset: 0,
+            particle_offsets: Vec::new(),
+            model_matrix: None,
+        });
+        self.update_layout();
+        Ok(handle)
+    }
+
+    // removing emitters shifts the bone and particle offsets of any groups
+    // added after them
+    pub fn remove_emitters(&mut self, handle: u32) -> Result<(), String> {
+        let index = self.group_index(handle)?;
+        self.groups.remove(index);
+        self.update_layout();
+        Ok(())
+    }
+
+    // refreshes a group's emission parameters from its M2's current
+    // animation state
+    pub fn update_params(&mut self, handle: u32, animation_manager: &AnimationManager) -> Result<(), String> {
+        let index = self.group_index(handle)?;
+        for emitter in &mut self.groups[index].emitters {
+            emitter.update_params(animation_manager);
+        }
+        Ok(())
+    }
+
+    // moves and simulates every emitter, with `bone_matrices` holding each
+    // group's bones at its bone offset
+    pub fn update(&mut self, dt_ms: f32, bone_matrices: &[f32]) -> Result<(), String> {
+        self.update_groups(dt_ms, bone_matrices, None)
+    }
+
+    // like `update()`, but the particles of groups with a model matrix
+    // collide with `geometry`
+    pub fn update_with_collision(&mut self, dt_ms: f32, bone_matrices: &[f32], geometry: &CollisionGeometry) -> Result<(), String> {
+        self.update_groups(dt_ms, bone_matrices, Some(geometry))
+    }
+
+    // places a group's M2 in the collision geometry's space
+    pub fn set_model_matrix(&mut self, handle: u32, model_matrix_slice: &[f32]) -> Result<(), String> {
+        if model_matrix_slice.len() != 16 {
+            return Err(format!("expected a 16 float matrix, got {}", model_matrix_slice.len()));
+        }
+        let index = self.group_index(handle)?;
+        self.groups[index].model_matrix = Some(Mat4::from_column_slice(model_matrix_slice));
+        Ok(())
+    }
+
+    pub fn fill_texture(&self, texture: &Float32Array) {
+        let mut data = vec![0.0; self.get_texture_size()];
+        self.write_texels(&mut data);
+        texture.copy_from(&data);
+    }
+
+    pub fn set_seed(&mut self, handle: u32, seed: u32) -> Result<(), String> {
+        let index = self.group_index(handle)?;
+        for (i, emitter) in self.groups[index].emitters.iter_mut().enumerate() {
+            emitter.set_seed(seed.wrapping_add(i as u32));
+        }
+        Ok(())
+    }
+
+    // applies to emitters added later, too. a step of 0 or less turns it off
+    pub fn set_fixed_timestep(&mut self, step_ms: f32) {
+        self.fixed_timestep_ms = step_ms;
+        for group in &mut self.groups {
+            group.emitters.iter_mut().for_each(|emitter| emitter.set_fixed_timestep(step_ms));
+        }
+    }
+
+    // the number of floats the bone matrix buffer passed to `update()` holds
+    pub fn get_bone_buffer_size(&self) -> usize {
+        self.bone_buffer_size
+    }
+
+    // the float offset of a group's first bone in the bone matrix buffer
+    pub fn get_bone_offset(&self, handle: u32) -> Result<usize, String> {
+        Ok(self.groups[self.group_index(handle)?].bone_offset)
+    }
+
+    // the number of floats the particle texture holds, at
+    // TEXELS_PER_PARTICLE RGBA texels per particle
+    pub fn get_texture_size(&self) -> usize {
+        self.num_particle_slots * TEXELS_PER_PARTICLE * 4
+    }
+
+    pub fn get_num_particle_slots(&self) -> usize {
+        self.num_particle_slots
+    }
+
+    // the texture row of an emitter's first particle
+    pub fn get_particle_offset(&self, handle: u32, emitter_index: usize) -> Result<usize, String> {
+        self.groups[self.group_index(handle)?].particle_offsets.get(emitter_index).copied()
+            .ok_or(format!("invalid emitter index {}", emitter_index))
+    }
+
+    // the live particle count of every emitter, in the order they were added
+    pub fn get_particle_counts(&self) -> Vec<u32> {
+        self.groups.iter()
+            .flat_map(|group| group.emitters.iter().map(|emitter| emitter.particles.len() as u32))
+            .collect()
+    }
+}
+
+// rust-only interface
+impl ParticleSystemManager {
+    pub fn write_texels(&self, data: &mut [f32]) {
+        for group in &self.groups {
+            for (emitter, &offset) in group.emitters.iter().zip(group.particle_offsets.iter()) {
+                let start = offset * TEXELS_PER_PARTICLE * 4;
+                let end = start + emitter.max_particles * TEXELS_PER_PARTICLE * 4;
+                let texels = &mut data[start..end];
+                texels.fill(0.0);
+                emitter.write_texels(texels);
+            }
+        }
+    }
+
+    fn update_groups(&mut self, dt_ms: f32, bone_matrices: &[f32], geometry: Option<&CollisionGeometry>) -> Result<(), String> {
+        if bone_matrices.len() != self.bone_buffer_size {
+            return Err(format!("expected {} floats of bone matrices, got {}", self.bone_buffer_size, bone_matrices.len()));
+        }
+        for group in &mut self.groups {
+            let collider = match (geometry, group.model_matrix) {
+                (Some(geometry), Some(model_matrix)) => Some(ParticleCollider::new(geometry, model_matrix)),
+                _ => None,
+            };
+            for emitter in &mut group.emitters {
+                let offset = group.bone_offset + emitter.bone as usize * FLOATS_PER_BONE;
+                let bone_transform = Mat4::from_column_slice(&bone_matrices[offset..offset + 16]);
+                let bone_post_billboard_transform = Mat4::from_column_slice(&bone_matrices[offset + 16..offset + 32]);
+                emitter.set_bone_transform(&bone_transform, &bone_post_billboard_transform);
+                emitter.advance(dt_ms, collider.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    fn group_index(&self, handle: u32) -> Result<usize, String> {
+        self.groups.iter().position(|group| group.handle == handle)
+            .ok_or(format!("unknown emitter group {}", handle))
+    }
+
+    fn update_layout(&mut self) {
+        self.bone_buffer_size = 0;
+        self.num_particle_slots = 0;
+        for group in &mut self.groups {
+            group.bone_offset = self.bone_buffer_size;
+            self.bone_buffer_size += group.num_bones * FLOATS_PER_BONE;
+            group.particle_offsets.clear();
+            for emitter in &group.emitters {
+                group.particle_offsets.push(self.num_particle_slots);
+                self.num_particle_slots += emitter.max_particles;
+            }
+        }
+    }
+}
+
 fn transform(p: &Vec3, m: &Mat4) -> Vec3 {
     let mut p_hom = vec3_to_vec4(p);
     p_hom[3] = 1.0;
     (m * p_hom).xyz()
 }
+
+#[cfg(test)]
+mod tests {
+    use deku::prelude::*;
+    use nalgebra_glm as glm;
+    use super::*;
+
+    fn build_emitter(seed: u32) -> Emitter {
+        let data = vec![0; 1024];
+        let (_, mut m2_emitter) = M2ParticleEmitter::from_bytes((&data, 0)).unwrap();
+        m2_emitter.emission_speed.allocate(&data).unwrap();
+        m2_emitter.speed_variation.allocate(&data).unwrap();
+        m2_emitter.vertical_range.allocate(&data).unwrap();
+        m2_emitter.horizontal_range.allocate(&data).unwrap();
+        m2_emitter.gravity.allocate(&data).unwrap();
+        m2_emitter.lifespan.allocate(&data).unwrap();
+        m2_emitter.emission_rate.allocate(&data).unwrap();
+        m2_emitter.emission_area_length.allocate(&data).unwrap();
+        m2_emitter.emission_area_width.allocate(&data).unwrap();
+        m2_emitter.z_source.allocate(&data).unwrap();
+        m2_emitter.color.allocate(&data).unwrap();
+        m2_emitter.alpha.allocate(&data).unwrap();
+        m2_emitter.scale.allocate(&data).unwrap();
+        m2_emitter.head_cell.allocate(&data).unwrap();
+        m2_emitter.tail_cell.allocate(&data).unwrap();
+        m2_emitter.enabled.allocate(&data).unwrap();
+        m2_emitter.spline_points = Some(Vec::new());
+        m2_emitter.emitter_type = 1;
+        m2_emitter.texture_dimension_rows = 1;
+        m2_emitter.texture_dimensions_cols = 1;
+        // all of the randomness comes from the variances, since the tracks
+        // are empty
+        m2_emitter.emission_rate_variance = 200.0;
+        m2_emitter.lifespan_variance = 100.0;
+        let mut emitter = Emitter::new(m2_emitter, 0, None, seed);
+        emitter.max_particles = 100;
+        emitter
+    }
+
+    fn simulate(emitter: &mut Emitter, frame_times_ms: &[f32]) -> String {
+        let animation_manager = AnimationManager::new(vec![], vec![], vec![], vec![], vec![], vec![], vec![]);
+        let identity = Mat4::identity();
+        for &dt_ms in frame_times_ms {
+            emitter.update(dt_ms, &animation_manager, identity.as_slice(), identity.as_slice());
+        }
+        format!("{} {:?}", emitter.particles_to_emit, emitter.particles)
+    }
+
+    #[test]
+    fn test_deterministic_particles() {
+        let frames = [16.0; 30];
+        let first = simulate(&mut build_emitter(1), &frames);
+        assert!(first.contains("Particle"));
+        assert_eq!(first, simulate(&mut build_emitter(1), &frames));
+        assert_ne!(first, simulate(&mut build_emitter(2), &frames));
+
+        let mut emitter = build_emitter(2);
+        simulate(&mut emitter, &frames);
+        emitter.set_seed(1);
+        assert_eq!(simulate(&mut emitter, &frames), first);
+
+        // with a fixed timestep, the frame rate doesn't change the results
+        let mut emitter = build_emitter(1);
+        emitter.set_fixed_timestep(10.0);
+        let fixed = simulate(&mut emitter, &[25.0; 40]);
+        assert!(fixed.contains("Particle"));
+        let mut emitter = build_emitter(1);
+        emitter.set_fixed_timestep(10.0);
+        assert_eq!(simulate(&mut emitter, &[40.0; 25]), fixed);
+    }
+
+    #[test]
+    fn test_particle_collision() {
+        let animation_manager = AnimationManager::new(vec![], vec![], vec![], vec![], vec![], vec![], vec![]);
+        let identity = Mat4::identity();
+        let mut ground = CollisionGeometry::new();
+        ground.push_plane(0.0, 0.0, 1.0, 1.0); // z = -1
+        let simulate_with_collision = |emitter: &mut Emitter| {
+            for _ in 0..30 {
+                emitter.update_with_collision(16.0, &animation_manager, identity.as_slice(), identity.as_slice(), &ground, identity.as_slice());
+            }
+        };
+
+        // particles spawn at the origin and get blown straight down
+        let mut falling = build_emitter(1);
+        falling.wind = vec3(0.0, 0.0, -50.0);
+        simulate(&mut falling, &[16.0; 30]);
+        assert!(falling.particles.iter().any(|particle| particle.position.z < -1.0));
+
+        let mut dying = build_emitter(1);
+        dying.wind = vec3(0.0, 0.0, -50.0);
+        dying.collision_response = ParticleCollisionResponse::Die;
+        simulate_with_collision(&mut dying);
+        assert!(dying.particles.iter().all(|particle| particle.position.z >= -1.0));
+        assert!(dying.particles.len() < falling.particles.len());
+
+        let mut bouncing = build_emitter(1);
+        bouncing.wind = vec3(0.0, 0.0, -50.0);
+        bouncing.collision_response = ParticleCollisionResponse::Bounce;
+        simulate_with_collision(&mut bouncing);
+        assert_eq!(bouncing.particles.len(), falling.particles.len());
+        assert!(bouncing.particles.iter().all(|particle| particle.position.z >= -1.0));
+        assert!(bouncing.particles.iter().any(|particle| particle.velocity.z > 0.0));
+
+        // clamped to ground particles spawn on the plane rather than at z = 0
+        let mut clamped = build_emitter(1);
+        clamped.inner.set_flags(0x2000, true);
+        simulate_with_collision(&mut clamped);
+        assert!(!clamped.particles.is_empty());
+        assert!(clamped.particles.iter().all(|particle| (particle.position.z + 1.0).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_particle_system_manager() {
+        let animation_manager = AnimationManager::new(vec![], vec![], vec![], vec![], vec![], vec![], vec![]);
+        let mut manager = ParticleSystemManager::new();
+        let first = manager.add_emitters(vec![build_emitter(1)], 1).unwrap();
+        let second = manager.add_emitters(vec![build_emitter(2), build_emitter(3)], 2).unwrap();
+        assert_eq!(manager.get_bone_buffer_size(), 3 * FLOATS_PER_BONE);
+        assert_eq!(manager.get_bone_offset(second).unwrap(), FLOATS_PER_BONE);
+        assert_eq!(manager.get_particle_offset(second, 1).unwrap(), 200);
+        assert_eq!(manager.get_num_particle_slots(), 300);
+
+        // the second group's first bone moves its particles up by 10
+        let identity = Mat4::identity();
+        let raised = glm::translation(&glm::vec3(0.0, 0.0, 10.0));
+        let mut bones = Vec::new();
+        for bone in [&identity, &identity, &raised, &identity, &identity, &identity] {
+            bones.extend_from_slice(bone.as_slice());
+        }
+        for group in [first, second] {
+            manager.update_params(group, &animation_manager).unwrap();
+        }
+        let mut emitters = [(first, 0, build_emitter(1), identity), (second, 0, build_emitter(2), raised), (second, 1, build_emitter(3), raised)];
+        for _ in 0..30 {
+            manager.update(16.0, &bones).unwrap();
+            for (_, _, emitter, bone) in &mut emitters {
+                emitter.update(16.0, &animation_manager, bone.as_slice(), identity.as_slice());
+            }
+        }
+        assert!(manager.update(16.0, &bones[16..]).is_err());
+
+        let counts = manager.get_particle_counts();
+        let mut texels = vec![0.0; manager.get_texture_size()];
+        manager.write_texels(&mut texels);
+        for (i, (group, emitter_index, emitter, _)) in emitters.iter().enumerate() {
+            assert_eq!(counts[i] as usize, emitter.particles.len());
+            let mut expected = vec![0.0; 100 * TEXELS_PER_PARTICLE * 4];
+            emitter.write_texels(&mut expected);
+            let start = manager.get_particle_offset(*group, *emitter_index).unwrap() * TEXELS_PER_PARTICLE * 4;
+            assert_eq!(&texels[start..start + expected.len()], expected.as_slice());
+        }
+
+        manager.remove_emitters(first).unwrap();
+        assert_eq!(manager.get_bone_offset(second).unwrap(), 0);
+        assert_eq!(manager.get_particle_offset(second, 0).unwrap(), 0);
+        assert!(manager.get_bone_offset(first).is_err());
+        assert!(manager.add_emitters(vec![build_emitter(1)], 0).is_err());
+
+        // emitters added after setting a fixed timestep use it too
+        manager.set_fixed_timestep(10.0);
+        let third = manager.add_emitters(vec![build_emitter(1)], 1).unwrap();
+        let index = manager.group_index(third).unwrap();
+        assert_eq!(manager.groups[index].emitters[0].fixed_timestep_ms, Some(10.0));
+        manager.set_fixed_timestep(0.0);
+        assert_eq!(manager.groups[index].emitters[0].fixed_timestep_ms, None);
+    }
+}
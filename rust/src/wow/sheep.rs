@@ -1,6 +1,11 @@
 use polymorph::sheepfile::{get_data_filename, reader::SheepfileReader, Entry};
 use wasm_bindgen::prelude::*;
-use std::{io::{Read, Seek}, path::Path};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
 /**
  * For more information about sheepfiles, check out `src/WorldOfWarcraft/util.ts`
@@ -49,16 +54,93 @@ impl SheepfileManager {
 }
 // QOL utils for local testing
 impl SheepfileManager {
-    pub fn load_file_id_data<P: AsRef<Path>>(sheepfile_path: P, file_id: u32) -> Result<Vec<u8>, polymorph::error::Error> {
-        let index_path = sheepfile_path.as_ref().join("index.shp");
-        let reader = SheepfileReader::parse(&std::fs::read(index_path).unwrap())?;
-        let entry = reader.get_entry_for_file_id(file_id).unwrap();
-        let data_filename = get_data_filename(entry.data_file_index as usize);
-        let data_path = sheepfile_path.as_ref().join(data_filename);
+    pub fn load_file_id_data<P: AsRef<Path>>(sheepfile_path: P, file_id: u32) -> Result<Vec<u8>, String> {
+        SheepfileDirectory::open(sheepfile_path)?.read_file_id(file_id)
+    }
+}
+
+// reads files straight out of a sheepfile dump on disk (an index.shp plus
+// its data files), for native tools and tests. Data files are kept open
+// between reads
+pub struct SheepfileDirectory {
+    path: PathBuf,
+    sheepfile: SheepfileReader,
+    data_files: HashMap<u32, File>,
+}
+
+impl SheepfileDirectory {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SheepfileDirectory, String> {
+        let path = path.as_ref().to_path_buf();
+        let index_path = path.join("index.shp");
+        let index = std::fs::read(&index_path)
+            .map_err(|e| format!("failed to read {}: {}", index_path.display(), e))?;
+        let sheepfile = SheepfileReader::parse(&index)
+            .map_err(|e| format!("failed to parse {}: {:?}", index_path.display(), e))?;
+        Ok(SheepfileDirectory {
+            path,
+            sheepfile,
+            data_files: HashMap::new(),
+        })
+    }
+
+    pub fn get_entry_for_file_id(&self, file_id: u32) -> Option<&Entry> {
+        self.sheepfile.get_entry_for_file_id(file_id)
+    }
+
+    pub fn get_entry_for_name(&self, file_name: &str) -> Option<&Entry> {
+        self.sheepfile.get_entry_for_name(file_name)
+    }
+
+    pub fn read_file_id(&mut self, file_id: u32) -> Result<Vec<u8>, String> {
+        let entry = self.get_entry_for_file_id(file_id)
+            .ok_or(format!("file ID {} isn't in the sheepfile", file_id))?
+            .clone();
+        self.read_entry(&entry)
+    }
+
+    pub fn read_file_name(&mut self, file_name: &str) -> Result<Vec<u8>, String> {
+        let entry = self.get_entry_for_name(file_name)
+            .ok_or(format!("{} isn't in the sheepfile", file_name))?
+            .clone();
+        self.read_entry(&entry)
+    }
+
+    // treats anything that parses as a number as a file ID, and everything
+    // else as a file name
+    pub fn read(&mut self, name_or_id: &str) -> Result<Vec<u8>, String> {
+        match name_or_id.parse::<u32>() {
+            Ok(file_id) => self.read_file_id(file_id),
+            Err(_) => self.read_file_name(name_or_id),
+        }
+    }
+
+    pub fn read_entry(&mut self, entry: &Entry) -> Result<Vec<u8>, String> {
+        let data_path = self.path.join(get_data_filename(entry.data_file_index as usize));
+        let file = match self.data_files.entry(entry.data_file_index) {
+            std::collections::hash_map::Entry::Occupied(file) => file.into_mut(),
+            std::collections::hash_map::Entry::Vacant(slot) => {
+                let file = File::open(&data_path)
+                    .map_err(|e| format!("failed to open {}: {}", data_path.display(), e))?;
+                slot.insert(file)
+            },
+        };
         let mut result = vec![0; entry.size_bytes as usize];
-        let mut file = std::fs::File::open(data_path).unwrap();
-        file.seek(std::io::SeekFrom::Start(entry.start_bytes as u64)).unwrap();
-        file.read_exact(&mut result).unwrap();
+        file.seek(SeekFrom::Start(entry.start_bytes as u64))
+            .and_then(|_| file.read_exact(&mut result))
+            .map_err(|e| format!("failed to read file ID {} from {}: {}", entry.file_id, data_path.display(), e))?;
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_sheepfile() {
+        let path = std::env::temp_dir().join("noclip-missing-sheepfile");
+        let err = SheepfileDirectory::open(&path).err().unwrap();
+        assert!(err.contains("index.shp"));
+        assert!(SheepfileManager::load_file_id_data(&path, 1).is_err());
+    }
+}
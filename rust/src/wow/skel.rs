@@ -0,0 +1,209 @@
+use deku::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use super::{
+    animation::{AnimFileId, M2CompBone, M2Sequence},
+    common::{parse, parse_array, ChunkedData, WowArray, WowCharArray},
+};
+
+#[derive(Debug, DekuRead, Clone)]
+struct SkeletonHeader {
+    pub _flags: u32,
+    pub name: WowCharArray,
+}
+
+// pointers in each of these chunks are relative to the start of the chunk
+#[derive(Debug, DekuRead, Clone)]
+struct SkeletonBoneHeader {
+    pub bones: WowArray<M2CompBone>,
+    pub _key_bone_lookup: WowArray<u16>,
+}
+
+#[derive(Debug, DekuRead, Clone)]
+struct SkeletonSequenceHeader {
+    pub global_sequence_durations: WowArray<u32>,
+    pub sequences: WowArray<M2Sequence>,
+    pub _sequence_lookups: WowArray<u16>,
+}
+
+#[derive(Debug, DekuRead, Clone)]
+struct SkeletonParent {
+    pub _unk: [u8; 8],
+    pub parent_skeleton_file_id: u32,
+}
+
+// A .skel file, which holds the bones and sequences for models (mostly
+// characters and creatures) that share a skeleton. Attach it to an M2's
+// animation manager with `WowM2AnimationManager::set_skeleton`.
+#[wasm_bindgen(js_name = "WowSkeleton", getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub name: String,
+    pub anim_file_ids: Vec<AnimFileId>,
+    pub bone_file_ids: Vec<u32>,
+    pub parent_skeleton_file_id: Option<u32>,
+    pub(crate) global_sequence_durations: Option<Vec<u32>>,
+    pub(crate) sequences: Option<Vec<M2Sequence>>,
+    pub(crate) bones: Option<Vec<M2CompBone>>,
+}
+
+#[wasm_bindgen(js_class = "WowSkeleton")]
+impl Skeleton {
+    pub fn new(data: &[u8]) -> Result<Skeleton, String> {
+        let mut name = String::new();
+        let mut anim_file_ids = Vec::new();
+        let mut bone_file_ids = Vec::new();
+        let mut parent_skeleton_file_id = None;
+        let mut bone_chunk: Option<(SkeletonBoneHeader, &[u8])> = None;
+        let mut global_sequence_durations = None;
+        let mut sequences = None;
+        for (chunk, chunk_data) in ChunkedData::new(data) {
+            match &chunk.magic {
+                b"SKL1" => {
+                    let header: SkeletonHeader = parse(chunk_data)?;
+                    name = header.name.to_string(chunk_data)?;
+                },
+                b"SKB1" => bone_chunk = Some((parse(chunk_data)?, chunk_data)),
+                b"SKS1" => {
+                    let header: SkeletonSequenceHeader = parse(chunk_data)?;
+                    global_sequence_durations = Some(header.global_sequence_durations.to_vec(chunk_data)?);
+                    sequences = Some(header.sequences.to_vec(chunk_data)?);
+                },
+                b"SKPD" => {
+                    let parent: SkeletonParent = parse(chunk_data)?;
+                    if parent.parent_skeleton_file_id != 0 {
+                        parent_skeleton_file_id = Some(parent.parent_skeleton_file_id);
+                    }
+                },
+                b"AFID" => anim_file_ids = parse_array(chunk_data, 8)?,
+                b"BFID" => bone_file_ids = parse_array(chunk_data, 4)?,
+                _ => {},
+            }
+        }
+
+        // bone tracks can only be allocated once we know which sequences are
+        // stored in .anim files, and SKS1 may come after SKB1
+        let mut bones = None;
+        if let Some((header, chunk_data)) = bone_chunk {
+            let sequences: &[M2Sequence] = sequences.as_deref().unwrap_or(&[]);
+            let mut skeleton_bones = header.bones.to_vec(chunk_data)?;
+            for bone in skeleton_bones.iter_mut() {
+                bone.allocate(chunk_data, sequences)?;
+            }
+            bones = Some(skeleton_bones);
+        }
+
+        Ok(Skeleton {
+            name,
+            anim_file_ids,
+            bone_file_ids,
+            parent_skeleton_file_id,
+            global_sequence_durations,
+            sequences,
+            bones,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wow::common::chunk;
+
+    fn push_u32s(buf: &mut Vec<u8>, values: &[u32]) {
+        for value in values {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn push_sequence(buf: &mut Vec<u8>, id: u16, flags: u32) {
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes()); // sub_id
+        push_u32s(buf, &[1000, 0, flags]); // duration, movespeed, flags
+        buf.extend_from_slice(&[0; 4]); // frequency + padding
+        push_u32s(buf, &[0, 0, 0]); // replay_min, replay_max, blend_time
+        buf.extend_from_slice(&[0; 28]); // bounds_aabb, bounds_radius
+        buf.extend_from_slice(&(-1i16).to_le_bytes()); // variation_next
+        buf.extend_from_slice(&0u16.to_le_bytes()); // alias_next
+    }
+
+    fn push_track(buf: &mut Vec<u8>, count: u32, timestamps_offset: u32, values_offset: u32) {
+        buf.extend_from_slice(&1u16.to_le_bytes()); // interpolation_type
+        buf.extend_from_slice(&(-1i16).to_le_bytes()); // global_sequence
+        push_u32s(buf, &[count, timestamps_offset, count, values_offset]);
+    }
+
+    #[test]
+    fn test_skeleton() {
+        let mut skl1 = Vec::new();
+        push_u32s(&mut skl1, &[0, 5, 12]);
+        skl1.extend_from_slice(b"root\0");
+
+        // one bone, with a translation track for two sequences. the second
+        // sequence's keyframe arrays point into its .anim file
+        let mut skb1 = Vec::new();
+        push_u32s(&mut skb1, &[1, 16, 0, 0]);
+        push_u32s(&mut skb1, &[0xffffffff, 0, 0]); // key_bone_id, flags, parent_bone + submesh_id
+        push_u32s(&mut skb1, &[0]); // bone_name_crc
+        push_track(&mut skb1, 2, 104, 120);
+        push_track(&mut skb1, 0, 0, 0);
+        push_track(&mut skb1, 0, 0, 0);
+        skb1.extend_from_slice(&[0; 12]); // pivot
+        assert_eq!(skb1.len(), 104);
+        push_u32s(&mut skb1, &[1, 136, 1, 0]);
+        push_u32s(&mut skb1, &[1, 140, 1, 4]);
+        push_u32s(&mut skb1, &[0]);
+        for value in [1.0f32, 2.0, 3.0] {
+            skb1.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut sks1 = Vec::new();
+        push_u32s(&mut sks1, &[1, 24, 2, 28, 0, 0]);
+        push_u32s(&mut sks1, &[2000]);
+        push_sequence(&mut sks1, 0, 0x20);
+        push_sequence(&mut sks1, 4, 0);
+
+        let mut afid = Vec::new();
+        afid.extend_from_slice(&4u16.to_le_bytes());
+        afid.extend_from_slice(&0u16.to_le_bytes());
+        push_u32s(&mut afid, &[12345]);
+
+        // SKS1 comes after SKB1, so bone allocation has to wait for it
+        let mut data = chunk(b"SKL1", &skl1);
+        data.extend(chunk(b"SKB1", &skb1));
+        data.extend(chunk(b"SKS1", &sks1));
+        data.extend(chunk(b"AFID", &afid));
+        data.extend(chunk(b"BFID", &[0x39, 0x30, 0, 0]));
+        let skeleton = Skeleton::new(&data).unwrap();
+
+        assert_eq!(skeleton.name, "root");
+        assert_eq!(skeleton.global_sequence_durations, Some(vec![2000]));
+        assert_eq!(skeleton.anim_file_ids.len(), 1);
+        assert_eq!(skeleton.anim_file_ids[0].anim_id, 4);
+        assert_eq!(skeleton.anim_file_ids[0].file_id, 12345);
+        assert_eq!(skeleton.bone_file_ids, vec![12345]);
+        assert_eq!(skeleton.parent_skeleton_file_id, None);
+
+        let sequences = skeleton.sequences.as_ref().unwrap();
+        assert_eq!(sequences.len(), 2);
+        assert!(!sequences[0].is_external());
+        assert!(sequences[1].is_external());
+
+        let mut bones = skeleton.bones.clone().unwrap();
+        assert_eq!(bones.len(), 1);
+        let translation = &bones[0].translation;
+        assert_eq!(translation.timestamps(), &vec![vec![0], vec![]]);
+        assert_eq!(translation.values()[0][0].y, 2.0);
+        assert!(translation.values()[1].is_empty());
+
+        let mut anim = Vec::new();
+        push_u32s(&mut anim, &[500]);
+        for value in [4.0f32, 5.0, 6.0] {
+            anim.extend_from_slice(&value.to_le_bytes());
+        }
+        bones[0].allocate_external(1, &anim).unwrap();
+        let translation = &bones[0].translation;
+        assert_eq!(translation.timestamps()[1], vec![500]);
+        assert_eq!(translation.values()[1][0].z, 6.0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use wasm_bindgen::prelude::*;
+
+// Blizzard encrypts some CASC content with 16-byte "TACT" keys, identified by
+// a 64-bit key name. Keys get published once the content is live, see
+// https://wowdev.wiki/TACT#Encryption and https://github.com/wowdev/TACTKeys
+#[wasm_bindgen(js_name = "WowTactKeyStore")]
+#[derive(Debug, Clone, Default)]
+pub struct TactKeyStore {
+    keys: HashMap<u64, [u8; 16]>,
+}
+
+#[wasm_bindgen(js_class = "WowTactKeyStore")]
+impl TactKeyStore {
+    pub fn new() -> TactKeyStore {
+        TactKeyStore::default()
+    }
+
+    pub fn add_key(&mut self, key_name: u64, key: &[u8]) -> Result<(), String> {
+        let key: [u8; 16] = key.try_into()
+            .map_err(|_| format!("TACT keys are 16 bytes, got {}", key.len()))?;
+        self.keys.insert(key_name, key);
+        Ok(())
+    }
+
+    // parses the "<key name> <key>" hex lines of a WoW.txt style key list,
+    // returning how many keys were added
+    pub fn add_keys_from_text(&mut self, text: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            let (key_name, key) = match (parts.next(), parts.next()) {
+                (Some(key_name), Some(key)) => (key_name, key),
+                _ => continue,
+            };
+            let key_name = u64::from_str_radix(key_name, 16)
+                .map_err(|e| format!("invalid key name {}: {:?}", key_name, e))?;
+            self.add_key(key_name, &parse_hex(key)?)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn has_key(&self, key_name: u64) -> bool {
+        self.keys.contains_key(&key_name)
+    }
+
+    // decrypts an encrypted ('E' mode) BLTE chunk, returning the inner chunk
+    // (which starts with its own mode byte). `chunk_index` is the chunk's
+    // position in the BLTE file, which is mixed into the nonce
+    pub fn decrypt_blte_chunk(&self, chunk: &[u8], chunk_index: u32) -> Result<Vec<u8>, String> {
+        let header = chunk.get(..16).ok_or("encrypted chunk too short")?;
+        if header[0] != b'E' {
+            return Err(format!("expected an encrypted chunk, got mode {}", header[0] as char));
+        }
+        if header[1] != 8 || header[10] != 4 {
+            return Err(format!("unexpected key name size {} or IV size {}", header[1], header[10]));
+        }
+        let key_name = u64::from_le_bytes(header[2..10].try_into().unwrap());
+        let key = self.get_key(key_name)
+            .ok_or(format!("missing TACT key {:016X}", key_name))?;
+        let mut nonce = [0; 8];
+        nonce[..4].copy_from_slice(&header[11..15]);
+        for (i, byte) in chunk_index.to_le_bytes().iter().enumerate() {
+            nonce[i] ^= byte;
+        }
+        match header[15] {
+            b'S' => {
+                let mut data = chunk[16..].to_vec();
+                salsa20_xor(key, &nonce, &mut data);
+                Ok(data)
+            },
+            encryption_type => Err(format!("unsupported encryption type {}", encryption_type as char)),
+        }
+    }
+}
+
+// rust-only interface
+impl TactKeyStore {
+    pub fn get_key(&self, key_name: u64) -> Option<&[u8; 16]> {
+        self.keys.get(&key_name)
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd length hex string {}", s));
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| format!("invalid hex {}: {:?}", s, e)))
+        .collect()
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[b] ^= state[a].wrapping_add(state[d]).rotate_left(7);
+    state[c] ^= state[b].wrapping_add(state[a]).rotate_left(9);
+    state[d] ^= state[c].wrapping_add(state[b]).rotate_left(13);
+    state[a] ^= state[d].wrapping_add(state[c]).rotate_left(18);
+}
+
+// Salsa20/20 with a 128-bit key, which the usual crates don't support
+pub fn salsa20_xor(key: &[u8; 16], nonce: &[u8; 8], data: &mut [u8]) {
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let tau = b"expand 16-byte k";
+    let mut input = [0u32; 16];
+    for i in 0..4 {
+        input[[0, 5, 10, 15][i]] = word(&tau[i * 4..]);
+        input[1 + i] = word(&key[i * 4..]);
+        input[11 + i] = word(&key[i * 4..]);
+    }
+    input[6] = word(&nonce[0..]);
+    input[7] = word(&nonce[4..]);
+
+    for (block_index, block) in data.chunks_mut(64).enumerate() {
+        input[8] = block_index as u32;
+        input[9] = (block_index as u64 >> 32) as u32;
+        let mut state = input;
+        for _ in 0..10 {
+            quarter_round(&mut state, 0, 4, 8, 12);
+            quarter_round(&mut state, 5, 9, 13, 1);
+            quarter_round(&mut state, 10, 14, 2, 6);
+            quarter_round(&mut state, 15, 3, 7, 11);
+            quarter_round(&mut state, 0, 1, 2, 3);
+            quarter_round(&mut state, 5, 6, 7, 4);
+            quarter_round(&mut state, 10, 11, 8, 9);
+            quarter_round(&mut state, 15, 12, 13, 14);
+        }
+        let mut keystream = [0u8; 64];
+        for i in 0..16 {
+            keystream[i * 4..i * 4 + 4].copy_from_slice(&state[i].wrapping_add(input[i]).to_le_bytes());
+        }
+        for (byte, k) in block.iter_mut().zip(keystream.iter()) {
+            *byte ^= k;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_salsa20() {
+        // eSTREAM Salsa20/20 128-bit key, set 1 vector 0
+        let mut key = [0; 16];
+        key[0] = 0x80;
+        let mut data = [0; 8];
+        salsa20_xor(&key, &[0; 8], &mut data);
+        assert_eq!(data, [0x4D, 0xFA, 0x5E, 0x48, 0x1D, 0xA2, 0x3E, 0xA0]);
+    }
+
+    #[test]
+    fn test_decrypt_blte_chunk() {
+        let mut keys = TactKeyStore::new();
+        let count = keys.add_keys_from_text("FA505078126ACB3E BDC51862ABED79B2DE48C8E7E66C6200\n\n").unwrap();
+        assert_eq!(count, 1);
+        assert!(keys.add_key(1, &[0; 4]).is_err());
+
+        let key_name = 0xFA505078126ACB3E;
+        let plaintext = b"Nhello world".to_vec();
+        let mut encrypted = plaintext.clone();
+        let chunk_index = 2u32;
+        let iv = [1, 2, 3, 4];
+        let nonce = [iv[0] ^ 2, iv[1], iv[2], iv[3], 0, 0, 0, 0];
+        salsa20_xor(keys.get_key(key_name).unwrap(), &nonce, &mut encrypted);
+
+        let mut chunk = vec![b'E', 8];
+        chunk.extend_from_slice(&u64::to_le_bytes(key_name));
+        chunk.push(4);
+        chunk.extend_from_slice(&iv);
+        chunk.push(b'S');
+        chunk.extend_from_slice(&encrypted);
+        assert_eq!(keys.decrypt_blte_chunk(&chunk, chunk_index).unwrap(), plaintext);
+
+        assert!(TactKeyStore::new().decrypt_blte_chunk(&chunk, chunk_index).is_err());
+    }
+}
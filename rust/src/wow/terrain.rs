@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+
+use nalgebra_glm as glm;
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::{CollisionGeometry, Heightfield};
+
+use super::adt::{Adt, MapChunkHeader, CHUNK_SIZE, TILE_SIZE, UNIT_SIZE};
+use super::common::{AABBox, Vec3};
+
+// world coordinates run from +MAP_ORIGIN at the top left corner of tile
+// (0, 0) down to -MAP_ORIGIN
+pub const MAP_ORIGIN: f32 = 32.0 * TILE_SIZE;
+
+pub const VERTICES_PER_CHUNK: usize = 9 * 9 + 8 * 8;
+
+#[derive(Debug, Clone)]
+struct TerrainChunk {
+    header: MapChunkHeader,
+    heights: [f32; VERTICES_PER_CHUNK],
+}
+
+impl TerrainChunk {
+    // MCVT vertices alternate between rows of 9 outer vertices and rows of 8
+    // inner vertices, which sit in the middle of each quad
+    fn vertex(&self, index: usize) -> glm::Vec3 {
+        let mut row = (index / 17) as f32;
+        let mut col = (index % 17) as f32;
+        if col > 8.0 {
+            row += 0.5;
+            col -= 8.5;
+        }
+        let position = &self.header.position;
+        glm::vec3(
+            position.x - row * UNIT_SIZE,
+            position.y - col * UNIT_SIZE,
+            position.z + self.heights[index],
+        )
+    }
+
+    fn query(&self, x: f32, y: f32) -> Option<(f32, glm::Vec3)> {
+        let fx = ((self.header.position.x - x) / UNIT_SIZE).clamp(0.0, 8.0);
+        let fy = ((self.header.position.y - y) / UNIT_SIZE).clamp(0.0, 8.0);
+        let row = (fx as usize).min(7);
+        let col = (fy as usize).min(7);
+        if self.header.is_hole(col, row) {
+            return None;
+        }
+
+        // each quad is split into 4 triangles around its inner vertex, in
+        // the same way as the ADT index buffer
+        let (u, v) = (fx - row as f32, fy - col as f32);
+        let base = row * 17 + col;
+        let (a, b) = if v < u && v < 1.0 - u {
+            (base, base + 17)
+        } else if u < v && u < 1.0 - v {
+            (base + 1, base)
+        } else if v > u {
+            (base + 18, base + 1)
+        } else {
+            (base + 17, base + 18)
+        };
+        let p0 = self.vertex(base + 9);
+        let p1 = self.vertex(a);
+        let p2 = self.vertex(b);
+
+        let x = self.header.position.x - fx * UNIT_SIZE;
+        let y = self.header.position.y - fy * UNIT_SIZE;
+        let d = (p1.y - p2.y) * (p0.x - p2.x) + (p2.x - p1.x) * (p0.y - p2.y);
+        let w0 = ((p1.y - p2.y) * (x - p2.x) + (p2.x - p1.x) * (y - p2.y)) / d;
+        let w1 = ((p2.y - p0.y) * (x - p2.x) + (p0.x - p2.x) * (y - p2.y)) / d;
+        let w2 = 1.0 - w0 - w1;
+        let height = w0 * p0.z + w1 * p1.z + w2 * p2.z;
+
+        let mut normal = glm::normalize(&glm::cross(&(p1 - p0), &(p2 - p0)));
+        if normal.z < 0.0 {
+            normal = -normal;
+        }
+        Some((height, normal))
+    }
+}
+
+// index of the chunk containing a world position, counting rows down from
+// +x and columns down from +y
+fn chunk_key(x: f32, y: f32) -> (i32, i32) {
+    (
+        ((MAP_ORIGIN - x) / CHUNK_SIZE).floor() as i32,
+        ((MAP_ORIGIN - y) / CHUNK_SIZE).floor() as i32,
+    )
+}
+
+// the ground height of every loaded ADT, for keeping the camera on the
+// ground and snapping things to the terrain
+#[wasm_bindgen(js_name = "WowTerrainHeightfield")]
+#[derive(Debug, Clone, Default)]
+pub struct TerrainHeightfield {
+    chunks: HashMap<(i32, i32), TerrainChunk>,
+}
+
+#[wasm_bindgen(js_class = "WowTerrainHeightfield")]
+impl TerrainHeightfield {
+    pub fn new() -> TerrainHeightfield {
+        TerrainHeightfield::default()
+    }
+
+    pub fn add_adt(&mut self, adt: &Adt) {
+        for chunk in adt.get_map_chunks() {
+            self.add_chunk(chunk.header.clone(), chunk.heightmap.heightmap);
+        }
+    }
+
+    // tile coordinates are as in ADT filenames, i.e. map_{tile_x}_{tile_y}.adt
+    pub fn remove_tile(&mut self, tile_x: i32, tile_y: i32) {
+        self.chunks.retain(|&(row, col), _| row.div_euclid(16) != tile_y || col.div_euclid(16) != tile_x);
+    }
+
+    // returns None outside of loaded terrain, or over holes
+    pub fn get_height(&self, x: f32, y: f32) -> Option<f32> {
+        self.query(x, y).map(|(height, _)| height)
+    }
+
+    pub fn get_normal(&self, x: f32, y: f32) -> Option<Vec3> {
+        self.query(x, y).map(|(_, normal)| Vec3 { x: normal.x, y: normal.y, z: normal.z })
+    }
+
+    // the heightfield is copied, so tiles loaded into it afterwards won't
+    // collide until it's added again
+    pub fn add_to_collision_geometry(&self, geometry: &mut CollisionGeometry) {
+        geometry.add_heightfield(Box::new(self.clone()));
+    }
+}
+
+// rust-only interface
+impl TerrainHeightfield {
+    fn add_chunk(&mut self, header: MapChunkHeader, heights: [f32; VERTICES_PER_CHUNK]) {
+        // a chunk's position is its +x/+y corner, so nudge it inwards to avoid
+        // float error putting it in its neighbour's cell
+        let key = chunk_key(header.position.x - UNIT_SIZE, header.position.y - UNIT_SIZE);
+        self.chunks.insert(key, TerrainChunk { header, heights });
+    }
+
+    pub fn query(&self, x: f32, y: f32) -> Option<(f32, glm::Vec3)> {
+        self.chunks.get(&chunk_key(x, y))?.query(x, y)
+    }
+}
+
+impl Heightfield for TerrainHeightfield {
+    fn query(&self, x: f32, y: f32) -> Option<(f32, glm::Vec3)> {
+        TerrainHeightfield::query(self, x, y)
+    }
+}
+
+// the outer vertices of an ADT's 16x16 chunks form a 129x129 grid
+const ADT_GRID_SIZE: usize = 16 * 8 + 1;
+
+#[wasm_bindgen(js_name = "WowAdtLodTerrain", getter_with_clone)]
+pub struct AdtLodTerrain {
+    // interleaved positions and normals, 6 floats per vertex
+    pub vertex_buffer: Option<Vec<f32>>,
+    pub index_buffer: Option<Vec<u16>>,
+    pub extents: AABBox,
+}
+
+#[wasm_bindgen(js_class = "WowAdtLodTerrain")]
+impl AdtLodTerrain {
+    pub fn take_vertex_buffer(&mut self) -> Vec<f32> {
+        self.vertex_buffer.take().expect("AdtLodTerrain vertex buffer already taken")
+    }
+
+    pub fn take_index_buffer(&mut self) -> Vec<u16> {
+        self.index_buffer.take().expect("AdtLodTerrain index buffer already taken")
+    }
+}
+
+// rust-only interface
+impl AdtLodTerrain {
+    // builds a simplified mesh of the whole ADT for distant terrain, keeping
+    // every 2^lod_level'th outer MCVT vertex. inner vertices and holes are
+    // dropped, and a skirt hanging `skirt_depth` units down from the tile's
+    // border hides cracks between neighbouring tiles of different LODs.
+    //
+    // the only LOD data an Adt holds, `lod_levels`, comes from _obj1.adt's
+    // MLFD chunk and just splits the LOD doodads and WMOs into levels. the
+    // precomputed terrain in _lod.adt (MLVH/MLVI) isn't parsed, so the mesh
+    // is always decimated from the full detail heights
+    pub fn new(adt: &Adt, lod_level: u32, skirt_depth: f32) -> Result<AdtLodTerrain, String> {
+        let map_chunks = adt.get_map_chunks();
+        if map_chunks.len() != 256 {
+            return Err(format!("expected 256 map chunks, found {}", map_chunks.len()));
+        }
+        if lod_level > 7 {
+            return Err(format!("LOD level {} is coarser than a single quad", lod_level));
+        }
+        let step = 1 << lod_level;
+        let size = (ADT_GRID_SIZE - 1) / step + 1;
+
+        // MCNKs are ordered by rows along -x, each row running along -y
+        let grid_position = |row: usize, col: usize| -> glm::Vec3 {
+            let chunk = &map_chunks[(row / 8).min(15) * 16 + (col / 8).min(15)];
+            let local_row = row - (row / 8).min(15) * 8;
+            let local_col = col - (col / 8).min(15) * 8;
+            let position = &chunk.header.position;
+            glm::vec3(
+                position.x - local_row as f32 * UNIT_SIZE,
+                position.y - local_col as f32 * UNIT_SIZE,
+                position.z + chunk.heightmap.heightmap[local_row * 17 + local_col],
+            )
+        };
+
+        let mut positions = Vec::with_capacity(size * size);
+        for row in 0..size {
+            for col in 0..size {
+                positions.push(grid_position(row * step, col * step));
+            }
+        }
+        let mut normals = Vec::with_capacity(size * size);
+        for row in 0..size {
+            for col in 0..size {
+                let dx = positions[row.saturating_sub(1) * size + col] - positions[(row + 1).min(size - 1) * size + col];
+                let dy = positions[row * size + col.saturating_sub(1)] - positions[row * size + (col + 1).min(size - 1)];
+                normals.push(glm::normalize(&glm::cross(&dx, &dy)));
+            }
+        }
+
+        let mut indices = Vec::with_capacity((size - 1) * (size - 1) * 6);
+        for row in 0..size - 1 {
+            for col in 0..size - 1 {
+                let i = (row * size + col) as u16;
+                let below = i + size as u16;
+                indices.extend_from_slice(&[i, below, i + 1, below, below + 1, i + 1]);
+            }
+        }
+
+        // walk the border counter-clockwise (seen from above), so that each
+        // skirt quad faces outwards
+        let mut border = Vec::with_capacity(4 * (size - 1));
+        border.extend((0..size - 1).map(|row| row * size));
+        border.extend((0..size - 1).map(|col| (size - 1) * size + col));
+        border.extend((1..size).rev().map(|row| row * size + size - 1));
+        border.extend((1..size).rev());
+        let skirt_start = positions.len();
+        for (i, &top) in border.iter().enumerate() {
+            positions.push(positions[top] - glm::vec3(0.0, 0.0, skirt_depth));
+            normals.push(normals[top]);
+            let next = (i + 1) % border.len();
+            let (a_top, b_top) = (top as u16, border[next] as u16);
+            let (a_bottom, b_bottom) = ((skirt_start + i) as u16, (skirt_start + next) as u16);
+            indices.extend_from_slice(&[a_top, a_bottom, b_top, b_top, a_bottom, b_bottom]);
+        }
+
+        let mut vertex_buffer = Vec::with_capacity(positions.len() * 6);
+        let mut extents = AABBox::default();
+        for (position, normal) in positions.iter().zip(normals.iter()) {
+            vertex_buffer.extend_from_slice(&[position.x, position.y, position.z, normal.x, normal.y, normal.z]);
+            extents.update(position.x, position.y, position.z);
+        }
+        Ok(AdtLodTerrain {
+            vertex_buffer: Some(vertex_buffer),
+            index_buffer: Some(indices),
+            extents,
+        })
+    }
+}
+
+// terrain textures repeat once per quad, so 8 times across a chunk
+const TEXTURE_REPEATS_PER_CHUNK: usize = 8;
+
+#[derive(Debug, Clone)]
+struct BakerTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl BakerTexture {
+    // box filters the texture down by an integer factor, so that nearest
+    // sampling doesn't alias when baking to a small output
+    fn downsample(self, max_size: usize) -> BakerTexture {
+        let factor = (self.width.max(self.height) / max_size.max(1)).max(1);
+        if factor == 1 {
+            return self;
+        }
+        let width = (self.width / factor).max(1);
+        let height = (self.height / factor).max(1);
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0u32; 4];
+                let mut count = 0;
+                for sy in y * factor..((y + 1) * factor).min(self.height) {
+                    for sx in x * factor..((x + 1) * factor).min(self.width) {
+                        let i = (sy * self.width + sx) * 4;
+                        for (c, total) in sum.iter_mut().enumerate() {
+                            *total += self.pixels[i + c] as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                pixels.extend(sum.iter().map(|v| (v / count) as u8));
+            }
+        }
+        BakerTexture { width, height, pixels }
+    }
+
+    // `u` and `v` wrap around
+    fn sample(&self, u: f32, v: f32) -> &[u8] {
+        let x = ((u.rem_euclid(1.0) * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v.rem_euclid(1.0) * self.height as f32) as usize).min(self.height - 1);
+        let i = (y * self.width + x) * 4;
+        &self.pixels[i..i + 4]
+    }
+}
+
+// bakes ADT chunks' blended texture layers into a single RGBA texture, for
+// distant terrain and exports. textures are registered by file ID, already
+// decoded to RGBA
+#[wasm_bindgen(js_name = "WowTerrainTextureBaker")]
+#[derive(Debug, Clone)]
+pub struct TerrainTextureBaker {
+    chunk_size: usize,
+    textures: HashMap<u32, BakerTexture>,
+}
+
+#[wasm_bindgen(js_class = "WowTerrainTextureBaker")]
+impl TerrainTextureBaker {
+    // `chunk_size` is the width and height in pixels of each baked chunk
+    pub fn new(chunk_size: usize) -> TerrainTextureBaker {
+        TerrainTextureBaker {
+            chunk_size: chunk_size.max(1),
+            textures: HashMap::new(),
+        }
+    }
+
+    // registers a diffuse or height (_h) texture
+    pub fn add_texture(&mut self, file_id: u32, pixels: Vec<u8>, width: usize, height: usize) -> Result<(), String> {
+        if width == 0 || height == 0 || pixels.len() != width * height * 4 {
+            return Err(format!("expected {}x{} RGBA pixels, got {} bytes", width, height, pixels.len()));
+        }
+        let texture = BakerTexture { width, height, pixels };
+        let texel_footprint = self.chunk_size / TEXTURE_REPEATS_PER_CHUNK;
+        self.textures.insert(file_id, texture.downsample(texel_footprint));
+        Ok(())
+    }
+
+    pub fn has_texture(&self, file_id: u32) -> bool {
+        self.textures.contains_key(&file_id)
+    }
+
+    // returns chunk_size * chunk_size RGBA pixels, with rows running along
+    // -x and columns along -y, like the chunk's vertices. the flags are the
+    // same as for WowAdt.get_render_result
+    pub fn bake_chunk(&self, adt: &Adt, chunk_index: usize, adt_has_big_alpha: bool, adt_has_height_texturing: bool) -> Result<Vec<u8>, String> {
+        let chunk = adt.get_map_chunks().get(chunk_index)
+            .ok_or(format!("invalid chunk index {}", chunk_index))?;
+        let size = self.chunk_size;
+        let mut result = vec![0; size * size * 4];
+        if chunk.texture_layers.is_empty() {
+            return Ok(result);
+        }
+
+        struct Layer<'a> {
+            diffuse: &'a BakerTexture,
+            height: Option<&'a BakerTexture>,
+            height_scale: f32,
+            height_offset: f32,
+        }
+        let mut layers = Vec::with_capacity(chunk.texture_layers.len());
+        for layer in chunk.texture_layers.iter().take(4) {
+            let texture_index = layer.texture_index as usize;
+            let diffuse_id = adt.get_diffuse_texture_id(texture_index)
+                .ok_or(format!("chunk {} has no diffuse texture {}", chunk_index, texture_index))?;
+            let diffuse = self.textures.get(&diffuse_id)
+                .ok_or(format!("texture {} hasn't been added", diffuse_id))?;
+            let params = adt.get_texture_params(texture_index);
+            let height = match adt.get_height_texture_id(texture_index) {
+                Some(height_id) if adt_has_height_texturing => self.textures.get(&height_id),
+                _ => None,
+            };
+            layers.push(Layer {
+                diffuse,
+                height,
+                height_scale: params.map_or(0.0, |params| params.height_scale),
+                height_offset: params.map_or(1.0, |params| params.height_offset),
+            });
+        }
+        let alpha_texture = chunk.build_alpha_texture(adt_has_big_alpha, adt_has_height_texturing);
+
+        for py in 0..size {
+            for px in 0..size {
+                let alpha_index = ((py * 64 / size) * 64 + px * 64 / size) * 4;
+                let mut weights = [0.0f32; 4];
+                for (i, weight) in weights.iter_mut().enumerate().take(layers.len()).skip(1) {
+                    *weight = match &alpha_texture {
+                        Some(alpha) => alpha[alpha_index + i] as f32 / 255.0,
+                        None => 0.0,
+                    };
+                }
+                weights[0] = 1.0 - (weights[1] + weights[2] + weights[3]).min(1.0);
+
+                let u = (px as f32 + 0.5) / size as f32 * TEXTURE_REPEATS_PER_CHUNK as f32;
+                let v = (py as f32 + 0.5) / size as f32 * TEXTURE_REPEATS_PER_CHUNK as f32;
+                if layers.iter().any(|layer| layer.height.is_some()) {
+                    // scale each layer by its height, then let the tallest
+                    // layers win, as in the terrain shader
+                    for (weight, layer) in weights.iter_mut().zip(layers.iter()) {
+                        let height = layer.height.map_or(0.0, |texture| texture.sample(u, v)[3] as f32 / 255.0);
+                        *weight *= height * layer.height_scale + layer.height_offset;
+                    }
+                    let max_weight = weights.iter().cloned().fold(0.0, f32::max);
+                    for weight in weights.iter_mut() {
+                        *weight *= 1.0 - (max_weight - *weight).clamp(0.0, 1.0);
+                    }
+                }
+                let total: f32 = weights.iter().sum();
+                if total <= 0.0 {
+                    continue;
+                }
+
+                let mut color = [0.0f32; 3];
+                for (weight, layer) in weights.iter().zip(layers.iter()) {
+                    let texel = layer.diffuse.sample(u, v);
+                    for c in 0..3 {
+                        color[c] += texel[c] as f32 * weight / total;
+                    }
+                }
+                let i = (py * size + px) * 4;
+                for c in 0..3 {
+                    result[i + c] = color[c].round().clamp(0.0, 255.0) as u8;
+                }
+                result[i + 3] = 255;
+            }
+        }
+        Ok(result)
+    }
+
+    // bakes all 16x16 chunks into one (16 * chunk_size)^2 RGBA texture
+    pub fn bake_adt(&self, adt: &Adt, adt_has_big_alpha: bool, adt_has_height_texturing: bool) -> Result<Vec<u8>, String> {
+        let size = self.chunk_size;
+        let stride = 16 * size * 4;
+        let mut result = vec![0; 16 * size * stride];
+        for chunk_index in 0..adt.get_map_chunks().len().min(256) {
+            let chunk = self.bake_chunk(adt, chunk_index, adt_has_big_alpha, adt_has_height_texturing)?;
+            let (chunk_row, chunk_col) = (chunk_index / 16, chunk_index % 16);
+            for y in 0..size {
+                let start = (chunk_row * size + y) * stride + chunk_col * size * 4;
+                result[start..start + size * 4].copy_from_slice(&chunk[y * size * 4..(y + 1) * size * 4]);
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deku::prelude::*;
+    use crate::wow::common::chunk;
+
+    fn flat_header(x: f32, y: f32, z: f32) -> MapChunkHeader {
+        let (_, mut header) = MapChunkHeader::from_bytes((&[0; 0x80], 0)).unwrap();
+        header.position = Vec3 { x, y, z };
+        header
+    }
+
+    #[test]
+    fn test_heightfield() {
+        let mut heightfield = TerrainHeightfield::new();
+        // a slope rising by 1 unit per UNIT_SIZE towards -x
+        let mut heights = [0.0; VERTICES_PER_CHUNK];
+        for (i, height) in heights.iter_mut().enumerate() {
+            let row = (i / 17) as f32 + if i % 17 > 8 { 0.5 } else { 0.0 };
+            *height = row;
+        }
+        let (x, y) = (CHUNK_SIZE * 3.0, CHUNK_SIZE * 5.0);
+        heightfield.add_chunk(flat_header(x, y, 10.0), heights);
+
+        let height = heightfield.get_height(x - UNIT_SIZE * 2.25, y - UNIT_SIZE * 4.6).unwrap();
+        assert!((height - 12.25).abs() < 1e-3);
+        let normal = heightfield.get_normal(x - UNIT_SIZE * 7.9, y - UNIT_SIZE * 0.1).unwrap();
+        let expected = glm::normalize(&glm::vec3(1.0, 0.0, UNIT_SIZE));
+        assert!((normal.x - expected.x).abs() < 1e-4 && (normal.z - expected.z).abs() < 1e-4);
+        assert!(heightfield.get_height(x + 1.0, y - 1.0).is_none());
+
+        let mut holes = heightfield.chunks.values().next().unwrap().header.clone();
+        holes.holes_low_res = 0x0001; // the 2x2 quads in the chunk's corner
+        heightfield.add_chunk(holes, heights);
+        assert!(heightfield.get_height(x - UNIT_SIZE * 1.5, y - UNIT_SIZE * 0.5).is_none());
+        assert!(heightfield.get_height(x - UNIT_SIZE * 2.5, y - UNIT_SIZE * 0.5).is_some());
+
+        let (row, col) = chunk_key(x - 1.0, y - 1.0);
+        heightfield.remove_tile(col / 16, row / 16);
+        assert!(heightfield.chunks.is_empty());
+    }
+
+    // a flat ADT at the given height, with the tile's +x/+y corner at the origin
+    fn build_adt(z: f32) -> Adt {
+        let mut data = Vec::new();
+        for i in 0..256 {
+            let mut header = vec![0; 0x80];
+            let position = [-((i / 16) as f32) * CHUNK_SIZE, -((i % 16) as f32) * CHUNK_SIZE, z];
+            for (j, v) in position.iter().enumerate() {
+                header[0x68 + j * 4..0x6c + j * 4].copy_from_slice(&v.to_le_bytes());
+            }
+            let mut mcnk = header;
+            mcnk.extend_from_slice(b"TVCM");
+            mcnk.extend_from_slice(&(VERTICES_PER_CHUNK as u32 * 4).to_le_bytes());
+            mcnk.extend_from_slice(&[0; VERTICES_PER_CHUNK * 4]);
+            mcnk.extend_from_slice(b"RNCM");
+            mcnk.extend_from_slice(&(VERTICES_PER_CHUNK as u32 * 3).to_le_bytes());
+            mcnk.extend_from_slice(&[0; VERTICES_PER_CHUNK * 3]);
+            data.extend_from_slice(b"KNCM");
+            data.extend_from_slice(&(mcnk.len() as u32).to_le_bytes());
+            data.extend_from_slice(&mcnk);
+        }
+        Adt::new(&data).unwrap()
+    }
+
+    #[test]
+    fn test_lod_terrain() {
+        let adt = build_adt(50.0);
+        let mut full = adt.build_lod_terrain(0, 10.0).unwrap();
+        assert_eq!(full.take_vertex_buffer().len(), (129 * 129 + 4 * 128) * 6);
+        assert_eq!(full.take_index_buffer().len(), 128 * 128 * 6 + 4 * 128 * 6);
+        assert_eq!(full.extents.min.z, 40.0);
+        assert!((full.extents.min.x + TILE_SIZE).abs() < 1e-2);
+
+        let mut coarse = adt.build_lod_terrain(7, 10.0).unwrap();
+        let vertices = coarse.take_vertex_buffer();
+        assert_eq!(vertices.len(), (4 + 4) * 6);
+        // flat terrain has straight up normals
+        assert_eq!(&vertices[3..6], &[0.0, 0.0, 1.0]);
+        let indices = coarse.take_index_buffer();
+        assert_eq!(indices.len(), 6 + 4 * 6);
+        assert!(indices.iter().all(|&i| (i as usize) < 8));
+        assert!(adt.build_lod_terrain(8, 10.0).is_err());
+    }
+
+    // gives the first chunk a blue base layer, and a red layer at ~53% alpha
+    fn build_tex_adt(with_heights: bool) -> Vec<u8> {
+        let mut layers = Vec::new();
+        for (texture_index, settings) in [(0u32, 0u32), (1, 0x100)] {
+            for v in [texture_index, settings, 0, 0] {
+                layers.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        let mut mcnk = chunk(b"YLCM", &layers);
+        // height texturing implies 4096 byte alpha maps
+        let alpha_size = if with_heights { 4096 } else { 2048 };
+        mcnk.extend(chunk(b"LACM", &vec![0x88; alpha_size]));
+        let mut data = chunk(b"DIDM", &[10u32.to_le_bytes(), 11u32.to_le_bytes()].concat());
+        if with_heights {
+            data.extend(chunk(b"DIHM", &[0u32.to_le_bytes(), 21u32.to_le_bytes()].concat()));
+            let params: Vec<u8> = [0u32, 1.0f32.to_bits(), 0, 0].iter().cycle().take(8)
+                .flat_map(|v| v.to_le_bytes())
+                .collect();
+            data.extend(chunk(b"PXTM", &params));
+        }
+        data.extend(chunk(b"KNCM", &mcnk));
+        data
+    }
+
+    #[test]
+    fn test_texture_baker() {
+        let mut adt = build_adt(0.0);
+        adt.append_tex_adt(&build_tex_adt(false)).unwrap();
+        let mut baker = TerrainTextureBaker::new(16);
+        baker.add_texture(10, [0, 0, 255, 255].repeat(4), 2, 2).unwrap();
+        assert!(baker.bake_chunk(&adt, 0, false, false).is_err());
+        assert!(baker.add_texture(11, vec![255; 3], 2, 2).is_err());
+        baker.add_texture(11, [255, 0, 0, 255].repeat(64 * 64), 64, 64).unwrap();
+        assert_eq!(baker.textures[&11].width, 2);
+
+        let pixels = baker.bake_chunk(&adt, 0, false, false).unwrap();
+        assert_eq!(pixels.len(), 16 * 16 * 4);
+        assert_eq!(&pixels[0..4], &[136, 0, 119, 255]);
+        assert!(baker.bake_chunk(&adt, 1, false, false).unwrap().iter().all(|&v| v == 0));
+        let baked_adt = baker.bake_adt(&adt, false, false).unwrap();
+        assert_eq!(baked_adt.len(), 256 * 256 * 4);
+        assert_eq!(&baked_adt[..4], &[136, 0, 119, 255]);
+
+        // with only the red layer having any height, it wins outright
+        let mut adt = build_adt(0.0);
+        adt.append_tex_adt(&build_tex_adt(true)).unwrap();
+        baker.add_texture(21, vec![255; 4], 1, 1).unwrap();
+        let pixels = baker.bake_chunk(&adt, 0, false, true).unwrap();
+        assert_eq!(&pixels[0..4], &[255, 0, 0, 255]);
+    }
+}
@@ -238,6 +238,30 @@ impl Wmo {
         self.groups.get(&file_id).expect(&format!("couldn't find group with file_id {}", file_id))
     }
 
+    // blends the fogs referenced by the given group based on the modelspace
+    // camera position. MFOG's first entry is the WMO's default fog, which
+    // fills in wherever the group's fogs don't fully apply
+    pub fn get_fog(&self, group_id: u32, point_slice: &[f32]) -> FogResult {
+        let p = make_vec3(point_slice);
+        let mut result = FogResult::default();
+        if let Some(group) = self.groups.get(&group_id) {
+            let mut seen = HashSet::new();
+            for &fog_id in &group.header.fog_ids {
+                if fog_id == 0 || !seen.insert(fog_id) {
+                    continue;
+                }
+                if let Some(fog) = self.fogs.get(fog_id as usize) {
+                    let weight = fog.get_weight(&p);
+                    if weight > 0.0 {
+                        result.add_scaled(fog, weight);
+                    }
+                }
+            }
+        }
+        result.normalize(self.fogs.first());
+        result
+    }
+
     pub fn find_group_for_modelspace_point(&self, point_slice: &[f32]) -> Option<u32> {
         let p = make_vec3(point_slice);
         let mut closest_group_id = None;
@@ -466,6 +490,19 @@ impl Wmo {
     }
 }
 
+// rust-only interface
+impl Wmo {
+    // note that a group's geometry is drained by `take_vertex_data()` and
+    // `take_indices()`
+    pub fn get_loaded_group(&self, file_id: u32) -> Option<&WmoGroup> {
+        self.groups.get(&file_id)
+    }
+
+    pub fn get_num_doodad_sets(&self) -> usize {
+        self.doodad_sets.len()
+    }
+}
+
 #[derive(DekuRead, Debug, Clone)]
 pub struct Portal {
     pub start_vertex: u16,
@@ -755,6 +792,32 @@ impl WmoGroup {
         }
         Some(result)
     }
+
+    // three floats per vertex
+    pub fn get_vertices(&self) -> &[f32] {
+        &self.vertices
+    }
+
+    // three little-endian floats per vertex
+    pub fn get_normals(&self) -> &[u8] {
+        &self.normals
+    }
+
+    // `num_uv_bufs` MOTV chunks back to back, each with two little-endian
+    // floats per vertex
+    pub fn get_uvs(&self) -> &[u8] {
+        &self.uvs
+    }
+
+    // `num_color_bufs` MOCV chunks back to back, each with a BGRA color per
+    // vertex
+    pub fn get_colors(&self) -> &[u8] {
+        &self.colors
+    }
+
+    pub fn get_indices(&self) -> &[u16] {
+        &self.indices
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1285,7 +1348,7 @@ pub struct WmoGroupHeader {
     pub int_batch_count: u16,
     pub ext_batch_count: u16,
     pub padding_or_batch_type_d: u16,
-    _fog_ids: [u8; 4],
+    pub fog_ids: [u8; 4], // indices into MFOG
     pub group_liquid: u32,
     pub group_flags2: u32,
     pub parent_or_first_child_split_group_index: u16,
@@ -1345,12 +1408,83 @@ pub struct Fog {
     pub larger_radius: f32,
     pub fog_end: f32,
     pub fog_start_scalar: f32,
-    pub fog_color: Rgba,
+    pub fog_color: Bgra,
     pub uw_fog_end: f32,
     pub uw_fog_start_scalar: f32,
     pub uw_fog_color: Bgra,
 }
 
+impl Fog {
+    // 0 outside of the fog's larger radius, 1 within its smaller radius
+    pub fn get_weight(&self, p: &Vec3) -> f32 {
+        if self.flags & 0x01 != 0 {
+            // infinite radius
+            return 1.0;
+        }
+        let position: Vec3 = self.position.into();
+        let distance = nalgebra_glm::distance(&position, p);
+        if distance <= self.smaller_radius {
+            1.0
+        } else if distance >= self.larger_radius {
+            0.0
+        } else {
+            1.0 - (distance - self.smaller_radius) / (self.larger_radius - self.smaller_radius)
+        }
+    }
+}
+
+fn bgra_to_color(color: Bgra) -> WowVec3 {
+    WowVec3 {
+        x: color.r as f32 / 255.0,
+        y: color.g as f32 / 255.0,
+        z: color.b as f32 / 255.0,
+    }
+}
+
+// like WowLightResult's fog fields, but for the inside of a WMO. fog start is
+// fog_end * fog_scaler
+#[wasm_bindgen(js_name = "WowWmoFogResult")]
+#[derive(Debug, Clone, Default)]
+pub struct FogResult {
+    pub fog_end: f32,
+    pub fog_scaler: f32,
+    pub fog_color: WowVec3,
+    pub uw_fog_end: f32,
+    pub uw_fog_scaler: f32,
+    pub uw_fog_color: WowVec3,
+    total_alpha: f32,
+}
+
+impl FogResult {
+    fn add_scaled(&mut self, fog: &Fog, t: f32) {
+        self.total_alpha += t;
+        self.fog_end += fog.fog_end * t;
+        self.fog_scaler += fog.fog_start_scalar * t;
+        self.fog_color += bgra_to_color(fog.fog_color) * t;
+        self.uw_fog_end += fog.uw_fog_end * t;
+        self.uw_fog_scaler += fog.uw_fog_start_scalar * t;
+        self.uw_fog_color += bgra_to_color(fog.uw_fog_color) * t;
+    }
+
+    fn normalize(&mut self, default_fog: Option<&Fog>) {
+        if self.total_alpha < 1.0 {
+            if let Some(fog) = default_fog {
+                self.add_scaled(fog, 1.0 - self.total_alpha);
+            }
+        }
+        if self.total_alpha > 0.0 && self.total_alpha != 1.0 {
+            let t = self.total_alpha;
+            self.fog_end /= t;
+            self.fog_scaler /= t;
+            self.fog_color /= t;
+            self.uw_fog_end /= t;
+            self.uw_fog_scaler /= t;
+            self.uw_fog_color /= t;
+            self.total_alpha = 1.0;
+        }
+    }
+}
+
 #[wasm_bindgen(js_name = "WowDoodadDef")]
 #[derive(DekuRead, Debug, Clone)]
 pub struct DoodadDef {
@@ -1427,3 +1561,41 @@ impl WmoMaterial {
         STATIC_SHADERS[self.shader_index as usize].1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fog(flags: u32, smaller_radius: f32, larger_radius: f32, fog_end: f32, r: u8) -> Fog {
+        Fog {
+            flags,
+            position: WowVec3::new(0.0),
+            smaller_radius,
+            larger_radius,
+            fog_end,
+            fog_start_scalar: 0.5,
+            fog_color: Bgra { b: 0, g: 0, r, a: 255 },
+            uw_fog_end: fog_end / 2.0,
+            uw_fog_start_scalar: 0.0,
+            uw_fog_color: Bgra { b: r, g: 0, r: 0, a: 255 },
+        }
+    }
+
+    #[test]
+    fn test_fog_blending() {
+        let default_fog = fog(0x01, 0.0, 0.0, 1000.0, 0);
+        let local_fog = fog(0, 10.0, 20.0, 100.0, 255);
+        assert_eq!(local_fog.get_weight(&vec3(5.0, 0.0, 0.0)), 1.0);
+        assert_eq!(local_fog.get_weight(&vec3(15.0, 0.0, 0.0)), 0.5);
+        assert_eq!(local_fog.get_weight(&vec3(25.0, 0.0, 0.0)), 0.0);
+
+        let mut result = FogResult::default();
+        result.add_scaled(&local_fog, local_fog.get_weight(&vec3(0.0, 15.0, 0.0)));
+        result.normalize(Some(&default_fog));
+        assert_eq!(result.fog_end, 550.0);
+        assert_eq!(result.uw_fog_end, 275.0);
+        assert_eq!(result.fog_scaler, 0.5);
+        assert_eq!(result.fog_color.x, 0.5);
+        assert_eq!(result.uw_fog_color.z, 0.5);
+    }
+}
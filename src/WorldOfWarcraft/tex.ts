@@ -18,29 +18,17 @@ import { GfxRenderCache } from "../gfx/render/GfxRenderCache.js";
 import { rust } from "../rustlib.js";
 
 function getTextureType(blpFile: WowBlp): GfxFormat | undefined {
+    // get_mip_data decodes everything but DXT compressed mips to RGBA
+    if (blpFile.header.color_encoding !== rust.WowColorEncoding.Dxtc)
+        return GfxFormat.U8_RGBA_NORM;
+
     switch (blpFile.header.preferred_format) {
         case rust.WowPixelFormat.Dxt1:
-            if (blpFile.header.alpha_bit_depth > 0) {
-                return GfxFormat.BC1;
-            } else {
-                return GfxFormat.BC1;
-            }
+            return GfxFormat.BC1;
         case rust.WowPixelFormat.Dxt3:
             return GfxFormat.BC2;
-        case rust.WowPixelFormat.Argb8888:
-        case rust.WowPixelFormat.Unspecified:
-            return GfxFormat.U8_RGBA_NORM;
-        case rust.WowPixelFormat.Argb1555:
-            return GfxFormat.U16_RGBA_5551;
-        case rust.WowPixelFormat.Argb4444:
-            return GfxFormat.U16_RGBA_NORM;
-        case rust.WowPixelFormat.Rgb565:
-            return GfxFormat.U16_RGB_565;
         case rust.WowPixelFormat.Dxt5:
             return GfxFormat.BC3;
-        case rust.WowPixelFormat.Argb2565:
-            console.log("uhhhh argb2565");
-            return undefined;
         default:
             break;
     }
@@ -68,18 +56,7 @@ function makeTexture(device: GfxDevice, blp: WowBlp, level = 0): GfxTexture {
     const texture = device.createTexture(textureDescriptor!);
     const levelDatas = [];
     for (let i = 0; i < mipmapCount; i++) {
-        const mipBuf = blp.get_mip_data(i);
-        if (
-            [
-                GfxFormat.U16_RGB_565,
-                GfxFormat.U16_RGBA_5551,
-                GfxFormat.U16_RGBA_NORM,
-            ].includes(format)
-        ) {
-            levelDatas.push(new Uint16Array(mipBuf.buffer));
-        } else {
-            levelDatas.push(mipBuf);
-        }
+        levelDatas.push(blp.get_mip_data(i));
     }
 
     device.uploadTextureData(texture, 0, levelDatas);